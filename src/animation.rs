@@ -0,0 +1,405 @@
+// skeletal and morph target animation
+// every model keeps its node tree as a Skeleton. an AnimationClip animates the local transforms of the nodes
+// and the morph target weights of their meshes. the AnimationPlayer of a ModelInstance picks the clips that are
+// sampled into a pose, and the joint matrices of the pose are uploaded to a storage buffer that the skinned
+// vertex shaders read. morph targets are blended by MorphPipeline
+
+use std::{collections::HashMap, sync::Arc};
+
+use cgmath::{InnerSpace, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector3, VectorSpace};
+
+// joint matrices of all instances drawn in one frame, instances beyond this are dropped with a warning
+pub const MAX_JOINT_MATRICES: usize = 16384;
+// joints that can influence one vertex, the smallest weights are dropped
+pub const MAX_JOINT_INFLUENCES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl NodeTransform {
+    /// splits a matrix without shear into translation, rotation and scale
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Self {
+        let translation = matrix.w.truncate();
+        let scale = Vector3::new(
+            matrix.x.truncate().magnitude(),
+            matrix.y.truncate().magnitude(),
+            matrix.z.truncate().magnitude(),
+        );
+        let rotation = Matrix3::from_cols(
+            matrix.x.truncate() / scale.x,
+            matrix.y.truncate() / scale.y,
+            matrix.z.truncate() / scale.z,
+        );
+        Self {
+            translation,
+            rotation: Quaternion::from(rotation).normalize(),
+            scale,
+        }
+    }
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+    /// t = 0 is self, t = 1 is other
+    pub fn blend(&self, other: &NodeTransform, t: f32) -> Self {
+        // q and -q are the same rotation, take the shorter way
+        let other_rotation = if self.rotation.dot(other.rotation) < 0.0 {
+            -other.rotation
+        } else {
+            other.rotation
+        };
+        Self {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: self.rotation.nlerp(other_rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SkeletonNode {
+    pub name: String,
+    // parents always come before their children
+    pub parent: Option<usize>,
+    pub local_transform: Matrix4<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Joint {
+    pub node: usize,
+    // moves a vertex from the space of the mesh into the space of the joint in the bind pose
+    pub inverse_bind_matrix: Matrix4<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    pub nodes: Vec<SkeletonNode>,
+    // referenced by SkinVertex::joints, empty if no mesh of the model is skinned
+    pub joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    pub fn node_index(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+    pub fn rest_pose(&self) -> Vec<NodeTransform> {
+        self.nodes
+            .iter()
+            .map(|node| NodeTransform::from_matrix(&node.local_transform))
+            .collect()
+    }
+    /// the transform of every node relative to the model
+    pub fn global_transforms(&self, pose: &[NodeTransform]) -> Vec<Matrix4<f32>> {
+        let mut global_transforms: Vec<Matrix4<f32>> = Vec::with_capacity(self.nodes.len());
+        for (node, local_transform) in self.nodes.iter().zip(pose.iter()) {
+            let local_transform = local_transform.to_matrix();
+            let global_transform = match node.parent {
+                Some(parent) => global_transforms[parent] * local_transform,
+                None => local_transform,
+            };
+            global_transforms.push(global_transform);
+        }
+        global_transforms
+    }
+    /// the global transforms of the rest pose, without the rounding of the translation, rotation, scale split
+    pub fn rest_global_transforms(&self) -> Vec<Matrix4<f32>> {
+        let mut global_transforms: Vec<Matrix4<f32>> = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes.iter() {
+            let global_transform = match node.parent {
+                Some(parent) => global_transforms[parent] * node.local_transform,
+                None => node.local_transform,
+            };
+            global_transforms.push(global_transform);
+        }
+        global_transforms
+    }
+    pub fn joint_matrices(&self, global_transforms: &[Matrix4<f32>]) -> Vec<[[f32; 4]; 4]> {
+        self.joints
+            .iter()
+            .map(|joint| (global_transforms[joint.node] * joint.inverse_bind_matrix).into())
+            .collect()
+    }
+}
+
+impl Default for Skeleton {
+    // a single root node, for models that are not loaded from a scene
+    fn default() -> Self {
+        Self {
+            nodes: vec![SkeletonNode {
+                name: "root".to_string(),
+                parent: None,
+                local_transform: Matrix4::identity(),
+            }],
+            joints: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Keyframes<T> {
+    // seconds, ascending
+    pub times: Vec<f32>,
+    pub values: Vec<T>,
+}
+
+impl<T: Clone> Keyframes<T> {
+    // linear interpolation between the two keys around time, the first and last key hold outside of them
+    fn sample(&self, time: f32, interpolate: impl Fn(&T, &T, f32) -> T) -> Option<T> {
+        let last = self.times.len().checked_sub(1)?;
+        let next = self.times.partition_point(|key_time| *key_time <= time);
+        if next == 0 {
+            return Some(self.values[0].clone());
+        }
+        if next > last {
+            return Some(self.values[last].clone());
+        }
+        let previous = next - 1;
+        let span = self.times[next] - self.times[previous];
+        let t = if span > 0.0 {
+            (time - self.times[previous]) / span
+        } else {
+            0.0
+        };
+        Some(interpolate(&self.values[previous], &self.values[next], t))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeChannel {
+    pub node: usize,
+    pub translations: Keyframes<Vector3<f32>>,
+    pub rotations: Keyframes<Quaternion<f32>>,
+    pub scales: Keyframes<Vector3<f32>>,
+}
+
+// weights of the morph targets of the meshes of one node
+#[derive(Debug, Clone)]
+pub struct MorphChannel {
+    pub node: usize,
+    // one weight per morph target for every key
+    pub weights: Keyframes<Vec<f32>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    // seconds
+    pub duration: f32,
+    pub channels: Vec<NodeChannel>,
+    pub morph_channels: Vec<MorphChannel>,
+}
+
+impl AnimationClip {
+    /// overwrites the nodes animated by this clip, the other nodes of the pose are left alone
+    pub fn sample(&self, time: f32, pose: &mut [NodeTransform]) {
+        for channel in self.channels.iter() {
+            let node_transform = &mut pose[channel.node];
+            if let Some(translation) = channel.translations.sample(time, |a, b, t| a.lerp(*b, t)) {
+                node_transform.translation = translation;
+            }
+            if let Some(rotation) = channel.rotations.sample(time, |a, b, t| a.slerp(*b, t)) {
+                node_transform.rotation = rotation;
+            }
+            if let Some(scale) = channel.scales.sample(time, |a, b, t| a.lerp(*b, t)) {
+                node_transform.scale = scale;
+            }
+        }
+    }
+    /// the morph target weights of every node animated by this clip
+    pub fn sample_morph_weights(&self, time: f32, morph_weights: &mut HashMap<usize, Vec<f32>>) {
+        for channel in self.morph_channels.iter() {
+            if let Some(weights) = channel
+                .weights
+                .sample(time, |a, b, t| blend_weights(a, b, t))
+            {
+                morph_weights.insert(channel.node, weights);
+            }
+        }
+    }
+}
+
+// missing weights count as 0
+fn blend_weights(a: &[f32], b: &[f32], t: f32) -> Vec<f32> {
+    (0..usize::max(a.len(), b.len()))
+        .map(|i| {
+            let a = a.get(i).copied().unwrap_or(0.0);
+            let b = b.get(i).copied().unwrap_or(0.0);
+            a + (b - a) * t
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct Playback {
+    clip: String,
+    // seconds since the clip started, not wrapped
+    time: f32,
+}
+
+// which clips a ModelInstance plays. the player only knows clips by name, so it can be set up before the
+// model has finished loading. the players live in State::animation_players and are advanced by State::update
+#[derive(Debug, Clone)]
+pub struct AnimationPlayer {
+    current: Option<Playback>,
+    // the clip that is faded out by cross_fade
+    previous: Option<Playback>,
+    fade_duration: f32,
+    fade_elapsed: f32,
+    pub speed: f32,
+    pub looping: bool,
+    pub paused: bool,
+}
+
+impl AnimationPlayer {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            previous: None,
+            fade_duration: 0.0,
+            fade_elapsed: 0.0,
+            speed: 1.0,
+            looping: true,
+            paused: false,
+        }
+    }
+    /// starts the clip from the beginning, without blending
+    pub fn play(&mut self, clip: &str) {
+        self.current = Some(Playback {
+            clip: clip.to_string(),
+            time: 0.0,
+        });
+        self.previous = None;
+    }
+    /// starts the clip from the beginning and blends it in over duration seconds while the current clip keeps playing
+    pub fn cross_fade(&mut self, clip: &str, duration: f32) {
+        if duration <= 0.0 || self.current.is_none() {
+            self.play(clip);
+            return;
+        }
+        self.previous = self.current.take();
+        self.current = Some(Playback {
+            clip: clip.to_string(),
+            time: 0.0,
+        });
+        self.fade_duration = duration;
+        self.fade_elapsed = 0.0;
+    }
+    /// back to the rest pose
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.previous = None;
+    }
+    pub fn current_clip(&self) -> Option<&str> {
+        self.current.as_ref().map(|playback| playback.clip.as_str())
+    }
+    pub fn update(&mut self, delta_time: f32) {
+        if self.paused {
+            return;
+        }
+        let clip_delta_time = delta_time * self.speed;
+        if let Some(current) = self.current.as_mut() {
+            current.time += clip_delta_time;
+        }
+        if let Some(previous) = self.previous.as_mut() {
+            previous.time += clip_delta_time;
+            // the fade is not affected by the speed
+            self.fade_elapsed += delta_time;
+            if self.fade_elapsed >= self.fade_duration {
+                self.previous = None;
+            }
+        }
+    }
+
+    // the clip of the playback and the time inside of it
+    fn find_clip<'a>(
+        &self,
+        playback: &Playback,
+        clips: &'a [Arc<AnimationClip>],
+    ) -> Option<(&'a AnimationClip, f32)> {
+        let clip = clips.iter().find(|clip| clip.name == playback.clip)?;
+        let time = if self.looping && clip.duration > 0.0 {
+            playback.time.rem_euclid(clip.duration)
+        } else {
+            playback.time.clamp(0.0, clip.duration)
+        };
+        Some((clip, time))
+    }
+
+    fn sample_playback(
+        &self,
+        playback: &Playback,
+        skeleton: &Skeleton,
+        clips: &[Arc<AnimationClip>],
+    ) -> Vec<NodeTransform> {
+        let mut pose = skeleton.rest_pose();
+        if let Some((clip, time)) = self.find_clip(playback, clips) {
+            clip.sample(time, &mut pose);
+        }
+        pose
+    }
+
+    /// the local transform of every node of the skeleton, unknown clips leave the rest pose
+    pub fn sample_pose(
+        &self,
+        skeleton: &Skeleton,
+        clips: &[Arc<AnimationClip>],
+    ) -> Vec<NodeTransform> {
+        let Some(current) = self.current.as_ref() else {
+            return skeleton.rest_pose();
+        };
+        let pose = self.sample_playback(current, skeleton, clips);
+        match self.previous.as_ref() {
+            Some(previous) => {
+                let previous_pose = self.sample_playback(previous, skeleton, clips);
+                let t = (self.fade_elapsed / self.fade_duration).clamp(0.0, 1.0);
+                previous_pose
+                    .iter()
+                    .zip(pose.iter())
+                    .map(|(from, to)| from.blend(to, t))
+                    .collect()
+            }
+            None => pose,
+        }
+    }
+
+    /// the morph target weights of the nodes animated by the playing clips, by node index
+    pub fn sample_morph_weights(&self, clips: &[Arc<AnimationClip>]) -> HashMap<usize, Vec<f32>> {
+        let sample = |playback: &Playback| {
+            let mut morph_weights = HashMap::new();
+            if let Some((clip, time)) = self.find_clip(playback, clips) {
+                clip.sample_morph_weights(time, &mut morph_weights);
+            }
+            morph_weights
+        };
+        let Some(current) = self.current.as_ref() else {
+            return HashMap::new();
+        };
+        let mut morph_weights = sample(current);
+        if let Some(previous) = self.previous.as_ref() {
+            let previous_weights = sample(previous);
+            let t = (self.fade_elapsed / self.fade_duration).clamp(0.0, 1.0);
+            for (node, from) in previous_weights.iter() {
+                let to = morph_weights.get(node).map(Vec::as_slice).unwrap_or(&[]);
+                let weights = blend_weights(from, to, t);
+                morph_weights.insert(*node, weights);
+            }
+            for (node, to) in morph_weights.iter_mut() {
+                if !previous_weights.contains_key(node) {
+                    *to = blend_weights(&[], to, t);
+                }
+            }
+        }
+        morph_weights
+    }
+}
+
+impl Default for AnimationPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -25,7 +25,10 @@ impl ApplicationHandler for App {
             .with_position(LogicalPosition::new(0, 0));
         let window = event_loop.create_window(attributes).unwrap();
         let window = Arc::new(window);
-        self.render_context = Some(RenderContext::new(window.clone()));
+        let mut render_context = RenderContext::new(window.clone());
+        // falls back to a lower sample count on adapters without 4x msaa
+        render_context.set_sample_count(4);
+        self.render_context = Some(render_context);
         self.window = Some(window);
         self.state.init();
     }
@@ -49,6 +52,10 @@ impl ApplicationHandler for App {
                 self.window.as_ref().unwrap().request_redraw();
                 self.state
                     .update(&mut self.input_context, &self.render_context.as_ref().unwrap().size);
+                self.input_context.end_frame();
+                self.state
+                    .fly_camera_controller
+                    .apply_cursor_grab(self.window.as_ref().unwrap());
                 match self
                     .render_context
                     .as_mut()
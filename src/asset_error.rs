@@ -0,0 +1,104 @@
+use std::fmt;
+
+// everything that can go wrong while loading a model, texture or font from disk
+// the loaders return this instead of panicking, callers log it and fall back to a placeholder
+#[derive(Debug)]
+pub enum AssetError {
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    Decode {
+        path: String,
+        source: image::ImageError,
+    },
+    Import {
+        path: String,
+        source: russimp::RussimpError,
+    },
+    MissingMaterialProperty {
+        path: String,
+        property: String,
+    },
+    UnsupportedFormat {
+        path: String,
+        description: String,
+    },
+    // a procedural model that was requested before AssetServer::insert_model
+    NotRegistered {
+        name: String,
+    },
+    // the loader panicked on a worker thread
+    Panicked {
+        path: String,
+        message: String,
+    },
+}
+
+impl AssetError {
+    /// sorts an image::ImageError into io, unsupported format or decode errors
+    pub fn from_image_error(path: &str, error: image::ImageError) -> Self {
+        match error {
+            image::ImageError::IoError(source) => AssetError::Io {
+                path: path.to_string(),
+                source,
+            },
+            image::ImageError::Unsupported(error) => AssetError::UnsupportedFormat {
+                path: path.to_string(),
+                description: error.to_string(),
+            },
+            source => AssetError::Decode {
+                path: path.to_string(),
+                source,
+            },
+        }
+    }
+    pub fn path(&self) -> &str {
+        match self {
+            AssetError::Io { path, .. } => path,
+            AssetError::Decode { path, .. } => path,
+            AssetError::Import { path, .. } => path,
+            AssetError::MissingMaterialProperty { path, .. } => path,
+            AssetError::UnsupportedFormat { path, .. } => path,
+            AssetError::NotRegistered { name } => name,
+            AssetError::Panicked { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::Io { path, source } => write!(f, "failed to read {}: {}", path, source),
+            AssetError::Decode { path, source } => {
+                write!(f, "failed to decode {}: {}", path, source)
+            }
+            AssetError::Import { path, source } => {
+                write!(f, "failed to import {}: {}", path, source)
+            }
+            AssetError::MissingMaterialProperty { path, property } => {
+                write!(f, "{} has no material property {}", path, property)
+            }
+            AssetError::UnsupportedFormat { path, description } => {
+                write!(f, "unsupported format in {}: {}", path, description)
+            }
+            AssetError::NotRegistered { name } => {
+                write!(f, "procedural model {} was never inserted", name)
+            }
+            AssetError::Panicked { path, message } => {
+                write!(f, "loading {} panicked: {}", path, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AssetError::Io { source, .. } => Some(source),
+            AssetError::Decode { source, .. } => Some(source),
+            AssetError::Import { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
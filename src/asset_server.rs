@@ -0,0 +1,363 @@
+// background asset loading
+// files are read and decoded (russimp scenes, images, glyph rasterization) on the worker threads of a tokio
+// runtime, the decoded data is sent back over a channel and uploaded to the gpu on the render thread
+// in process_uploads. until then the handle of the asset stays in the Loading state.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        mpsc::{Receiver, Sender, channel},
+    },
+};
+
+use image::{ImageBuffer, Rgba};
+use tokio::runtime::Runtime;
+
+use crate::{
+    asset_error::AssetError,
+    model_data::{DecodedModel, ModelData},
+    model_meta::{ModelMeta, create_placeholder_model},
+    my_texture::{MyTexture, TextureSource},
+    opaque_pipeline::OpaquePipeline,
+    ui_renderable::create_placeholder_texture,
+};
+
+pub enum AssetState<T> {
+    Loading,
+    Ready(Arc<T>),
+    Failed(Arc<AssetError>),
+}
+
+impl<T> Clone for AssetState<T> {
+    fn clone(&self) -> Self {
+        match self {
+            AssetState::Loading => AssetState::Loading,
+            AssetState::Ready(asset) => AssetState::Ready(asset.clone()),
+            AssetState::Failed(error) => AssetState::Failed(error.clone()),
+        }
+    }
+}
+
+// shared between the asset server and everyone that requested the asset
+pub struct AssetHandle<T> {
+    state: Arc<Mutex<AssetState<T>>>,
+}
+
+impl<T> Clone for AssetHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> AssetHandle<T> {
+    fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(AssetState::Loading)),
+        }
+    }
+    fn set(&self, state: AssetState<T>) {
+        *self.state.lock().unwrap() = state;
+    }
+    pub fn state(&self) -> AssetState<T> {
+        self.state.lock().unwrap().clone()
+    }
+    /// the asset, if it is ready
+    pub fn get(&self) -> Option<Arc<T>> {
+        match &*self.state.lock().unwrap() {
+            AssetState::Ready(asset) => Some(asset.clone()),
+            _ => None,
+        }
+    }
+    pub fn is_loading(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), AssetState::Loading)
+    }
+}
+
+enum DecodedAsset {
+    Model(ModelMeta, Result<DecodedModel, AssetError>),
+    Texture(
+        TextureSource,
+        Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Arc<AssetError>>,
+    ),
+}
+
+pub struct AssetServer {
+    runtime: Runtime,
+    sender: Sender<DecodedAsset>,
+    receiver: Receiver<DecodedAsset>,
+    models: HashMap<ModelMeta, AssetHandle<ModelData>>,
+    textures: HashMap<TextureSource, AssetHandle<MyTexture>>,
+    placeholder_model: Option<Arc<ModelData>>,
+    placeholder_texture: Option<Arc<MyTexture>>,
+    // number of requests that have not been uploaded yet
+    pending: usize,
+    // uploading a large model takes a while, so only this many assets are uploaded per frame
+    pub max_uploads_per_frame: usize,
+}
+
+impl AssetServer {
+    pub fn new() -> Self {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .thread_name("asset worker")
+            .build()
+            .unwrap();
+        let (sender, receiver) = channel();
+        Self {
+            runtime,
+            sender,
+            receiver,
+            models: HashMap::new(),
+            textures: HashMap::new(),
+            placeholder_model: None,
+            placeholder_texture: None,
+            pending: 0,
+            max_uploads_per_frame: 2,
+        }
+    }
+
+    /// returns the handle of the model, and starts decoding it in the background if this is the first request
+    pub fn load_model(&mut self, model_meta: ModelMeta) -> AssetHandle<ModelData> {
+        self.load_model_with(model_meta, |model_meta| model_meta.decode_model())
+    }
+
+    // load_model with another decoder
+    fn load_model_with(
+        &mut self,
+        model_meta: ModelMeta,
+        decode: impl FnOnce(&ModelMeta) -> Result<DecodedModel, AssetError> + Send + 'static,
+    ) -> AssetHandle<ModelData> {
+        if let Some(handle) = self.models.get(&model_meta) {
+            return handle.clone();
+        }
+        let handle = AssetHandle::new();
+        self.models.insert(model_meta.clone(), handle.clone());
+        // fails right away instead of on a worker, so the error can not arrive after a later insert_model
+        if let ModelMeta::Procedural(name) = &model_meta {
+            let error = AssetError::NotRegistered { name: name.clone() };
+            log::warn!("{}", error);
+            handle.set(AssetState::Failed(Arc::new(error)));
+            return handle;
+        }
+        self.pending += 1;
+        let sender = self.sender.clone();
+        self.runtime.spawn_blocking(move || {
+            let decoded_model = catch_decode_panic(&model_meta.name(), || decode(&model_meta));
+            // the server may already be gone
+            let _ = sender.send(DecodedAsset::Model(model_meta, decoded_model));
+        });
+        handle
+    }
+
+    /// registers a model built at runtime, usually under a ModelMeta::Procedural name.
+    /// it is uploaded by process_uploads like a decoded file, and replaces an earlier model with the same meta
+    pub fn insert_model(
+        &mut self,
+        model_meta: ModelMeta,
+        mut decoded_model: DecodedModel,
+    ) -> AssetHandle<ModelData> {
+        decoded_model.build_bvhs();
+        let handle = self
+            .models
+            .entry(model_meta.clone())
+            .or_insert_with(AssetHandle::new)
+            .clone();
+        self.pending += 1;
+        // the worker threads send their models over the same channel
+        let _ = self
+            .sender
+            .send(DecodedAsset::Model(model_meta, Ok(decoded_model)));
+        handle
+    }
+
+    /// same as load_model, for textures
+    pub fn load_texture(&mut self, texture_source: TextureSource) -> AssetHandle<MyTexture> {
+        self.load_texture_with(texture_source, MyTexture::decode_image)
+    }
+
+    // load_texture with another decoder
+    fn load_texture_with(
+        &mut self,
+        texture_source: TextureSource,
+        decode: impl FnOnce(TextureSource) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, Arc<AssetError>>
+        + Send
+        + 'static,
+    ) -> AssetHandle<MyTexture> {
+        if let Some(handle) = self.textures.get(&texture_source) {
+            return handle.clone();
+        }
+        let handle = AssetHandle::new();
+        self.textures.insert(texture_source.clone(), handle.clone());
+        self.pending += 1;
+        let sender = self.sender.clone();
+        self.runtime.spawn_blocking(move || {
+            let name = format!("{:?}", texture_source);
+            let image = catch_decode_panic(&name, || decode(texture_source.clone()));
+            let _ = sender.send(DecodedAsset::Texture(texture_source, image));
+        });
+        handle
+    }
+
+    /// true if every requested asset is either ready or failed
+    pub fn is_idle(&self) -> bool {
+        self.pending == 0
+    }
+
+    /// the model drawn in place of models that failed to load
+    pub fn placeholder_model(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        opaque_pipeline: &OpaquePipeline,
+    ) -> Arc<ModelData> {
+        self.placeholder_model
+            .get_or_insert_with(|| {
+                Arc::new(create_placeholder_model(device, queue, opaque_pipeline))
+            })
+            .clone()
+    }
+
+    /// the texture drawn in place of textures that are loading or failed to load
+    pub fn placeholder_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Arc<MyTexture> {
+        self.placeholder_texture
+            .get_or_insert_with(|| Arc::new(create_placeholder_texture(device, queue)))
+            .clone()
+    }
+
+    /// uploads the assets that finished decoding since the last call, has to run on the render thread
+    pub fn process_uploads(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        opaque_pipeline: &OpaquePipeline,
+    ) {
+        for _ in 0..self.max_uploads_per_frame {
+            let decoded_asset = match self.receiver.try_recv() {
+                Ok(decoded_asset) => decoded_asset,
+                Err(_) => break,
+            };
+            self.pending -= 1;
+            match decoded_asset {
+                DecodedAsset::Model(model_meta, decoded_model) => {
+                    let handle = &self.models[&model_meta];
+                    match decoded_model {
+                        Ok(decoded_model) => {
+                            let model_data = decoded_model.upload(device, queue, opaque_pipeline);
+                            handle.set(AssetState::Ready(Arc::new(model_data)));
+                        }
+                        Err(error) => {
+                            log::warn!("{}", error);
+                            handle.set(AssetState::Failed(Arc::new(error)));
+                        }
+                    }
+                }
+                DecodedAsset::Texture(texture_source, image) => {
+                    let handle = &self.textures[&texture_source];
+                    match image {
+                        Ok(image) => {
+                            let texture = MyTexture::from_image(&image, device, queue);
+                            handle.set(AssetState::Ready(Arc::new(texture)));
+                        }
+                        Err(error) => {
+                            log::warn!("{}", error);
+                            handle.set(AssetState::Failed(error));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// runs the decoder of a worker and turns a panic into an error. without a result the handle would stay Loading
+// and pending would never go back to 0
+fn catch_decode_panic<T, E: From<AssetError>>(
+    name: &str,
+    decode: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    // the decoder owns everything it touches, nothing is left half updated after a panic
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(decode)).unwrap_or_else(|payload| {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => payload
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_else(|| "unknown panic".to_string()),
+        };
+        Err(AssetError::Panicked {
+            path: name.to_string(),
+            message,
+        }
+        .into())
+    })
+}
+
+impl Default for AssetServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::mesh_builder::Primitive;
+
+    fn assert_panicked(error: &AssetError, expected_message: &str) {
+        match error {
+            AssetError::Panicked { message, .. } => assert_eq!(message, expected_message),
+            error => panic!("expected a panic error, got {}", error),
+        }
+    }
+
+    #[test]
+    fn panicking_texture_loader_fails() {
+        let mut asset_server = AssetServer::new();
+        let handle = asset_server.load_texture_with(
+            TextureSource::PureColor {
+                red: 255,
+                green: 0,
+                blue: 0,
+            },
+            |_| panic!("broken texture loader"),
+        );
+        assert!(handle.is_loading());
+        assert!(!asset_server.is_idle());
+        // the worker still sends a result, process_uploads marks the handle as failed when it receives it
+        let decoded_asset = asset_server
+            .receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the panicking loader sent nothing");
+        match decoded_asset {
+            DecodedAsset::Texture(_, Err(error)) => {
+                assert_panicked(&error, "broken texture loader")
+            }
+            _ => panic!("expected a failed texture"),
+        }
+    }
+
+    #[test]
+    fn panicking_model_loader_fails() {
+        let mut asset_server = AssetServer::new();
+        let model_meta = ModelMeta::Primitive(Primitive::Cube { size: 1.0 });
+        // a formatted message panics with a String instead of a &str
+        let index = 3;
+        asset_server.load_model_with(model_meta, move |_| panic!("broken model loader {}", index));
+        let decoded_asset = asset_server
+            .receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the panicking loader sent nothing");
+        match decoded_asset {
+            DecodedAsset::Model(_, Err(error)) => assert_panicked(&error, "broken model loader 3"),
+            _ => panic!("expected a failed model"),
+        }
+    }
+}
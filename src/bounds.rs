@@ -0,0 +1,279 @@
+// bounding volumes of models and rays to test against them, used to frame models with the orbit camera
+// and for picking
+
+use cgmath::{EuclideanSpace, InnerSpace, MetricSpace, Point3, Transform, Vector3};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    /// the direction is normalized, so t is the distance from the origin
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.direction * t
+    }
+
+    /// the ray in another space. the direction is not normalized again, so every point keeps its t
+    pub fn transform(&self, matrix: &cgmath::Matrix4<f32>) -> Self {
+        Self {
+            origin: matrix.transform_point(self.origin),
+            direction: matrix.transform_vector(self.direction),
+        }
+    }
+
+    /// t of the hit with the triangle, both sides count
+    pub fn intersect_triangle(&self, triangle: &[Point3<f32>; 3]) -> Option<f32> {
+        // moller-trumbore
+        let edge1 = triangle[1] - triangle[0];
+        let edge2 = triangle[2] - triangle[0];
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < f32::EPSILON {
+            return None;
+        }
+        let inverse_determinant = 1.0 / determinant;
+        let s = self.origin - triangle[0];
+        let u = s.dot(p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = self.direction.dot(q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(q) * inverse_determinant;
+        (t >= 0.0).then_some(t)
+    }
+}
+
+// axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// contains nothing, growing it by a point gives a box around that point
+    pub fn empty() -> Self {
+        Self {
+            min: Point3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Point3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Self {
+        let mut aabb = Self::empty();
+        for point in points {
+            aabb.grow(point);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x
+    }
+
+    pub fn grow(&mut self, point: Point3<f32>) {
+        for i in 0..3 {
+            self.min[i] = self.min[i].min(point[i]);
+            self.max[i] = self.max[i].max(point[i]);
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        let mut aabb = *self;
+        aabb.grow(other.min);
+        aabb.grow(other.max);
+        aabb
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Point3::new(min.x, min.y, min.z),
+            Point3::new(max.x, min.y, min.z),
+            Point3::new(min.x, max.y, min.z),
+            Point3::new(max.x, max.y, min.z),
+            Point3::new(min.x, min.y, max.z),
+            Point3::new(max.x, min.y, max.z),
+            Point3::new(min.x, max.y, max.z),
+            Point3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    /// the box around the transformed corners
+    pub fn transform(&self, matrix: &cgmath::Matrix4<f32>) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        Self::from_points(self.corners().map(|corner| matrix.transform_point(corner)))
+    }
+
+    /// t where the ray enters the box, 0 if it starts inside. boxes entered after max_t are missed
+    pub fn intersect_ray(&self, ray: &Ray, max_t: f32) -> Option<f32> {
+        // slab test. a ray parallel to a slab never enters or leaves it, it is either inside or misses the box.
+        // dividing by the zero component instead would give 0 * inf = NaN for a ray on the plane of a face
+        let mut t_min = 0.0_f32;
+        let mut t_max = max_t;
+        for i in 0..3 {
+            if ray.direction[i] == 0.0 {
+                if ray.origin[i] < self.min[i] || ray.origin[i] > self.max[i] {
+                    return None;
+                }
+                continue;
+            }
+            let inverse_direction = 1.0 / ray.direction[i];
+            let t0 = (self.min[i] - ray.origin[i]) * inverse_direction;
+            let t1 = (self.max[i] - ray.origin[i]) * inverse_direction;
+            t_min = t_min.max(t0.min(t1));
+            // rounding can put the exit of a ray through a corner or an edge just before its entry,
+            // so the exit is pushed out a little. a few more boxes are hit, no hit is lost
+            t_max = t_max.min(t0.max(t1) * (1.0 + 4.0 * f32::EPSILON));
+        }
+        (t_min <= t_max).then_some(t_min)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// centered on the box around the points, not the smallest sphere but close enough for framing.
+    /// no points give an empty sphere at the origin
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>> + Clone) -> Self {
+        let aabb = Aabb::from_points(points.clone());
+        if aabb.is_empty() {
+            return Self {
+                center: Point3::new(0.0, 0.0, 0.0),
+                radius: 0.0,
+            };
+        }
+        let center = aabb.center();
+        let radius = points
+            .into_iter()
+            .map(|point| point.distance2(center))
+            .fold(0.0, f32::max)
+            .sqrt();
+        Self { center, radius }
+    }
+
+    /// the sphere around this one after the transform, non uniform scales grow it by the largest axis
+    pub fn transform(&self, matrix: &cgmath::Matrix4<f32>) -> Self {
+        let scale = (0..3)
+            .map(|i| matrix[i].truncate().magnitude())
+            .fold(0.0, f32::max);
+        Self {
+            center: matrix.transform_point(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> [Point3<f32>; 3] {
+        [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        ]
+    }
+
+    fn unit_box() -> Aabb {
+        Aabb {
+            min: Point3::new(-1.0, -1.0, -1.0),
+            max: Point3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    #[test]
+    fn ray_triangle_hit() {
+        let ray = Ray::new(Point3::new(0.25, 0.25, -2.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(ray.intersect_triangle(&triangle()), Some(2.0));
+        // the back side counts as well
+        let ray = Ray::new(Point3::new(0.25, 0.25, 3.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(ray.intersect_triangle(&triangle()), Some(3.0));
+    }
+
+    #[test]
+    fn ray_triangle_miss() {
+        // next to the triangle
+        let ray = Ray::new(Point3::new(0.75, 0.75, -2.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(ray.intersect_triangle(&triangle()), None);
+        // the triangle is behind the origin
+        let ray = Ray::new(Point3::new(0.25, 0.25, -2.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(ray.intersect_triangle(&triangle()), None);
+        // parallel to the plane of the triangle
+        let ray = Ray::new(Point3::new(-1.0, 0.25, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(ray.intersect_triangle(&triangle()), None);
+    }
+
+    #[test]
+    fn ray_triangle_edge_hit() {
+        let hit = |x, y| {
+            Ray::new(Point3::new(x, y, -1.0), Vector3::new(0.0, 0.0, 1.0))
+                .intersect_triangle(&triangle())
+        };
+        // on an edge and on a corner
+        assert_eq!(hit(0.5, 0.0), Some(1.0));
+        assert_eq!(hit(0.0, 0.5), Some(1.0));
+        assert_eq!(hit(0.0, 0.0), Some(1.0));
+        // just outside the edge
+        assert_eq!(hit(0.5, -0.001), None);
+    }
+
+    #[test]
+    fn aabb_slab_hit() {
+        let ray = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&ray, f32::INFINITY), Some(4.0));
+        let ray = Ray::new(Point3::new(-3.0, -3.0, -3.0), Vector3::new(1.0, 1.0, 1.0));
+        let t = unit_box().intersect_ray(&ray, f32::INFINITY).unwrap();
+        assert!((t - 12.0_f32.sqrt()).abs() < 1e-5);
+        // a ray starting inside enters at 0
+        let ray = Ray::new(Point3::new(0.5, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&ray, f32::INFINITY), Some(0.0));
+    }
+
+    #[test]
+    fn aabb_slab_miss() {
+        // passes above the box
+        let ray = Ray::new(Point3::new(-5.0, 2.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&ray, f32::INFINITY), None);
+        // points away from the box
+        let ray = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vector3::new(-1.0, 0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&ray, f32::INFINITY), None);
+        // the box is entered after max_t
+        let ray = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(unit_box().intersect_ray(&ray, 3.0), None);
+        // an empty box is never hit
+        assert_eq!(Aabb::empty().intersect_ray(&ray, f32::INFINITY), None);
+    }
+
+    #[test]
+    fn aabb_slab_edge_hit() {
+        // touches the edge at x = -1, y = 1 and leaves again
+        let ray = Ray::new(Point3::new(-2.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 0.0));
+        let t = unit_box().intersect_ray(&ray, f32::INFINITY).unwrap();
+        assert!((t - 2.0_f32.sqrt()).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,272 @@
+// persistent gpu buffers that the per frame data of the draws is written into with queue.write_buffer,
+// instead of creating a new buffer for every draw. every allocation is a sub-range of one of the chunks and is
+// only valid until the next reset. every frame in flight has its own arena, FramePacer::begin_frame resets it
+// once the gpu finished the frame that last used it.
+// when a frame does not fit into the chunks they grow by another chunk, and the next reset merges them into
+// one chunk large enough for the whole frame
+
+use wgpu::BufferAddress;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaAllocation {
+    chunk: usize,
+    pub offset: BufferAddress,
+    pub size: BufferAddress,
+}
+
+pub struct BufferArena {
+    label: String,
+    // COPY_DST is added to it
+    usage: wgpu::BufferUsages,
+    // of the offset of every allocation
+    alignment: BufferAddress,
+    chunks: Vec<wgpu::Buffer>,
+    // the chunk that is filled at the moment, and its first free byte
+    current: usize,
+    offset: BufferAddress,
+    // size of the next chunk that is created
+    chunk_size: BufferAddress,
+}
+
+impl BufferArena {
+    const MIN_CHUNK_SIZE: BufferAddress = 64 * 1024;
+
+    pub fn new(label: &str, usage: wgpu::BufferUsages, alignment: BufferAddress) -> Self {
+        Self {
+            label: label.to_string(),
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            alignment: alignment.max(wgpu::COPY_BUFFER_ALIGNMENT),
+            chunks: Vec::new(),
+            current: 0,
+            offset: 0,
+            chunk_size: Self::MIN_CHUNK_SIZE,
+        }
+    }
+
+    /// for instance data, see set_vertex_buffer
+    pub fn vertex(label: &str) -> Self {
+        Self::new(
+            label,
+            wgpu::BufferUsages::VERTEX,
+            wgpu::VERTEX_STRIDE_ALIGNMENT,
+        )
+    }
+
+    /// for uniforms bound with binding, the offsets follow the alignment the device asks for
+    pub fn uniform(label: &str, device: &wgpu::Device) -> Self {
+        Self::new(
+            label,
+            wgpu::BufferUsages::UNIFORM,
+            device.limits().min_uniform_buffer_offset_alignment as BufferAddress,
+        )
+    }
+
+    /// makes every chunk free again, the allocations of the last frame are invalid after this
+    pub fn reset(&mut self) {
+        // the last frame needed more than one chunk, the next one gets a single chunk that fits all of it
+        if self.current > 0 {
+            let total = self
+                .chunks
+                .iter()
+                .map(|chunk| chunk.size())
+                .sum::<BufferAddress>();
+            self.chunks.clear();
+            self.chunk_size = total.next_power_of_two();
+        }
+        self.current = 0;
+        self.offset = 0;
+    }
+
+    /// copies the data into the arena
+    pub fn alloc(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[u8],
+    ) -> ArenaAllocation {
+        // write_buffer only takes whole words
+        let size = (data.len() as BufferAddress)
+            .div_ceil(wgpu::COPY_BUFFER_ALIGNMENT)
+            .max(1)
+            * wgpu::COPY_BUFFER_ALIGNMENT;
+        let mut offset = self.offset.div_ceil(self.alignment) * self.alignment;
+        let fits = |chunk: &wgpu::Buffer, offset| offset + size <= chunk.size();
+        if self
+            .chunks
+            .get(self.current)
+            .is_none_or(|chunk| !fits(chunk, offset))
+        {
+            if !self.chunks.is_empty() {
+                self.current += 1;
+            }
+            offset = 0;
+            if self
+                .chunks
+                .get(self.current)
+                .is_none_or(|chunk| !fits(chunk, offset))
+            {
+                // replaces a chunk that is too small for the data, or appends a new one
+                let chunk_size = self.chunk_size.max(size.next_power_of_two());
+                self.chunk_size = chunk_size * 2;
+                let chunk = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(&self.label),
+                    size: chunk_size,
+                    usage: self.usage,
+                    mapped_at_creation: false,
+                });
+                if self.current < self.chunks.len() {
+                    self.chunks[self.current] = chunk;
+                } else {
+                    self.chunks.push(chunk);
+                }
+            }
+        }
+        let chunk = &self.chunks[self.current];
+        if data.len() as BufferAddress == size {
+            queue.write_buffer(chunk, offset, data);
+        } else {
+            let mut padded = data.to_vec();
+            padded.resize(size as usize, 0);
+            queue.write_buffer(chunk, offset, &padded);
+        }
+        self.offset = offset + size;
+        ArenaAllocation {
+            chunk: self.current,
+            offset,
+            size: data.len() as BufferAddress,
+        }
+    }
+
+    pub fn buffer(&self, allocation: &ArenaAllocation) -> &wgpu::Buffer {
+        &self.chunks[allocation.chunk]
+    }
+
+    pub fn slice(&self, allocation: &ArenaAllocation) -> wgpu::BufferSlice<'_> {
+        self.buffer(allocation)
+            .slice(allocation.offset..allocation.offset + allocation.size)
+    }
+
+    pub fn binding(&self, allocation: &ArenaAllocation) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: self.buffer(allocation),
+            offset: allocation.offset,
+            size: wgpu::BufferSize::new(allocation.size),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_context::RenderContext;
+
+    // the device of a headless render context. a machine without any adapter, not even a software one, fails the
+    // tests instead of passing them without checking anything
+    fn device_and_queue() -> (wgpu::Device, wgpu::Queue) {
+        let render_context = RenderContext::new_headless(4, 4).expect(
+            "the buffer arena tests need a gpu or software adapter, skip them with --skip buffer_arena",
+        );
+        (render_context.device, render_context.queue)
+    }
+
+    // the bytes of the whole chunk of the allocation
+    fn read_chunk(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        arena: &BufferArena,
+        allocation: &ArenaAllocation,
+    ) -> Vec<u8> {
+        let chunk = arena.buffer(allocation);
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Arena Test Output Buffer"),
+            size: chunk.size(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Arena Test Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(chunk, 0, &output_buffer, 0, chunk.size());
+        queue.submit(std::iter::once(encoder.finish()));
+        let buffer_slice = output_buffer.slice(..);
+        buffer_slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        buffer_slice.get_mapped_range().to_vec()
+    }
+
+    #[test]
+    fn alignment() {
+        let (device, queue) = device_and_queue();
+        let mut arena = BufferArena::new("Test Arena", wgpu::BufferUsages::COPY_SRC, 256);
+        let first = arena.alloc(&device, &queue, &[1, 2, 3]);
+        let second = arena.alloc(&device, &queue, &[4; 8]);
+        let third = arena.alloc(&device, &queue, &[5; 300]);
+        assert_eq!((first.offset, first.size), (0, 3));
+        assert_eq!((second.offset, second.size), (256, 8));
+        assert_eq!((third.offset, third.size), (512, 300));
+        // the data lands at the offsets, the padding of the first allocation is zero
+        let data = read_chunk(&device, &queue, &arena, &first);
+        assert_eq!(data[0..4], [1, 2, 3, 0]);
+        assert_eq!(data[256..264], [4; 8]);
+        assert_eq!(data[512..812], [5; 300]);
+
+        // offsets are never less aligned than write_buffer needs
+        let mut arena = BufferArena::new("Test Arena", wgpu::BufferUsages::COPY_SRC, 1);
+        arena.alloc(&device, &queue, &[1]);
+        assert_eq!(arena.alloc(&device, &queue, &[2]).offset, 4);
+        // an empty allocation still takes a word
+        let mut arena = BufferArena::vertex("Test Arena");
+        arena.alloc(&device, &queue, &[]);
+        assert_eq!(arena.alloc(&device, &queue, &[2]).offset, 4);
+    }
+
+    #[test]
+    fn chunk_growth() {
+        let (device, queue) = device_and_queue();
+        let mut arena = BufferArena::vertex("Test Arena");
+        let data = vec![0; 40 * 1024];
+        let first = arena.alloc(&device, &queue, &data);
+        // the second allocation does not fit behind the first one, a chunk twice as large is added
+        let second = arena.alloc(&device, &queue, &data);
+        assert_eq!(arena.chunks.len(), 2);
+        assert_eq!(arena.buffer(&first).size(), BufferArena::MIN_CHUNK_SIZE);
+        assert_eq!(
+            arena.buffer(&second).size(),
+            2 * BufferArena::MIN_CHUNK_SIZE
+        );
+        assert_eq!((second.chunk, second.offset), (1, 0));
+        // the third one fits into the second chunk
+        let third = arena.alloc(&device, &queue, &data);
+        assert_eq!((third.chunk, third.offset), (1, 40 * 1024));
+        // data larger than the next chunk gets a chunk of its own size
+        let large = arena.alloc(&device, &queue, &vec![0; 300 * 1024]);
+        assert_eq!(arena.buffer(&large).size(), 512 * 1024);
+    }
+
+    #[test]
+    fn reset() {
+        let (device, queue) = device_and_queue();
+        let mut arena = BufferArena::vertex("Test Arena");
+        let first = arena.alloc(&device, &queue, &[1; 16]);
+        let first_buffer = arena.buffer(&first).clone();
+        // a frame that fits into one chunk keeps it
+        arena.reset();
+        let second = arena.alloc(&device, &queue, &[2; 16]);
+        assert_eq!(second, first);
+        assert_eq!(arena.buffer(&second), &first_buffer);
+
+        // a frame that needed two chunks is followed by one chunk large enough for both
+        arena.alloc(&device, &queue, &vec![3; 64 * 1024]);
+        assert_eq!(arena.chunks.len(), 2);
+        arena.reset();
+        assert!(arena.chunks.is_empty());
+        let third = arena.alloc(&device, &queue, &[4; 16]);
+        assert_eq!((third.chunk, third.offset), (0, 0));
+        assert_eq!(
+            arena.buffer(&third).size(),
+            (3 * BufferArena::MIN_CHUNK_SIZE).next_power_of_two()
+        );
+        arena.alloc(&device, &queue, &vec![5; 150 * 1024]);
+        assert_eq!(arena.chunks.len(), 1);
+    }
+}
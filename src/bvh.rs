@@ -0,0 +1,264 @@
+// bounding volume hierarchy over the triangles of one mesh, in the space of the mesh.
+// built once when the model is decoded and used by picking to find the nearest triangle under a ray
+// without testing all of them
+
+use cgmath::Point3;
+
+use crate::{
+    bounds::{Aabb, Ray},
+    model_data::MeshIndices,
+    vertex::Vertex,
+};
+
+// leaves are not split any further once they have this many triangles
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+#[derive(Debug, Clone)]
+struct BvhNode {
+    aabb: Aabb,
+    // leaves: first entry in triangles. inner nodes: index of the second child, the first one follows the node
+    first: u32,
+    // 0 for inner nodes
+    count: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TriangleBvh {
+    nodes: Vec<BvhNode>,
+    // the corners and the index in the mesh of every triangle, in the order of the leaves
+    triangles: Vec<([Point3<f32>; 3], u32)>,
+}
+
+impl TriangleBvh {
+    pub fn new(vertices: &[Vertex], indices: &MeshIndices) -> Self {
+        let mut triangles = (0..indices.len() / 3)
+            .map(|triangle| {
+                let corner =
+                    |i| Point3::from(vertices[indices.get(triangle * 3 + i) as usize].position);
+                ([corner(0), corner(1), corner(2)], triangle as u32)
+            })
+            .collect::<Vec<_>>();
+        let mut nodes = Vec::new();
+        if !triangles.is_empty() {
+            Self::build_node(&mut nodes, &mut triangles, 0);
+        }
+        Self { nodes, triangles }
+    }
+
+    // splits the triangles at the median of their centers along the longest axis, returns the node index
+    fn build_node(
+        nodes: &mut Vec<BvhNode>,
+        triangles: &mut [([Point3<f32>; 3], u32)],
+        first: usize,
+    ) -> usize {
+        let node_index = nodes.len();
+        let aabb = Aabb::from_points(triangles.iter().flat_map(|(corners, _)| *corners));
+        let centroid = |corners: &[Point3<f32>; 3]| {
+            Point3::new(
+                (corners[0].x + corners[1].x + corners[2].x) / 3.0,
+                (corners[0].y + corners[1].y + corners[2].y) / 3.0,
+                (corners[0].z + corners[1].z + corners[2].z) / 3.0,
+            )
+        };
+        let centroids = Aabb::from_points(triangles.iter().map(|(corners, _)| centroid(corners)));
+        let extent = centroids.max - centroids.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        nodes.push(BvhNode {
+            aabb,
+            first: first as u32,
+            count: triangles.len() as u32,
+        });
+        // triangles with the same center can not be split
+        if triangles.len() <= MAX_LEAF_TRIANGLES || extent[axis] <= 0.0 {
+            return node_index;
+        }
+        triangles.sort_by(|a, b| centroid(&a.0)[axis].total_cmp(&centroid(&b.0)[axis]));
+        let middle = triangles.len() / 2;
+        let (left, right) = triangles.split_at_mut(middle);
+        Self::build_node(nodes, left, first);
+        let right_index = Self::build_node(nodes, right, first + middle);
+        nodes[node_index].first = right_index as u32;
+        nodes[node_index].count = 0;
+        node_index
+    }
+
+    /// t and the index in the mesh of the nearest triangle the ray hits before max_t
+    pub fn intersect(&self, ray: &Ray, max_t: f32) -> Option<(f32, usize)> {
+        let mut nearest: Option<(f32, usize)> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let limit = nearest.map_or(max_t, |(t, _)| t);
+            if node.aabb.intersect_ray(ray, limit).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.first as usize);
+                stack.push(node_index + 1);
+                continue;
+            }
+            let first = node.first as usize;
+            for (corners, triangle) in &self.triangles[first..first + node.count as usize] {
+                if let Some(t) = ray.intersect_triangle(corners)
+                    && t <= nearest.map_or(max_t, |(t, _)| t)
+                {
+                    nearest = Some((t, *triangle as usize));
+                }
+            }
+        }
+        nearest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{EuclideanSpace, Vector3};
+
+    use super::*;
+
+    fn vertex(position: [f32; 3]) -> Vertex {
+        Vertex {
+            position,
+            tex_coords: [0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    // a unit quad of two triangles at every depth, facing the z axis
+    fn stacked_quads(depths: &[f32]) -> (Vec<Vertex>, MeshIndices) {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for &z in depths {
+            let first = vertices.len() as u32;
+            for (x, y) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                vertices.push(vertex([x, y, z]));
+            }
+            indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+        }
+        let vertex_count = vertices.len();
+        (vertices, MeshIndices::new(indices, vertex_count))
+    }
+
+    // small deterministic generator so the tests do not need a rand dependency
+    struct Lcg(u64);
+
+    impl Lcg {
+        // in -1..1
+        fn next(&mut self) -> f32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+        }
+        fn point(&mut self, scale: f32) -> Point3<f32> {
+            Point3::new(self.next(), self.next(), self.next()) * scale
+        }
+    }
+
+    fn brute_force(vertices: &[Vertex], indices: &MeshIndices, ray: &Ray) -> Option<f32> {
+        (0..indices.len() / 3)
+            .filter_map(|triangle| {
+                let corner =
+                    |i| Point3::from(vertices[indices.get(triangle * 3 + i) as usize].position);
+                ray.intersect_triangle(&[corner(0), corner(1), corner(2)])
+            })
+            .min_by(f32::total_cmp)
+    }
+
+    #[test]
+    fn nearest_of_several() {
+        let (vertices, indices) = stacked_quads(&[0.0, 1.0, 2.0, 3.0, 4.0]);
+        let bvh = TriangleBvh::new(&vertices, &indices);
+        let ray = Ray::new(Point3::new(0.3, 0.6, -5.0), Vector3::new(0.0, 0.0, 1.0));
+        // the second triangle of the first quad
+        assert_eq!(bvh.intersect(&ray, f32::INFINITY), Some((5.0, 1)));
+        let ray = Ray::new(Point3::new(0.6, 0.3, 10.0), Vector3::new(0.0, 0.0, -1.0));
+        // the first triangle of the last quad
+        assert_eq!(bvh.intersect(&ray, f32::INFINITY), Some((6.0, 8)));
+        // starting between the quads only the ones in front count
+        let ray = Ray::new(Point3::new(0.6, 0.3, 2.5), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(bvh.intersect(&ray, f32::INFINITY), Some((0.5, 6)));
+    }
+
+    #[test]
+    fn miss() {
+        let (vertices, indices) = stacked_quads(&[0.0, 1.0, 2.0, 3.0, 4.0]);
+        let bvh = TriangleBvh::new(&vertices, &indices);
+        let ray = Ray::new(Point3::new(1.5, 0.5, -5.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(bvh.intersect(&ray, f32::INFINITY), None);
+        // every quad is further away than max_t
+        let ray = Ray::new(Point3::new(0.5, 0.5, -5.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(bvh.intersect(&ray, 4.0), None);
+        let (vertices, indices) = stacked_quads(&[]);
+        assert_eq!(
+            TriangleBvh::new(&vertices, &indices).intersect(&ray, f32::INFINITY),
+            None
+        );
+    }
+
+    #[test]
+    fn edge_hit() {
+        let (vertices, indices) = stacked_quads(&[0.0, 1.0, 2.0, 3.0, 4.0]);
+        let bvh = TriangleBvh::new(&vertices, &indices);
+        // on the outer edge of the quads, where the aabbs of the nodes end as well
+        let ray = Ray::new(Point3::new(1.0, 0.5, -5.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(
+            bvh.intersect(&ray, f32::INFINITY).map(|(t, _)| t),
+            Some(5.0)
+        );
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let mut lcg = Lcg(7);
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        // 300 random triangles of up to about 1 in size inside a box of 10
+        for triangle in 0..300u32 {
+            let center = lcg.point(5.0);
+            for _ in 0..3 {
+                let corner = center + lcg.point(0.5).to_vec();
+                vertices.push(vertex(corner.into()));
+            }
+            indices.extend([triangle * 3, triangle * 3 + 1, triangle * 3 + 2]);
+        }
+        let vertex_count = vertices.len();
+        let indices = MeshIndices::new(indices, vertex_count);
+        let bvh = TriangleBvh::new(&vertices, &indices);
+        let mut hits = 0;
+        for ray_index in 0..500 {
+            let origin = lcg.point(8.0);
+            // every other ray aims at the corner of a triangle, which is the hardest case for the aabbs of the
+            // nodes. rounding decides whether the triangle itself is hit, the bvh has to agree either way
+            let target = match ray_index % 2 {
+                0 => Point3::from(vertices[ray_index].position),
+                _ => lcg.point(4.0),
+            };
+            let ray = Ray::new(origin, target - origin);
+            let expected = brute_force(&vertices, &indices, &ray);
+            let actual = bvh.intersect(&ray, f32::INFINITY);
+            assert_eq!(actual.map(|(t, _)| t), expected, "ray {:?}", ray);
+            if let Some((t, triangle)) = actual {
+                // the reported triangle is really hit at t
+                let corner = |i: usize| Point3::from(vertices[triangle * 3 + i].position);
+                assert_eq!(
+                    ray.intersect_triangle(&[corner(0), corner(1), corner(2)]),
+                    Some(t)
+                );
+                hits += 1;
+            }
+        }
+        assert!(hits > 100, "only {} hits", hits);
+    }
+}
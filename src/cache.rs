@@ -5,7 +5,7 @@ use moka::{notification::RemovalCause, sync::Cache};
 use rusttype::Font;
 
 use crate::{
-    model_data::ModelData, model_meta::ModelMeta, my_texture::{MyTexture, TextureSource}, ui_node::UIIdentifier, ui_renderable::{TextureMeta, UIRenderable}
+    asset_error::AssetError, model_data::ModelData, model_meta::ModelMeta, my_texture::{MyTexture, TextureSource}, ui_node::UIIdentifier, ui_renderable::{TextureMeta, UIRenderable}
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -50,11 +50,17 @@ lazy_static! {
     };
 }
 
-pub fn get_font(font_file_path: String) -> Arc<CacheValue> {
-    CACHE.get_with(CacheKey::Font(font_file_path.clone()), || {
-        let font_data = std::fs::read(font_file_path).unwrap();
-        let font = Font::try_from_vec(font_data).unwrap();
+pub fn get_font(font_file_path: String) -> Result<Arc<CacheValue>, Arc<AssetError>> {
+    CACHE.try_get_with(CacheKey::Font(font_file_path.clone()), || {
+        let font_data = std::fs::read(&font_file_path).map_err(|source| AssetError::Io {
+            path: font_file_path.clone(),
+            source,
+        })?;
+        let font = Font::try_from_vec(font_data).ok_or_else(|| AssetError::UnsupportedFormat {
+            path: font_file_path.clone(),
+            description: "not a valid TrueType or OpenType font".to_string(),
+        })?;
         let font = CacheValue::Font(font);
-        Arc::new(font)
+        Ok(Arc::new(font))
     })
 }
@@ -0,0 +1,161 @@
+// what the renderer needs to know about a camera, see CameraUniform::new.
+// the main pass stores reversed depth, 1 at the near plane and 0 at the far plane, because float depth is
+// much more precise that way. projections that are not reversed get flipped when they are uploaded, so
+// switching between them needs no pipeline changes. the shadow maps keep the usual depth
+
+use cgmath::{Matrix4, Point3, SquareMatrix, Transform, Vector3};
+
+use crate::{bounds::Ray, my_camera::OPENGL_TO_WGPU_MATRIX};
+
+// maps the depth d of the wgpu clip space to 1 - d
+#[rustfmt::skip]
+pub const REVERSE_Z_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
+);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    // fovy in degrees
+    Perspective { fovy: f32, znear: f32, zfar: f32 },
+    // no far plane, the depth already comes out reversed
+    ReversedInfinitePerspective { fovy: f32, znear: f32 },
+    // height of the visible area in world units, the width follows the aspect.
+    // good for top-down and 2d editor views
+    Orthographic { height: f32, znear: f32, zfar: f32 },
+}
+
+impl Projection {
+    /// into the wgpu clip space, with a depth from 0 at znear to 1 at zfar unless reversed_z is true
+    pub fn matrix(&self, aspect: f32) -> Matrix4<f32> {
+        match *self {
+            Projection::Perspective { fovy, znear, zfar } => {
+                OPENGL_TO_WGPU_MATRIX * cgmath::perspective(cgmath::Deg(fovy), aspect, znear, zfar)
+            }
+            Projection::ReversedInfinitePerspective { fovy, znear } => {
+                let f = 1.0 / (fovy / 2.0).to_radians().tan();
+                // the depth is znear / view distance
+                #[rustfmt::skip]
+                let matrix = Matrix4::new(
+                    f / aspect, 0.0, 0.0, 0.0,
+                    0.0, f, 0.0, 0.0,
+                    0.0, 0.0, 0.0, -1.0,
+                    0.0, 0.0, znear, 0.0,
+                );
+                matrix
+            }
+            Projection::Orthographic {
+                height,
+                znear,
+                zfar,
+            } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * aspect;
+                OPENGL_TO_WGPU_MATRIX
+                    * cgmath::ortho(
+                        -half_width,
+                        half_width,
+                        -half_height,
+                        half_height,
+                        znear,
+                        zfar,
+                    )
+            }
+        }
+    }
+
+    pub fn reversed_z(&self) -> bool {
+        matches!(self, Projection::ReversedInfinitePerspective { .. })
+    }
+
+    pub fn znear(&self) -> f32 {
+        match *self {
+            Projection::Perspective { znear, .. }
+            | Projection::ReversedInfinitePerspective { znear, .. }
+            | Projection::Orthographic { znear, .. } => znear,
+        }
+    }
+
+    /// infinity without a far plane
+    pub fn zfar(&self) -> f32 {
+        match *self {
+            Projection::Perspective { zfar, .. } | Projection::Orthographic { zfar, .. } => zfar,
+            Projection::ReversedInfinitePerspective { .. } => f32::INFINITY,
+        }
+    }
+
+    /// view space corners of the visible volume between the view distances near and far
+    pub fn slice_corners(&self, aspect: f32, near: f32, far: f32) -> [Vector3<f32>; 8] {
+        // half the height of the visible area at a distance
+        let half_height = |distance: f32| match *self {
+            Projection::Perspective { fovy, .. }
+            | Projection::ReversedInfinitePerspective { fovy, .. } => {
+                distance * (fovy / 2.0).to_radians().tan()
+            }
+            Projection::Orthographic { height, .. } => height / 2.0,
+        };
+        let mut corners = [Vector3::new(0.0, 0.0, 0.0); 8];
+        for (i, distance) in [near, far].into_iter().enumerate() {
+            let half_height = half_height(distance);
+            for (j, (x, y)) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                .into_iter()
+                .enumerate()
+            {
+                corners[i * 4 + j] =
+                    Vector3::new(x * half_height * aspect, y * half_height, -distance);
+            }
+        }
+        corners
+    }
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective {
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+}
+
+pub trait Camera {
+    fn build_view_matrix(&self) -> Matrix4<f32>;
+    fn projection(&self) -> Projection;
+    /// world space position, the shaders use it for the view direction
+    fn position(&self) -> Point3<f32>;
+
+    fn build_projection_matrix(&self, aspect: f32) -> Matrix4<f32> {
+        self.projection().matrix(aspect)
+    }
+
+    /// world space ray through a cursor position in physical pixels, like InputContext::mouse_position.
+    /// it starts on the near plane
+    fn screen_ray(
+        &self,
+        cursor_position: (f64, f64),
+        screen_size: &winit::dpi::PhysicalSize<u32>,
+    ) -> Ray {
+        let (width, height) = (
+            screen_size.width.max(1) as f32,
+            screen_size.height.max(1) as f32,
+        );
+        let x = cursor_position.0 as f32 / width * 2.0 - 1.0;
+        // pixel rows go down, clip space y goes up
+        let y = 1.0 - cursor_position.1 as f32 / height * 2.0;
+        let inverse = (self.build_projection_matrix(width / height) * self.build_view_matrix())
+            .invert()
+            .unwrap();
+        // the far plane of a reversed infinite projection can not be unprojected, half way always can
+        let near_depth = if self.projection().reversed_z() {
+            1.0
+        } else {
+            0.0
+        };
+        let near = inverse.transform_point(Point3::new(x, y, near_depth));
+        let middle = inverse.transform_point(Point3::new(x, y, 0.5));
+        Ray::new(near, middle - near)
+    }
+}
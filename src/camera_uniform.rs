@@ -1,4 +1,7 @@
-use crate::my_camera::MyCamera;
+use crate::{
+    camera::{Camera, REVERSE_Z_MATRIX},
+    frustum::Frustum,
+};
 
 // We need this for Rust to store our data correctly for the shaders
 #[repr(C)]
@@ -13,17 +16,32 @@ pub struct CameraUniform {
 }
 
 impl CameraUniform {
-    pub fn new(camera: &MyCamera, aspect: f32, translate: bool) -> Self {
+    pub fn new(camera: &impl Camera, aspect: f32, translate: bool) -> Self {
         let mut view = camera.build_view_matrix();
         if !translate {
             view[3][0] = 0.0;
             view[3][1] = 0.0;
             view[3][2] = 0.0;
         }
+        // the main pass uses reversed depth, see camera.rs
+        let proj = match camera.projection().reversed_z() {
+            true => camera.build_projection_matrix(aspect),
+            false => REVERSE_Z_MATRIX * camera.build_projection_matrix(aspect),
+        };
+        let pos = camera.position();
         Self {
             view: view.into(),
-            proj: camera.build_projection_matrix(aspect).into(),
-            camera_pos: [camera.pos.x, camera.pos.y, camera.pos.z, 1.0],
+            proj: proj.into(),
+            camera_pos: [pos.x, pos.y, pos.z, 1.0],
         }
     }
+
+    pub fn view_projection(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from(self.proj) * cgmath::Matrix4::from(self.view)
+    }
+
+    /// the view volume in world space, around the origin for uniforms made without translation
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&self.view_projection())
+    }
 }
@@ -0,0 +1,115 @@
+// first person fly camera: the mouse turns the camera while the cursor is grabbed, WASD moves along the view
+// direction and QE moves down and up. the velocity lives in MyCamera::curr_local_speed (x right, y up,
+// z forward), it grows with MyCamera::acceleration up to max_speed and decays with damp_factor once the keys
+// of an axis are released
+
+use cgmath::{InnerSpace, Vector3};
+use winit::{
+    keyboard::KeyCode,
+    window::{CursorGrabMode, Window},
+};
+
+use crate::{input_context::InputContext, my_camera::MyCamera};
+
+pub struct FlyCameraController {
+    // mouse look only works while the cursor is grabbed, otherwise the mouse belongs to the ui
+    pub cursor_grabbed: bool,
+    // toggles cursor_grabbed
+    pub grab_key: KeyCode,
+    // degrees, keeps the camera from flipping over at the poles
+    pub max_pitch: f32,
+    // the grab state last applied to the window
+    applied_cursor_grab: bool,
+}
+
+impl FlyCameraController {
+    pub fn new() -> Self {
+        Self {
+            cursor_grabbed: false,
+            grab_key: KeyCode::Escape,
+            max_pitch: 89.0,
+            applied_cursor_grab: false,
+        }
+    }
+
+    pub fn toggle_cursor_grab(&mut self) {
+        self.cursor_grabbed = !self.cursor_grabbed;
+    }
+
+    /// turns and moves the camera, delta_time is the frame time in seconds
+    pub fn update(
+        &mut self,
+        camera: &mut MyCamera,
+        input_context: &mut InputContext,
+        delta_time: f32,
+    ) {
+        if input_context.get_key_down(self.grab_key) {
+            self.toggle_cursor_grab();
+        }
+        if self.cursor_grabbed {
+            let (delta_x, delta_y) = input_context.device_mouse_delta_accumulated();
+            camera.yaw = (camera.yaw + delta_x as f32 * camera.sensitivity) % 360.0;
+            // moving the mouse up gives a negative delta
+            camera.pitch = (camera.pitch - delta_y as f32 * camera.sensitivity)
+                .clamp(-self.max_pitch, self.max_pitch);
+        }
+
+        let mut axis = |positive: KeyCode, negative: KeyCode| {
+            input_context.get_key(positive) as i32 as f32
+                - input_context.get_key(negative) as i32 as f32
+        };
+        let input = Vector3::new(
+            axis(KeyCode::KeyD, KeyCode::KeyA),
+            axis(KeyCode::KeyE, KeyCode::KeyQ),
+            axis(KeyCode::KeyW, KeyCode::KeyS),
+        );
+        // diagonal movement is not faster
+        let input = if input.magnitude2() > 0.0 {
+            input.normalize()
+        } else {
+            input
+        };
+        let damping = (camera.damp_factor * delta_time).min(1.0);
+        let mut speed = camera.curr_local_speed;
+        for i in 0..3 {
+            if input[i] != 0.0 {
+                speed[i] += input[i] * camera.acceleration * delta_time;
+            } else {
+                speed[i] -= speed[i] * damping;
+            }
+        }
+        if speed.magnitude() > camera.max_speed {
+            speed = speed.normalize_to(camera.max_speed);
+        }
+        camera.curr_local_speed = speed;
+        let velocity =
+            camera.right() * speed.x + Vector3::unit_y() * speed.y + camera.forward() * speed.z;
+        camera.pos += velocity * delta_time;
+    }
+
+    /// grabs or releases the cursor of the window if cursor_grabbed changed since the last call
+    pub fn apply_cursor_grab(&mut self, window: &Window) {
+        if self.applied_cursor_grab == self.cursor_grabbed {
+            return;
+        }
+        self.applied_cursor_grab = self.cursor_grabbed;
+        let result = if self.cursor_grabbed {
+            // not every platform can lock the cursor in place, confining it to the window works as well
+            window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(error) = result {
+            log::warn!("could not change the cursor grab: {}", error);
+        }
+        window.set_cursor_visible(!self.cursor_grabbed);
+    }
+}
+
+impl Default for FlyCameraController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,107 @@
+// keeps the cpu at most a few frames ahead of the gpu instead of waiting for every frame to finish.
+// only the instance_arena is duplicated, every frame in flight has its own and it is only reset once the gpu
+// finished the submission that read it. the camera, light, shadow, joint and morph buffers exist once and are
+// overwritten with queue.write_buffer every frame, which the queue orders before the next submission, so they
+// need no copy per frame
+
+use crate::buffer_arena::BufferArena;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresentSettings {
+    // Fifo waits for vsync, Mailbox replaces the queued frame with a newer one, Immediate does not wait
+    // and may tear. modes the surface does not support fall back to Fifo
+    pub present_mode: wgpu::PresentMode,
+    // frames recorded before the oldest one has to finish, also the desired_maximum_frame_latency of the surface
+    pub max_frames_in_flight: u32,
+}
+
+impl Default for PresentSettings {
+    fn default() -> Self {
+        Self {
+            present_mode: wgpu::PresentMode::Fifo,
+            max_frames_in_flight: 2,
+        }
+    }
+}
+
+// the resources of one frame in flight
+pub struct FrameResources {
+    // instance data of every pass
+    pub instance_arena: BufferArena,
+    // the submit that last read the resources, None once the gpu is done with it
+    submission: Option<wgpu::SubmissionIndex>,
+}
+
+impl FrameResources {
+    fn new(index: usize) -> Self {
+        Self {
+            instance_arena: BufferArena::vertex(&format!("Instance Arena {index}")),
+            submission: None,
+        }
+    }
+
+    fn wait(&mut self, device: &wgpu::Device) {
+        if let Some(submission) = self.submission.take() {
+            device.poll(wgpu::Maintain::WaitForSubmissionIndex(submission));
+        }
+    }
+}
+
+pub struct FramePacer {
+    frames: Vec<FrameResources>,
+    // the frame that is recorded at the moment
+    current: usize,
+}
+
+impl FramePacer {
+    pub fn new(frames_in_flight: usize) -> Self {
+        Self {
+            frames: (0..frames_in_flight.max(1))
+                .map(FrameResources::new)
+                .collect(),
+            current: 0,
+        }
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// waits until the gpu is done with the resources of the current frame and resets them
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        let frame = &mut self.frames[self.current];
+        frame.wait(device);
+        frame.instance_arena.reset();
+    }
+
+    pub fn frame_mut(&mut self) -> &mut FrameResources {
+        &mut self.frames[self.current]
+    }
+
+    /// remembers the submit that reads the resources of the current frame and moves on to the next one
+    pub fn end_frame(&mut self, submission: wgpu::SubmissionIndex) {
+        self.frames[self.current].submission = Some(submission);
+        self.current = (self.current + 1) % self.frames.len();
+    }
+
+    /// waits for every frame in flight
+    pub fn wait_idle(&mut self, device: &wgpu::Device) {
+        for frame in &mut self.frames {
+            frame.wait(device);
+        }
+    }
+
+    /// the frames still in flight finish first, their resources are dropped or kept for the new count
+    pub fn set_frames_in_flight(&mut self, device: &wgpu::Device, frames_in_flight: usize) {
+        let frames_in_flight = frames_in_flight.max(1);
+        if frames_in_flight == self.frames.len() {
+            return;
+        }
+        self.wait_idle(device);
+        self.frames.truncate(frames_in_flight);
+        let len = self.frames.len();
+        self.frames
+            .extend((len..frames_in_flight).map(FrameResources::new));
+        self.current = 0;
+    }
+}
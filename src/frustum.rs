@@ -0,0 +1,217 @@
+// view frustum culling: instances whose bounds are completely outside the view volume are dropped before the
+// instance buffers of the opaque and transparent passes are built. the shadow pass still draws them, they
+// can cast shadows into the view
+
+use cgmath::{InnerSpace, Matrix4, Vector3, Vector4};
+
+use crate::{
+    bounds::{Aabb, BoundingSphere},
+    model_data::MyMesh,
+    model_instance::ModelInstanceRaw,
+};
+
+// the planes of a view volume, points inside have a positive distance to all of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    // xyz is the unit normal pointing inside, w the distance of the origin
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// the frustum of a view projection matrix into the wgpu clip space, with normal or reversed depth
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Self {
+        let row = |i: usize| Vector4::new(matrix.x[i], matrix.y[i], matrix.z[i], matrix.w[i]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        // -w <= x <= w, -w <= y <= w and 0 <= z <= w
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            let length = plane.truncate().magnitude();
+            // the far plane of an infinite projection has no normal, everything is in front of it
+            if length < f32::EPSILON {
+                Vector4::new(0.0, 0.0, 0.0, 1.0)
+            } else {
+                plane / length
+            }
+        });
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        let center = Vector3::new(sphere.center.x, sphere.center.y, sphere.center.z);
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center) + plane.w >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner farthest along the normal
+            let coordinate = |i: usize| match plane[i] >= 0.0 {
+                true => aabb.max[i],
+                false => aabb.min[i],
+            };
+            let corner = Vector3::new(coordinate(0), coordinate(1), coordinate(2));
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+// instances drawn and culled by the opaque and transparent passes in one frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub drawn: u32,
+    pub culled: u32,
+}
+
+impl CullStats {
+    pub fn add(&mut self, other: CullStats) {
+        self.drawn += other.drawn;
+        self.culled += other.culled;
+    }
+}
+
+/// the instances of the mesh that may be visible. the sphere is tested first because it is cheaper, the box
+/// only for the instances the sphere keeps
+pub fn cull_instances(
+    frustum: &Frustum,
+    mesh: &MyMesh,
+    instances: &[ModelInstanceRaw],
+    cull_stats: &mut CullStats,
+) -> Vec<ModelInstanceRaw> {
+    // the vertices of skinned and morphed meshes move on the gpu, away from the bounds they were loaded with
+    let deformed = mesh.skin_buffer.is_some() || mesh.morph.is_some();
+    let visible = instances
+        .iter()
+        .filter(|instance| {
+            if deformed {
+                return true;
+            }
+            let model = instance.model_matrix();
+            frustum.intersects_sphere(&mesh.bounding_sphere.transform(&model))
+                && frustum.intersects_aabb(&mesh.aabb.transform(&model))
+        })
+        .copied()
+        .collect::<Vec<_>>();
+    cull_stats.drawn += visible.len() as u32;
+    cull_stats.culled += (instances.len() - visible.len()) as u32;
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Point3;
+
+    use super::*;
+    use crate::camera::{Projection, REVERSE_Z_MATRIX};
+
+    const PERSPECTIVE: Projection = Projection::Perspective {
+        fovy: 90.0,
+        znear: 0.1,
+        zfar: 100.0,
+    };
+    const REVERSED_INFINITE: Projection = Projection::ReversedInfinitePerspective {
+        fovy: 90.0,
+        znear: 0.1,
+    };
+    const ORTHOGRAPHIC: Projection = Projection::Orthographic {
+        height: 10.0,
+        znear: 0.1,
+        zfar: 100.0,
+    };
+
+    // the camera at z = 5 looks at the origin, the view is 10 wide at the origin for every projection.
+    // like CameraUniform the depth is reversed before the planes are taken
+    fn frustum(projection: Projection) -> Frustum {
+        let view = Matrix4::look_at_rh(
+            Point3::new(0.0, 0.0, 5.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::unit_y(),
+        );
+        let projection_matrix = match projection.reversed_z() {
+            true => projection.matrix(1.0),
+            false => REVERSE_Z_MATRIX * projection.matrix(1.0),
+        };
+        Frustum::from_matrix(&(projection_matrix * view))
+    }
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> BoundingSphere {
+        BoundingSphere {
+            center: Point3::new(x, y, z),
+            radius,
+        }
+    }
+
+    fn aabb(min: [f32; 3], max: [f32; 3]) -> Aabb {
+        Aabb {
+            min: min.into(),
+            max: max.into(),
+        }
+    }
+
+    #[test]
+    fn inside() {
+        for projection in [PERSPECTIVE, REVERSED_INFINITE, ORTHOGRAPHIC] {
+            let frustum = frustum(projection);
+            assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, 0.0, 0.5)));
+            assert!(frustum.intersects_sphere(&sphere(2.0, -2.0, -10.0, 0.5)));
+            assert!(frustum.intersects_aabb(&aabb([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0])));
+        }
+    }
+
+    #[test]
+    fn outside() {
+        for projection in [PERSPECTIVE, REVERSED_INFINITE, ORTHOGRAPHIC] {
+            let frustum = frustum(projection);
+            // behind the camera
+            assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 10.0, 1.0)));
+            assert!(!frustum.intersects_aabb(&aabb([-1.0, -1.0, 8.0], [1.0, 1.0, 9.0])));
+            // next to and above the view
+            assert!(!frustum.intersects_sphere(&sphere(8.0, 0.0, 0.0, 1.0)));
+            assert!(!frustum.intersects_aabb(&aabb([-1.0, 7.0, -1.0], [1.0, 9.0, 1.0])));
+        }
+        // beyond the far plane, only the infinite projection has none
+        let far_sphere = sphere(0.0, 0.0, -200.0, 1.0);
+        let far_box = aabb([-1.0, -1.0, -201.0], [1.0, 1.0, -199.0]);
+        for projection in [PERSPECTIVE, ORTHOGRAPHIC] {
+            assert!(!frustum(projection).intersects_sphere(&far_sphere));
+            assert!(!frustum(projection).intersects_aabb(&far_box));
+        }
+        assert!(frustum(REVERSED_INFINITE).intersects_sphere(&far_sphere));
+        assert!(frustum(REVERSED_INFINITE).intersects_aabb(&far_box));
+    }
+
+    #[test]
+    fn straddling() {
+        for projection in [PERSPECTIVE, REVERSED_INFINITE, ORTHOGRAPHIC] {
+            let frustum = frustum(projection);
+            // across the right plane, which is at x = 5 at the origin
+            assert!(frustum.intersects_sphere(&sphere(5.5, 0.0, 0.0, 1.0)));
+            assert!(frustum.intersects_aabb(&aabb([4.0, -1.0, -1.0], [6.0, 1.0, 1.0])));
+            // across the near plane, partly behind the camera
+            assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, 5.0, 1.0)));
+            assert!(frustum.intersects_aabb(&aabb([-1.0, -1.0, 3.0], [1.0, 1.0, 7.0])));
+        }
+        // across the far plane
+        for projection in [PERSPECTIVE, ORTHOGRAPHIC] {
+            let frustum = frustum(projection);
+            assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -95.0, 1.0)));
+            assert!(frustum.intersects_aabb(&aabb([-1.0, -1.0, -96.0], [1.0, 1.0, -94.0])));
+        }
+    }
+
+    #[test]
+    fn normal_depth() {
+        // from_matrix takes the near and far planes from a depth of 0 to 1 as well
+        let view = Matrix4::look_at_rh(
+            Point3::new(0.0, 0.0, 5.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::unit_y(),
+        );
+        for projection in [PERSPECTIVE, ORTHOGRAPHIC] {
+            let normal = Frustum::from_matrix(&(projection.matrix(1.0) * view));
+            assert!(normal.intersects_sphere(&sphere(0.0, 0.0, 0.0, 0.5)));
+            assert!(normal.intersects_sphere(&sphere(0.0, 0.0, -95.0, 1.0)));
+            assert!(!normal.intersects_sphere(&sphere(0.0, 0.0, -200.0, 1.0)));
+            assert!(!normal.intersects_sphere(&sphere(0.0, 0.0, 10.0, 1.0)));
+        }
+    }
+}
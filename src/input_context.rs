@@ -1,6 +1,6 @@
 use std::{collections::HashMap, result};
 use winit::{
-    event::{DeviceEvent, ElementState, MouseButton, WindowEvent},
+    event::{DeviceEvent, ElementState, MouseButton, MouseScrollDelta, WindowEvent},
     keyboard::{Key, KeyCode, NamedKey, PhysicalKey},
 };
 #[derive(Default)]
@@ -15,6 +15,11 @@ pub struct InputContext {
     mouse_right: bool,
     mouse_right_pressed_flag: bool,
     mouse_right_released_flag: bool,
+    mouse_middle: bool,
+    mouse_middle_pressed_flag: bool,
+    mouse_middle_released_flag: bool,
+    // in lines, positive when scrolling up / away from the user
+    scroll_delta_accumulated: f32,
     cursor_position: Option<(f64, f64)>,
     device_mouse_delta_accumulated: (f64, f64),
     pressed_str: Option<String>,
@@ -110,9 +115,32 @@ impl InputContext {
                             );
                         }
                     },
+                    MouseButton::Middle => match state {
+                        ElementState::Pressed => {
+                            handle_mouse_pressed(
+                                &mut self.mouse_middle,
+                                &mut self.mouse_middle_pressed_flag,
+                                &mut self.mouse_middle_released_flag,
+                            );
+                        }
+                        ElementState::Released => {
+                            handle_mouse_released(
+                                &mut self.mouse_middle,
+                                &mut self.mouse_middle_pressed_flag,
+                                &mut self.mouse_middle_released_flag,
+                            );
+                        }
+                    },
                     _ => {}
                 }
             }
+            WindowEvent::MouseWheel { delta, .. } => {
+                // touchpads report pixels, count 20 of them as one line
+                self.scroll_delta_accumulated += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                };
+            }
             WindowEvent::CursorMoved { device_id: _, position }=> {
                 self.cursor_position = Some((position.x, position.y));
             }
@@ -181,12 +209,36 @@ impl InputContext {
         result
     }
 
+    pub fn mouse_middle_down(&mut self) -> bool {
+        let result = self.mouse_middle_pressed_flag;
+        self.mouse_middle_pressed_flag = false;
+        result
+    }
+
+    pub fn mouse_middle(&mut self) -> bool {
+        self.mouse_middle
+    }
+
+    pub fn mouse_middle_up(&mut self) -> bool {
+        let result = self.mouse_middle_released_flag;
+        self.mouse_middle_released_flag = false;
+        result
+    }
+
     pub fn mouse_position(&self) -> Option<(f64, f64)> {
         self.cursor_position
     }
     pub fn device_mouse_delta_accumulated(&mut self) -> (f64, f64) {
         self.device_mouse_delta_accumulated
     }
+    pub fn scroll_delta_accumulated(&mut self) -> f32 {
+        self.scroll_delta_accumulated
+    }
+    // called once per frame after State::update, so the mouse and scroll deltas only cover one frame
+    pub fn end_frame(&mut self) {
+        self.device_mouse_delta_accumulated = (0.0, 0.0);
+        self.scroll_delta_accumulated = 0.0;
+    }
     pub fn get_pressed_str(&mut self) -> Option<String> {
         let result = self.pressed_str.clone();
         self.pressed_str = None;
@@ -1,17 +1,38 @@
+pub mod animation;
 pub mod app;
+pub mod asset_error;
+pub mod asset_server;
+pub mod bounds;
+pub mod buffer_arena;
+pub mod bvh;
 pub mod cache;
+pub mod camera;
 pub mod camera_uniform;
 pub mod canvas;
+pub mod fly_camera_controller;
+pub mod frame_pacer;
+pub mod frustum;
 pub mod light_uniform;
+pub mod material_uniform;
+pub mod mesh_builder;
+pub mod mesh_cache;
 pub mod model_data;
 pub mod model_instance;
 pub mod model_meta;
+pub mod morph_pipeline;
 pub mod my_camera;
 pub mod my_texture;
 pub mod opaque_pipeline;
+pub mod orbit_camera;
+pub mod picking;
 pub mod render_context;
+pub mod shadow_pipeline;
 pub mod state;
+pub mod tonemap_pipeline;
+pub mod transparent_pipeline;
 pub mod ui;
+pub mod ui_golden;
+pub mod ui_layout;
 pub mod ui_node;
 pub mod ui_pipeline;
 pub mod ui_renderable;
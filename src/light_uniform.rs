@@ -1,10 +1,137 @@
+// lights are submitted to State every frame and uploaded to a storage buffer:
+// a LightsHeader followed by up to MAX_LIGHTS LightRaw entries
+
+// lights beyond this are dropped with a warning
+pub const MAX_LIGHTS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot,
+}
+
+#[derive(Debug, Clone)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: cgmath::Vector3<f32>, // ignored by directional lights
+    pub direction: cgmath::Vector3<f32>, // ignored by point lights
+    pub color: cgmath::Vector3<f32>,
+    pub intensity: f32,
+    // distance at which point and spot lights fade out completely
+    pub range: f32,
+    // spot cone angles in degrees, measured from the direction
+    pub inner_cone: f32,
+    pub outer_cone: f32,
+    // only directional and spot lights can cast shadows, see ShadowPipeline
+    pub cast_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(
+        direction: cgmath::Vector3<f32>,
+        color: cgmath::Vector3<f32>,
+        intensity: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            position: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            direction,
+            color,
+            intensity,
+            range: 0.0,
+            inner_cone: 0.0,
+            outer_cone: 0.0,
+            cast_shadows: true,
+        }
+    }
+    pub fn point(
+        position: cgmath::Vector3<f32>,
+        color: cgmath::Vector3<f32>,
+        intensity: f32,
+        range: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            direction: cgmath::Vector3::new(0.0, -1.0, 0.0),
+            color,
+            intensity,
+            range,
+            inner_cone: 0.0,
+            outer_cone: 0.0,
+            cast_shadows: false,
+        }
+    }
+    pub fn spot(
+        position: cgmath::Vector3<f32>,
+        direction: cgmath::Vector3<f32>,
+        color: cgmath::Vector3<f32>,
+        intensity: f32,
+        range: f32,
+        inner_cone: f32,
+        outer_cone: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot,
+            position,
+            direction,
+            color,
+            intensity,
+            range,
+            inner_cone,
+            outer_cone,
+            cast_shadows: true,
+        }
+    }
+    /// shadow_layer is the first layer of the shadow texture used by this light, or -1 without shadows
+    pub fn to_raw(&self, shadow_layer: i32) -> LightRaw {
+        let kind = match self.kind {
+            LightKind::Directional => 0,
+            LightKind::Point => 1,
+            LightKind::Spot => 2,
+        };
+        LightRaw {
+            position: self.position.into(),
+            kind,
+            direction: self.direction.into(),
+            range: self.range,
+            color: self.color.into(),
+            intensity: self.intensity,
+            cos_inner_cone: self.inner_cone.to_radians().cos(),
+            cos_outer_cone: self.outer_cone.to_radians().cos(),
+            shadow_layer,
+            _padding: 0.0,
+        }
+    }
+}
+
+// matches struct Light in pbr.wgsl, vec3 fields are followed by a scalar to fill the 16 byte alignment
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct LightUniform {
+pub struct LightRaw {
     pub position: [f32; 3],
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding: u32,
+    pub kind: u32,
+    pub direction: [f32; 3],
+    pub range: f32,
     pub color: [f32; 3],
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding2: u32,
+    pub intensity: f32,
+    pub cos_inner_cone: f32,
+    pub cos_outer_cone: f32,
+    pub shadow_layer: i32,
+    pub _padding: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightsHeader {
+    pub count: u32,
+    // the light array starts at the next 16 byte boundary
+    pub _padding: [u32; 3],
+}
+
+impl LightsHeader {
+    pub fn buffer_size() -> wgpu::BufferAddress {
+        (size_of::<LightsHeader>() + MAX_LIGHTS * size_of::<LightRaw>()) as wgpu::BufferAddress
+    }
 }
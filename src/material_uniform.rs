@@ -0,0 +1,27 @@
+use crate::model_data::DecodedMaterial;
+
+// matches struct Material in pbr.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub base_color_factor: [f32; 4],
+    pub emissive_factor: [f32; 3],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub opacity: f32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    pub _padding: [f32; 2],
+}
+
+impl MaterialUniform {
+    pub fn new(material: &DecodedMaterial) -> Self {
+        Self {
+            base_color_factor: material.base_color_factor,
+            emissive_factor: material.emissive_factor,
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            opacity: material.opacity,
+            _padding: [0.0; 2],
+        }
+    }
+}
@@ -0,0 +1,466 @@
+// meshes built from cpu side vertex and index arrays instead of a model file
+// a MeshBuilder can be uploaded on its own with build, or turned into a DecodedModel and submitted like a model file,
+// either as a ModelMeta::Primitive that the asset server generates by itself, or registered under a
+// ModelMeta::Procedural name with AssetServer::insert_model.
+// every generator produces texture coordinates, normals and tangents, and is centered on the origin
+
+use std::{
+    collections::HashMap,
+    f32::consts::{PI, TAU},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use cgmath::{InnerSpace, SquareMatrix, Vector2, Vector3, Zero};
+
+use crate::{
+    animation::Skeleton,
+    model_data::{DecodedMaterial, DecodedMesh, DecodedModel, MyMesh},
+    opaque_pipeline::OpaquePipeline,
+    vertex::Vertex,
+};
+
+pub struct MeshBuilder {
+    pub vertices: Vec<Vertex>,
+    // triangle list, counter clockwise triangles face the camera
+    pub indices: Vec<u32>,
+    pub material: DecodedMaterial,
+}
+
+impl MeshBuilder {
+    pub fn new() -> Self {
+        Self {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            material: DecodedMaterial::default(),
+        }
+    }
+    pub fn with_vertices(mut self, vertices: Vec<Vertex>) -> Self {
+        self.vertices = vertices;
+        self
+    }
+    pub fn with_indices(mut self, indices: Vec<u32>) -> Self {
+        self.indices = indices;
+        self
+    }
+    pub fn with_material(mut self, material: DecodedMaterial) -> Self {
+        self.material = material;
+        self
+    }
+
+    /// adds a vertex without a tangent and returns its index, see generate_tangents
+    pub fn add_vertex(
+        &mut self,
+        position: [f32; 3],
+        tex_coords: [f32; 2],
+        normal: [f32; 3],
+    ) -> u32 {
+        self.vertices.push(Vertex {
+            position,
+            tex_coords,
+            normal,
+            tangent: [0.0, 0.0, 0.0, 1.0],
+        });
+        (self.vertices.len() - 1) as u32
+    }
+    pub fn add_triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.indices.extend_from_slice(&[a, b, c]);
+    }
+
+    // a (columns + 1) x (rows + 1) grid of vertices, vertex returns the position and normal of a grid point.
+    // the texture coordinates run from 0 to 1 along the columns and from 1 to 0 down the rows (images are
+    // flipped on upload, so v = 1 is the top row of the image). the triangles face the side that
+    // cross(d position / d row, d position / d column) points to
+    fn add_grid(
+        &mut self,
+        columns: u32,
+        rows: u32,
+        vertex: impl Fn(u32, u32) -> ([f32; 3], [f32; 3]),
+    ) {
+        let base = self.vertices.len() as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (position, normal) = vertex(column, row);
+                let tex_coords = [
+                    column as f32 / columns as f32,
+                    1.0 - row as f32 / rows as f32,
+                ];
+                self.add_vertex(position, tex_coords, normal);
+            }
+        }
+        let index = |column: u32, row: u32| base + row * (columns + 1) + column;
+        for row in 0..rows {
+            for column in 0..columns {
+                let top_left = index(column, row);
+                let top_right = index(column + 1, row);
+                let bottom_right = index(column + 1, row + 1);
+                let bottom_left = index(column, row + 1);
+                self.add_triangle(top_left, bottom_right, top_right);
+                self.add_triangle(top_left, bottom_left, bottom_right);
+            }
+        }
+    }
+
+    // a disc of the given radius at height y facing up or down, with the texture mapped from above
+    fn add_disc(&mut self, radius: f32, y: f32, segments: u32, up: bool) {
+        let normal = if up {
+            [0.0, 1.0, 0.0]
+        } else {
+            [0.0, -1.0, 0.0]
+        };
+        // seen from below, x runs the other way
+        let s_direction = if up { 0.5 } else { -0.5 };
+        let center = self.add_vertex([0.0, y, 0.0], [0.5, 0.5], normal);
+        for segment in 0..=segments {
+            let angle = segment as f32 / segments as f32 * TAU;
+            let (sin, cos) = angle.sin_cos();
+            self.add_vertex(
+                [radius * sin, y, radius * cos],
+                [0.5 + s_direction * sin, 0.5 - 0.5 * cos],
+                normal,
+            );
+        }
+        for segment in 0..segments {
+            let current = center + 1 + segment;
+            if up {
+                self.add_triangle(center, current, current + 1);
+            } else {
+                self.add_triangle(center, current + 1, current);
+            }
+        }
+    }
+
+    /// computes the tangents from the texture coordinates, the way assimp's CalculateTangentSpace does.
+    /// vertices whose triangles have degenerate texture coordinates keep a zero tangent
+    pub fn generate_tangents(&mut self) {
+        let mut tangents = vec![Vector3::zero(); self.vertices.len()];
+        let mut bitangents = vec![Vector3::zero(); self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| &self.vertices[triangle[i] as usize]);
+            let edge1 = Vector3::from(b.position) - Vector3::from(a.position);
+            let edge2 = Vector3::from(c.position) - Vector3::from(a.position);
+            let delta1 = Vector2::from(b.tex_coords) - Vector2::from(a.tex_coords);
+            let delta2 = Vector2::from(c.tex_coords) - Vector2::from(a.tex_coords);
+            let determinant = delta1.x * delta2.y - delta2.x * delta1.y;
+            if determinant.abs() < f32::EPSILON {
+                continue;
+            }
+            let tangent = (edge1 * delta2.y - edge2 * delta1.y) / determinant;
+            let bitangent = (edge2 * delta1.x - edge1 * delta2.x) / determinant;
+            for index in triangle {
+                tangents[*index as usize] += tangent;
+                bitangents[*index as usize] += bitangent;
+            }
+        }
+        for (vertex, (tangent, bitangent)) in self
+            .vertices
+            .iter_mut()
+            .zip(tangents.into_iter().zip(bitangents))
+        {
+            let normal = Vector3::from(vertex.normal);
+            // gram-schmidt, the tangent has to be perpendicular to the normal
+            let tangent = tangent - normal * normal.dot(tangent);
+            if tangent.magnitude2() < f32::EPSILON {
+                vertex.tangent = [0.0, 0.0, 0.0, 1.0];
+                continue;
+            }
+            let tangent = tangent.normalize();
+            let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            vertex.tangent = [tangent.x, tangent.y, tangent.z, handedness];
+        }
+    }
+
+    /// a single mesh at the root of the model
+    pub fn to_decoded_mesh(&self) -> DecodedMesh {
+        DecodedMesh::new(
+            self.vertices.clone(),
+            self.indices.clone(),
+            0,
+            cgmath::Matrix4::identity(),
+            0,
+            "root".to_string(),
+        )
+    }
+
+    /// a model with just this mesh, it can be submitted with AssetServer::insert_model
+    pub fn into_decoded_model(self) -> DecodedModel {
+        DecodedModel {
+            meshes: vec![self.to_decoded_mesh()],
+            materials: HashMap::from([(0, self.material)]),
+            skeleton: Skeleton::default(),
+            animations: Vec::new(),
+        }
+    }
+
+    /// uploads the mesh and its material, has to run on the thread that owns the queue
+    pub fn build(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        opaque_pipeline: &OpaquePipeline,
+    ) -> MyMesh {
+        let material_bind_group =
+            opaque_pipeline.create_material_bind_group(device, queue, &self.material);
+        self.to_decoded_mesh()
+            .upload(device, Arc::new(material_bind_group))
+    }
+
+    /// a cube with the given edge length, every face has its own vertices and the whole texture
+    pub fn cube(size: f32) -> Self {
+        // (normal, right and down direction of the texture on the face)
+        let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+            ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+            ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+            ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+            ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+            ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+        ];
+        let mut builder = Self::new();
+        for (normal, right, down) in faces {
+            let [normal, right, down] = [normal, right, down].map(Vector3::from);
+            builder.add_grid(1, 1, |column, row| {
+                let s = column as f32 * 2.0 - 1.0;
+                let t = row as f32 * 2.0 - 1.0;
+                let position = (normal + right * s + down * t) * (size / 2.0);
+                (position.into(), normal.into())
+            });
+        }
+        builder.generate_tangents();
+        builder
+    }
+
+    /// a sphere with segments columns around the y axis and rings rows from the top to the bottom pole
+    pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Self {
+        let segments = segments.max(3);
+        let rings = rings.max(2);
+        let mut builder = Self::new();
+        builder.add_grid(segments, rings, |column, row| {
+            let normal = sphere_normal(
+                column as f32 / segments as f32 * TAU,
+                row as f32 / rings as f32 * PI,
+            );
+            ((normal * radius).into(), normal.into())
+        });
+        builder.generate_tangents();
+        builder
+    }
+
+    /// a plane in the xz plane facing up, split into subdivisions x subdivisions quads
+    pub fn plane(width: f32, depth: f32, subdivisions: u32) -> Self {
+        let subdivisions = subdivisions.max(1);
+        let mut builder = Self::new();
+        builder.add_grid(subdivisions, subdivisions, |column, row| {
+            let x = (column as f32 / subdivisions as f32 - 0.5) * width;
+            let z = (row as f32 / subdivisions as f32 - 0.5) * depth;
+            ([x, 0.0, z], [0.0, 1.0, 0.0])
+        });
+        builder.generate_tangents();
+        builder
+    }
+
+    /// a closed cylinder along the y axis
+    pub fn cylinder(radius: f32, height: f32, segments: u32) -> Self {
+        let segments = segments.max(3);
+        let mut builder = Self::new();
+        builder.add_grid(segments, 1, |column, row| {
+            let normal = sphere_normal(column as f32 / segments as f32 * TAU, PI / 2.0);
+            let y = height * (0.5 - row as f32);
+            ([normal.x * radius, y, normal.z * radius], normal.into())
+        });
+        builder.add_disc(radius, height / 2.0, segments, true);
+        builder.add_disc(radius, -height / 2.0, segments, false);
+        builder.generate_tangents();
+        builder
+    }
+
+    /// a cylinder with hemispheres at both ends, height is the distance between their centers.
+    /// rings is the number of rows of each hemisphere
+    pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Self {
+        let segments = segments.max(3);
+        let rings = rings.max(1);
+        let mut builder = Self::new();
+        // rows 0 to rings are the top hemisphere, the next rings + 1 rows the bottom one,
+        // the row between them is the cylinder
+        builder.add_grid(segments, 2 * rings + 1, |column, row| {
+            let (polar_angle, y) = if row <= rings {
+                (row as f32 / rings as f32 * PI / 2.0, height / 2.0)
+            } else {
+                let row = row - rings - 1;
+                (
+                    PI / 2.0 + row as f32 / rings as f32 * PI / 2.0,
+                    -height / 2.0,
+                )
+            };
+            let normal = sphere_normal(column as f32 / segments as f32 * TAU, polar_angle);
+            let position = normal * radius + Vector3::new(0.0, y, 0.0);
+            (position.into(), normal.into())
+        });
+        builder.generate_tangents();
+        builder
+    }
+
+    /// a torus around the y axis, major_radius is the distance from the center to the middle of the tube
+    pub fn torus(
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: u32,
+        minor_segments: u32,
+    ) -> Self {
+        let major_segments = major_segments.max(3);
+        let minor_segments = minor_segments.max(3);
+        let mut builder = Self::new();
+        builder.add_grid(major_segments, minor_segments, |column, row| {
+            let (major_sin, major_cos) = (column as f32 / major_segments as f32 * TAU).sin_cos();
+            // starts at the outer equator and goes down first
+            let (minor_sin, minor_cos) = (row as f32 / minor_segments as f32 * TAU).sin_cos();
+            let normal = Vector3::new(minor_cos * major_sin, -minor_sin, minor_cos * major_cos);
+            let center = Vector3::new(major_sin, 0.0, major_cos) * major_radius;
+            ((center + normal * minor_radius).into(), normal.into())
+        });
+        builder.generate_tangents();
+        builder
+    }
+}
+
+impl Default for MeshBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the point of the unit sphere at the given angle around the y axis (0 is +z) and angle from +y
+fn sphere_normal(azimuth: f32, polar_angle: f32) -> Vector3<f32> {
+    let (azimuth_sin, azimuth_cos) = azimuth.sin_cos();
+    let (polar_sin, polar_cos) = polar_angle.sin_cos();
+    Vector3::new(polar_sin * azimuth_sin, polar_cos, polar_sin * azimuth_cos)
+}
+
+// a generated model, submitted as ModelMeta::Primitive. the parameters are the ones of the MeshBuilder functions
+#[derive(Debug, Clone, Copy)]
+pub enum Primitive {
+    Cube {
+        size: f32,
+    },
+    UvSphere {
+        radius: f32,
+        segments: u32,
+        rings: u32,
+    },
+    Plane {
+        width: f32,
+        depth: f32,
+        subdivisions: u32,
+    },
+    Cylinder {
+        radius: f32,
+        height: f32,
+        segments: u32,
+    },
+    Capsule {
+        radius: f32,
+        height: f32,
+        segments: u32,
+        rings: u32,
+    },
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: u32,
+        minor_segments: u32,
+    },
+}
+
+impl Primitive {
+    /// the mesh of the primitive, with the default material
+    pub fn mesh_builder(&self) -> MeshBuilder {
+        match *self {
+            Primitive::Cube { size } => MeshBuilder::cube(size),
+            Primitive::UvSphere {
+                radius,
+                segments,
+                rings,
+            } => MeshBuilder::uv_sphere(radius, segments, rings),
+            Primitive::Plane {
+                width,
+                depth,
+                subdivisions,
+            } => MeshBuilder::plane(width, depth, subdivisions),
+            Primitive::Cylinder {
+                radius,
+                height,
+                segments,
+            } => MeshBuilder::cylinder(radius, height, segments),
+            Primitive::Capsule {
+                radius,
+                height,
+                segments,
+                rings,
+            } => MeshBuilder::capsule(radius, height, segments, rings),
+            Primitive::Torus {
+                major_radius,
+                minor_radius,
+                major_segments,
+                minor_segments,
+            } => MeshBuilder::torus(major_radius, minor_radius, major_segments, minor_segments),
+        }
+    }
+
+    // the kind and parameters as bits, primitives are cache keys so the sizes are compared bit for bit
+    fn key(&self) -> Vec<u32> {
+        match *self {
+            Primitive::Cube { size } => vec![0, size.to_bits()],
+            Primitive::UvSphere {
+                radius,
+                segments,
+                rings,
+            } => vec![1, radius.to_bits(), segments, rings],
+            Primitive::Plane {
+                width,
+                depth,
+                subdivisions,
+            } => vec![2, width.to_bits(), depth.to_bits(), subdivisions],
+            Primitive::Cylinder {
+                radius,
+                height,
+                segments,
+            } => vec![3, radius.to_bits(), height.to_bits(), segments],
+            Primitive::Capsule {
+                radius,
+                height,
+                segments,
+                rings,
+            } => vec![4, radius.to_bits(), height.to_bits(), segments, rings],
+            Primitive::Torus {
+                major_radius,
+                minor_radius,
+                major_segments,
+                minor_segments,
+            } => vec![
+                5,
+                major_radius.to_bits(),
+                minor_radius.to_bits(),
+                major_segments,
+                minor_segments,
+            ],
+        }
+    }
+}
+
+impl PartialEq for Primitive {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Primitive {}
+
+impl Hash for Primitive {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
@@ -0,0 +1,594 @@
+// on-disk cache of decoded models, so large files only go through russimp once.
+// ModelMeta::decode_model stores every imported model as one file in the cache directory and memory-maps it on
+// later runs. an entry is only used if it was written for the same path, import settings, modification time
+// of the source file and LOADER_VERSION, anything else is a miss and the model is imported again.
+// textures in separate files next to the model are stored in the entry but are not part of the key, so after
+// editing one of them the cache directory has to be deleted (or the model file touched) to see the change
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::UNIX_EPOCH,
+};
+
+use bytemuck::Pod;
+use cgmath::Quaternion;
+use image::ImageBuffer;
+use lazy_static::lazy_static;
+use memmap2::Mmap;
+
+use crate::{
+    animation::{
+        AnimationClip, Joint, Keyframes, MorphChannel, NodeChannel, Skeleton, SkeletonNode,
+    },
+    model_data::{DecodedMaterial, DecodedMesh, DecodedModel, MeshIndices, MorphTarget},
+    model_meta::ModelMeta,
+};
+
+// bump whenever decode_model or the layout of the cache files changes, old entries are then ignored
+pub const LOADER_VERSION: u32 = 1;
+const MAGIC: &[u8; 4] = b"LWMC";
+
+lazy_static! {
+    static ref CACHE_DIR: RwLock<Option<PathBuf>> = RwLock::new(Some(PathBuf::from(".mesh_cache")));
+}
+
+/// sets the directory of the cache files, None turns the cache off
+pub fn set_cache_dir(cache_dir: Option<PathBuf>) {
+    *CACHE_DIR.write().unwrap() = cache_dir;
+}
+
+pub fn cache_dir() -> Option<PathBuf> {
+    CACHE_DIR.read().unwrap().clone()
+}
+
+// everything an entry has to match, stored at the start of the file
+#[derive(Hash, PartialEq)]
+struct CacheKey {
+    path: String,
+    max_vertices_per_mesh: Option<u32>,
+    modified: u64,
+    loader_version: u32,
+}
+
+impl CacheKey {
+    // None if the source file can not be read, it is imported (and fails) as usual then.
+    // only model files are cached, the other models are cheap to rebuild
+    fn new(model_meta: &ModelMeta) -> Option<Self> {
+        let ModelMeta::File {
+            path,
+            max_vertices_per_mesh,
+        } = model_meta
+        else {
+            return None;
+        };
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_nanos() as u64;
+        Some(Self {
+            path: path.clone(),
+            max_vertices_per_mesh: *max_vertices_per_mesh,
+            modified,
+            loader_version: LOADER_VERSION,
+        })
+    }
+    fn file_path(&self, cache_dir: &Path) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        cache_dir.join(format!("{:016x}.bin", hasher.finish()))
+    }
+    fn write(&self, writer: &mut CacheWriter) {
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.u32(self.loader_version);
+        writer.string(&self.path);
+        writer.u32(self.max_vertices_per_mesh.unwrap_or(0));
+        writer.u64(self.modified);
+    }
+    fn read(reader: &mut CacheReader) -> Option<Self> {
+        if reader.take(MAGIC.len())? != MAGIC {
+            return None;
+        }
+        let loader_version = reader.u32()?;
+        let path = reader.string()?;
+        let max_vertices_per_mesh = Some(reader.u32()?).filter(|max_vertices| *max_vertices != 0);
+        let modified = reader.u64()?;
+        Some(Self {
+            path,
+            max_vertices_per_mesh,
+            modified,
+            loader_version,
+        })
+    }
+}
+
+/// the cached model, or None if there is no valid entry for it
+pub fn load(model_meta: &ModelMeta) -> Option<DecodedModel> {
+    let cache_dir = cache_dir()?;
+    let key = CacheKey::new(model_meta)?;
+    let file = File::open(key.file_path(&cache_dir)).ok()?;
+    // the file is only read, and replaced by a rename rather than written in place
+    let mmap = unsafe { Mmap::map(&file) }.ok()?;
+    let mut reader = CacheReader {
+        bytes: &mmap,
+        offset: 0,
+    };
+    if CacheKey::read(&mut reader)? != key {
+        return None;
+    }
+    let decoded_model = read_model(&mut reader);
+    if decoded_model.is_none() {
+        log::warn!(
+            "{}: the mesh cache entry is corrupt, importing the model again",
+            model_meta.name()
+        );
+    }
+    decoded_model
+}
+
+/// writes the model to the cache, failures are only logged
+pub fn store(model_meta: &ModelMeta, decoded_model: &DecodedModel) {
+    let (Some(cache_dir), Some(key)) = (cache_dir(), CacheKey::new(model_meta)) else {
+        return;
+    };
+    let mut writer = CacheWriter { bytes: Vec::new() };
+    key.write(&mut writer);
+    write_model(&mut writer, decoded_model);
+    let file_path = key.file_path(&cache_dir);
+    // written next to the entry and renamed, so a reader never sees half a file
+    let temp_path = file_path.with_extension("tmp");
+    let result = std::fs::create_dir_all(&cache_dir)
+        .and_then(|_| File::create(&temp_path))
+        .and_then(|mut file| file.write_all(&writer.bytes))
+        .and_then(|_| std::fs::rename(&temp_path, &file_path));
+    if let Err(error) = result {
+        log::warn!(
+            "{}: could not write the mesh cache entry {}: {}",
+            model_meta.name(),
+            file_path.display(),
+            error
+        );
+    }
+}
+
+struct CacheWriter {
+    bytes: Vec<u8>,
+}
+
+impl CacheWriter {
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn usize(&mut self, value: usize) {
+        self.u32(value as u32);
+    }
+    // length followed by the raw values
+    fn slice<T: Pod>(&mut self, values: &[T]) {
+        self.usize(values.len());
+        self.bytes.extend_from_slice(bytemuck::cast_slice(values));
+    }
+    fn string(&mut self, value: &str) {
+        self.slice(value.as_bytes());
+    }
+    fn matrix(&mut self, matrix: &cgmath::Matrix4<f32>) {
+        let matrix: [[f32; 4]; 4] = (*matrix).into();
+        self.bytes.extend_from_slice(bytemuck::cast_slice(&matrix));
+    }
+    fn image(&mut self, image: &ImageBuffer<image::Rgba<u8>, Vec<u8>>) {
+        self.u32(image.width());
+        self.u32(image.height());
+        self.slice(image.as_raw());
+    }
+    fn keyframes<T, P: Pod>(&mut self, keyframes: &Keyframes<T>, to_pod: impl Fn(&T) -> P) {
+        self.slice(&keyframes.times);
+        self.slice(&keyframes.values.iter().map(to_pod).collect::<Vec<_>>());
+    }
+}
+
+// every read returns None once the data runs out
+struct CacheReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> CacheReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+    fn f32(&mut self) -> Option<f32> {
+        Some(f32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+    fn usize(&mut self) -> Option<usize> {
+        self.u32().map(|value| value as usize)
+    }
+    // the mapped file has no alignment guarantees, so the values are copied out
+    fn slice<T: Pod>(&mut self) -> Option<Vec<T>> {
+        let len = self.usize()?;
+        let bytes = self.take(len.checked_mul(size_of::<T>())?)?;
+        Some(bytemuck::pod_collect_to_vec(bytes))
+    }
+    fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.slice()?).ok()
+    }
+    fn matrix(&mut self) -> Option<cgmath::Matrix4<f32>> {
+        let matrix: [[f32; 4]; 4] = bytemuck::pod_read_unaligned(self.take(64)?);
+        Some(matrix.into())
+    }
+    fn image(&mut self) -> Option<ImageBuffer<image::Rgba<u8>, Vec<u8>>> {
+        let width = self.u32()?;
+        let height = self.u32()?;
+        ImageBuffer::from_raw(width, height, self.slice()?)
+    }
+    fn keyframes<T, P: Pod>(&mut self, from_pod: impl Fn(P) -> T) -> Option<Keyframes<T>> {
+        let times = self.slice()?;
+        let values = self.slice::<P>()?;
+        if values.len() != times.len() {
+            return None;
+        }
+        Some(Keyframes {
+            times,
+            values: values.into_iter().map(from_pod).collect(),
+        })
+    }
+    // count followed by the items
+    fn list<T>(&mut self, read_item: impl Fn(&mut Self) -> Option<T>) -> Option<Vec<T>> {
+        let len = self.usize()?;
+        (0..len).map(|_| read_item(self)).collect()
+    }
+}
+
+fn write_model(writer: &mut CacheWriter, decoded_model: &DecodedModel) {
+    writer.usize(decoded_model.meshes.len());
+    for mesh in decoded_model.meshes.iter() {
+        writer.slice(&mesh.vertices);
+        match &mesh.skin {
+            Some(skin) => {
+                writer.u32(1);
+                writer.slice(skin);
+            }
+            None => writer.u32(0),
+        }
+        writer.usize(mesh.morph_targets.len());
+        for morph_target in mesh.morph_targets.iter() {
+            writer.string(&morph_target.name);
+            writer.f32(morph_target.default_weight);
+            writer.slice(&morph_target.position_deltas);
+            writer.slice(&morph_target.normal_deltas);
+            writer.slice(&morph_target.tangent_deltas);
+        }
+        match &mesh.indices {
+            MeshIndices::U16(indices) => {
+                writer.u32(16);
+                writer.slice(indices);
+            }
+            MeshIndices::U32(indices) => {
+                writer.u32(32);
+                writer.slice(indices);
+            }
+        }
+        writer.u32(mesh.material_index);
+        writer.matrix(&mesh.transform);
+        writer.usize(mesh.node_index);
+        writer.string(&mesh.node_name);
+    }
+
+    writer.usize(decoded_model.materials.len());
+    for (material_index, material) in decoded_model.materials.iter() {
+        writer.u32(*material_index);
+        writer.slice(&material.base_color_factor);
+        writer.image(&material.base_color_image);
+        writer.f32(material.metallic_factor);
+        writer.f32(material.roughness_factor);
+        writer.image(&material.metallic_roughness_image);
+        writer.image(&material.normal_image);
+        writer.image(&material.occlusion_image);
+        writer.slice(&material.emissive_factor);
+        writer.image(&material.emissive_image);
+        writer.f32(material.opacity);
+    }
+
+    let skeleton = &decoded_model.skeleton;
+    writer.usize(skeleton.nodes.len());
+    for node in skeleton.nodes.iter() {
+        writer.string(&node.name);
+        // u32::MAX for the root
+        writer.u32(node.parent.map_or(u32::MAX, |parent| parent as u32));
+        writer.matrix(&node.local_transform);
+    }
+    writer.usize(skeleton.joints.len());
+    for joint in skeleton.joints.iter() {
+        writer.usize(joint.node);
+        writer.matrix(&joint.inverse_bind_matrix);
+    }
+
+    writer.usize(decoded_model.animations.len());
+    for animation in decoded_model.animations.iter() {
+        writer.string(&animation.name);
+        writer.f32(animation.duration);
+        writer.usize(animation.channels.len());
+        for channel in animation.channels.iter() {
+            writer.usize(channel.node);
+            writer.keyframes(&channel.translations, |v| [v.x, v.y, v.z]);
+            writer.keyframes(&channel.rotations, |q| [q.v.x, q.v.y, q.v.z, q.s]);
+            writer.keyframes(&channel.scales, |v| [v.x, v.y, v.z]);
+        }
+        writer.usize(animation.morph_channels.len());
+        for channel in animation.morph_channels.iter() {
+            writer.usize(channel.node);
+            writer.slice(&channel.weights.times);
+            for weights in channel.weights.values.iter() {
+                writer.slice(weights);
+            }
+        }
+    }
+}
+
+fn read_model(reader: &mut CacheReader) -> Option<DecodedModel> {
+    let meshes = reader.list(|reader| {
+        let vertices = reader.slice()?;
+        let skin = match reader.u32()? {
+            0 => None,
+            _ => Some(reader.slice()?),
+        };
+        let morph_targets = reader.list(|reader| {
+            Some(MorphTarget {
+                name: reader.string()?,
+                default_weight: reader.f32()?,
+                position_deltas: reader.slice()?,
+                normal_deltas: reader.slice()?,
+                tangent_deltas: reader.slice()?,
+            })
+        })?;
+        let indices = match reader.u32()? {
+            16 => MeshIndices::U16(reader.slice()?),
+            32 => MeshIndices::U32(reader.slice()?),
+            _ => return None,
+        };
+        Some(DecodedMesh {
+            vertices,
+            skin,
+            morph_targets,
+            indices,
+            material_index: reader.u32()?,
+            transform: reader.matrix()?,
+            node_index: reader.usize()?,
+            node_name: reader.string()?,
+            bvh: None,
+        })
+    })?;
+
+    let materials = reader
+        .list(|reader| {
+            let material_index = reader.u32()?;
+            let material = DecodedMaterial {
+                base_color_factor: reader.slice::<f32>()?.try_into().ok()?,
+                base_color_image: reader.image()?,
+                metallic_factor: reader.f32()?,
+                roughness_factor: reader.f32()?,
+                metallic_roughness_image: reader.image()?,
+                normal_image: reader.image()?,
+                occlusion_image: reader.image()?,
+                emissive_factor: reader.slice::<f32>()?.try_into().ok()?,
+                emissive_image: reader.image()?,
+                opacity: reader.f32()?,
+            };
+            Some((material_index, material))
+        })?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    let nodes = reader.list(|reader| {
+        Some(SkeletonNode {
+            name: reader.string()?,
+            parent: Some(reader.usize()?).filter(|parent| *parent != u32::MAX as usize),
+            local_transform: reader.matrix()?,
+        })
+    })?;
+    let joints = reader.list(|reader| {
+        Some(Joint {
+            node: reader.usize()?,
+            inverse_bind_matrix: reader.matrix()?,
+        })
+    })?;
+
+    let animations = reader.list(|reader| {
+        let name = reader.string()?;
+        let duration = reader.f32()?;
+        let channels = reader.list(|reader| {
+            Some(NodeChannel {
+                node: reader.usize()?,
+                translations: reader.keyframes(|v: [f32; 3]| v.into())?,
+                rotations: reader
+                    .keyframes(|q: [f32; 4]| Quaternion::new(q[3], q[0], q[1], q[2]))?,
+                scales: reader.keyframes(|v: [f32; 3]| v.into())?,
+            })
+        })?;
+        let morph_channels = reader.list(|reader| {
+            let node = reader.usize()?;
+            let times = reader.slice::<f32>()?;
+            let values = (0..times.len())
+                .map(|_| reader.slice())
+                .collect::<Option<Vec<_>>>()?;
+            Some(MorphChannel {
+                node,
+                weights: Keyframes { times, values },
+            })
+        })?;
+        Some(AnimationClip {
+            name,
+            duration,
+            channels,
+            morph_channels,
+        })
+    })?;
+
+    // the indices into the node list are trusted from here on
+    let node_count = nodes.len();
+    let in_range = |node: usize| node < node_count;
+    let valid = meshes.iter().all(|mesh| in_range(mesh.node_index))
+        && joints.iter().all(|joint| in_range(joint.node))
+        && animations.iter().all(|animation| {
+            animation
+                .channels
+                .iter()
+                .all(|channel| in_range(channel.node))
+                && animation
+                    .morph_channels
+                    .iter()
+                    .all(|channel| in_range(channel.node))
+        })
+        && meshes.iter().all(|mesh| {
+            materials.contains_key(&mesh.material_index) && valid_mesh(mesh, joints.len())
+        });
+    if !valid {
+        return None;
+    }
+    Some(DecodedModel {
+        meshes,
+        materials,
+        skeleton: Skeleton { nodes, joints },
+        animations,
+    })
+}
+
+// the per vertex data of a mesh has one entry for every vertex and only refers to vertices and joints that
+// exist. a truncated entry could pass the length prefixes and would otherwise panic in upload or build_bvhs,
+// or read out of bounds on the gpu
+fn valid_mesh(mesh: &DecodedMesh, joint_count: usize) -> bool {
+    let vertex_count = mesh.vertices.len();
+    let valid_skin = mesh.skin.as_ref().is_none_or(|skin| {
+        skin.len() == vertex_count
+            && skin.iter().all(|skin_vertex| {
+                skin_vertex
+                    .joints
+                    .iter()
+                    .all(|joint| (*joint as usize) < joint_count)
+            })
+    });
+    let valid_morph_targets = mesh.morph_targets.iter().all(|morph_target| {
+        morph_target.position_deltas.len() == vertex_count
+            && morph_target.normal_deltas.len() == vertex_count
+            && morph_target.tangent_deltas.len() == vertex_count
+    });
+    let valid_indices =
+        (0..mesh.indices.len()).all(|i| (mesh.indices.get(i) as usize) < vertex_count);
+    valid_skin && valid_morph_targets && valid_indices
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::SquareMatrix;
+
+    use super::*;
+    use crate::{mesh_builder::MeshBuilder, vertex::SkinVertex};
+
+    // a skinned cube with one node and one joint
+    fn cube_model() -> DecodedModel {
+        let mut decoded_model = MeshBuilder::cube(1.0).into_decoded_model();
+        decoded_model.skeleton.nodes.push(SkeletonNode {
+            name: "root".to_string(),
+            parent: None,
+            local_transform: cgmath::Matrix4::identity(),
+        });
+        decoded_model.skeleton.joints.push(Joint {
+            node: 0,
+            inverse_bind_matrix: cgmath::Matrix4::identity(),
+        });
+        let mesh = &mut decoded_model.meshes[0];
+        let skin_vertex = SkinVertex {
+            joints: [0; 4],
+            weights: [1.0, 0.0, 0.0, 0.0],
+        };
+        mesh.skin = Some(vec![skin_vertex; mesh.vertices.len()]);
+        let deltas = vec![[0.0; 3]; mesh.vertices.len()];
+        mesh.morph_targets.push(MorphTarget {
+            name: "target".to_string(),
+            default_weight: 0.0,
+            position_deltas: deltas.clone(),
+            normal_deltas: deltas.clone(),
+            tangent_deltas: deltas,
+        });
+        decoded_model
+    }
+
+    // writes the model like store and reads it back like load
+    fn round_trip(decoded_model: &DecodedModel) -> Option<DecodedModel> {
+        let mut writer = CacheWriter { bytes: Vec::new() };
+        write_model(&mut writer, decoded_model);
+        let mut reader = CacheReader {
+            bytes: &writer.bytes,
+            offset: 0,
+        };
+        read_model(&mut reader)
+    }
+
+    #[test]
+    fn valid_entry_is_read() {
+        let decoded_model = round_trip(&cube_model()).unwrap();
+        let mesh = &decoded_model.meshes[0];
+        assert_eq!(mesh.vertices.len(), cube_model().meshes[0].vertices.len());
+        assert_eq!(mesh.indices.len(), cube_model().meshes[0].indices.len());
+        assert_eq!(mesh.morph_targets.len(), 1);
+    }
+
+    #[test]
+    fn truncated_entry_is_rejected() {
+        let mut writer = CacheWriter { bytes: Vec::new() };
+        write_model(&mut writer, &cube_model());
+        let len = writer.bytes.len();
+        let mut reader = CacheReader {
+            bytes: &writer.bytes[..len / 2],
+            offset: 0,
+        };
+        assert!(read_model(&mut reader).is_none());
+    }
+
+    #[test]
+    fn index_out_of_range_is_rejected() {
+        let mut decoded_model = cube_model();
+        let mesh = &mut decoded_model.meshes[0];
+        let mut indices = (0..mesh.indices.len())
+            .map(|i| mesh.indices.get(i))
+            .collect::<Vec<_>>();
+        indices[4] = mesh.vertices.len() as u32;
+        mesh.indices = MeshIndices::new(indices, mesh.vertices.len());
+        assert!(round_trip(&decoded_model).is_none());
+    }
+
+    #[test]
+    fn skin_mismatch_is_rejected() {
+        let mut decoded_model = cube_model();
+        decoded_model.meshes[0].skin.as_mut().unwrap()[2].joints[1] = 1;
+        assert!(round_trip(&decoded_model).is_none());
+        let mut decoded_model = cube_model();
+        decoded_model.meshes[0].skin.as_mut().unwrap().pop();
+        assert!(round_trip(&decoded_model).is_none());
+    }
+
+    #[test]
+    fn morph_target_mismatch_is_rejected() {
+        let mut decoded_model = cube_model();
+        decoded_model.meshes[0].morph_targets[0].normal_deltas.pop();
+        assert!(round_trip(&decoded_model).is_none());
+    }
+}
@@ -1,26 +1,64 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
-use wgpu::BindGroup;
+use cgmath::Transform;
+use image::{ImageBuffer, Rgba};
+use wgpu::{BindGroup, util::DeviceExt};
 
-use crate::model_meta::ModelMeta;
+use crate::{
+    animation::{AnimationClip, Skeleton},
+    bounds::{Aabb, BoundingSphere},
+    bvh::TriangleBvh,
+    model_meta::ModelMeta,
+    my_texture::MyTexture,
+    opaque_pipeline::OpaquePipeline,
+    vertex::{SkinVertex, Vertex},
+};
 
 #[derive(Debug, Clone)]
 pub struct MyMesh {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_indices: u32,
+    pub index_format: wgpu::IndexFormat,
     pub material_bind_group: Arc<BindGroup>,
+    // joints and weights, only skinned meshes have one
+    pub skin_buffer: Option<wgpu::Buffer>,
+    // only meshes with morph targets have one, see MorphPipeline
+    pub morph: Option<Arc<MeshMorph>>,
+    // accumulated transform of the node the mesh belongs to, relative to the model.
+    // identity for skinned meshes, the joint matrices already move them into the space of the model
+    pub transform: cgmath::Matrix4<f32>,
+    // index into Skeleton::nodes
+    pub node_index: usize,
+    pub node_name: String,
+    // around the vertices, in the space of the mesh
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+    // the triangles for picking, see DecodedMesh::bvh
+    pub bvh: Option<Arc<TriangleBvh>>,
+}
+
+// the morph targets of an uploaded mesh
+#[derive(Debug)]
+pub struct MeshMorph {
+    // position, normal and tangent offsets (9 floats) of every vertex, one target after the other
+    pub target_buffer: wgpu::Buffer,
+    pub target_names: Vec<String>,
+    pub default_weights: Vec<f32>,
+    pub vertex_count: u32,
+}
+
+impl MeshMorph {
+    /// the index of the target with this name
+    pub fn target_index(&self, name: &str) -> Option<usize> {
+        self.target_names
+            .iter()
+            .position(|target_name| target_name == name)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct MaterialBindGroup(BindGroup);
-// pub color_diffuse: cgmath::Vector4<f32>,
-// pub textures: HashMap<TextureType, Arc<MyTexture>>,
-// pub metallic: f32,
-// pub roughness: f32,
-// pub shininess: f32,
-// pub emmisive: cgmath::Vector4<f32>,
-// pub opacity: f32,
 
 // This is a key to a mesh to be rendered
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -32,4 +70,402 @@ pub struct MeshMeta {
 pub struct ModelData {
     pub opaque_meshes: Vec<Arc<MyMesh>>,
     pub transparent_meshes: Vec<Arc<MyMesh>>,
+    pub skeleton: Arc<Skeleton>,
+    pub animations: Vec<Arc<AnimationClip>>,
+    // in the space of the model, around the meshes in their rest pose
+    pub bounding_sphere: BoundingSphere,
+}
+
+impl ModelData {
+    /// the meshes of the node with this name, a node can have opaque and transparent meshes
+    pub fn node_meshes(&self, node_name: &str) -> Vec<Arc<MyMesh>> {
+        self.opaque_meshes
+            .iter()
+            .chain(self.transparent_meshes.iter())
+            .filter(|mesh| mesh.node_name == node_name)
+            .cloned()
+            .collect()
+    }
+    /// the opaque meshes followed by the transparent ones, mesh indices count in this order
+    pub fn meshes(&self) -> impl Iterator<Item = &Arc<MyMesh>> {
+        self.opaque_meshes
+            .iter()
+            .chain(self.transparent_meshes.iter())
+    }
+    pub fn node_names(&self) -> Vec<&str> {
+        let mut node_names = self
+            .opaque_meshes
+            .iter()
+            .chain(self.transparent_meshes.iter())
+            .map(|mesh| mesh.node_name.as_str())
+            .collect::<Vec<_>>();
+        node_names.sort();
+        node_names.dedup();
+        node_names
+    }
+}
+
+// 16 bit indices halve the size of the index buffer, so they are used whenever the vertices fit
+pub enum MeshIndices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl MeshIndices {
+    /// picks the smallest index format that can address vertex_count vertices
+    pub fn new(indices: Vec<u32>, vertex_count: usize) -> Self {
+        if vertex_count <= u16::MAX as usize + 1 {
+            MeshIndices::U16(indices.into_iter().map(|index| index as u16).collect())
+        } else {
+            MeshIndices::U32(indices)
+        }
+    }
+    pub fn len(&self) -> usize {
+        match self {
+            MeshIndices::U16(indices) => indices.len(),
+            MeshIndices::U32(indices) => indices.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn format(&self) -> wgpu::IndexFormat {
+        match self {
+            MeshIndices::U16(_) => wgpu::IndexFormat::Uint16,
+            MeshIndices::U32(_) => wgpu::IndexFormat::Uint32,
+        }
+    }
+    pub fn get(&self, i: usize) -> u32 {
+        match self {
+            MeshIndices::U16(indices) => indices[i] as u32,
+            MeshIndices::U32(indices) => indices[i],
+        }
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            MeshIndices::U16(indices) => bytemuck::cast_slice(indices),
+            MeshIndices::U32(indices) => bytemuck::cast_slice(indices),
+        }
+    }
+}
+
+// offsets of one morph target from the base vertices of its mesh, one entry per vertex
+#[derive(Debug, Clone)]
+pub struct MorphTarget {
+    pub name: String,
+    // used when neither the instance nor an animation sets the weight
+    pub default_weight: f32,
+    pub position_deltas: Vec<[f32; 3]>,
+    pub normal_deltas: Vec<[f32; 3]>,
+    pub tangent_deltas: Vec<[f32; 3]>,
+}
+
+// cpu side copy of a model, produced by ModelMeta::decode_model and turned into ModelData by upload
+pub struct DecodedMesh {
+    pub vertices: Vec<Vertex>,
+    // one entry per vertex
+    pub skin: Option<Vec<SkinVertex>>,
+    pub morph_targets: Vec<MorphTarget>,
+    pub indices: MeshIndices,
+    pub material_index: u32,
+    pub transform: cgmath::Matrix4<f32>,
+    pub node_index: usize,
+    pub node_name: String,
+    // built by DecodedModel::build_bvhs, not stored in the mesh cache
+    pub bvh: Option<Arc<TriangleBvh>>,
+}
+
+impl DecodedMesh {
+    pub fn new(
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+        material_index: u32,
+        transform: cgmath::Matrix4<f32>,
+        node_index: usize,
+        node_name: String,
+    ) -> Self {
+        let indices = MeshIndices::new(indices, vertices.len());
+        Self {
+            vertices,
+            skin: None,
+            morph_targets: Vec::new(),
+            indices,
+            material_index,
+            transform,
+            node_index,
+            node_name,
+            bvh: None,
+        }
+    }
+    pub fn with_skin(mut self, skin: Option<Vec<SkinVertex>>) -> Self {
+        self.skin = skin;
+        self
+    }
+    pub fn with_morph_targets(mut self, morph_targets: Vec<MorphTarget>) -> Self {
+        self.morph_targets = morph_targets;
+        self
+    }
+
+    // a mesh made of the given vertices of this mesh, indices refer to the position in vertex_map
+    fn sub_mesh(&self, vertex_map: &[u32], indices: Vec<u32>) -> DecodedMesh {
+        let pick = |values: &Vec<[f32; 3]>| {
+            vertex_map
+                .iter()
+                .map(|index| values[*index as usize])
+                .collect()
+        };
+        let vertices = vertex_map
+            .iter()
+            .map(|index| self.vertices[*index as usize])
+            .collect();
+        let skin = self.skin.as_ref().map(|skin| {
+            vertex_map
+                .iter()
+                .map(|index| skin[*index as usize])
+                .collect()
+        });
+        let morph_targets = self
+            .morph_targets
+            .iter()
+            .map(|morph_target| MorphTarget {
+                name: morph_target.name.clone(),
+                default_weight: morph_target.default_weight,
+                position_deltas: pick(&morph_target.position_deltas),
+                normal_deltas: pick(&morph_target.normal_deltas),
+                tangent_deltas: pick(&morph_target.tangent_deltas),
+            })
+            .collect();
+        DecodedMesh::new(
+            vertices,
+            indices,
+            self.material_index,
+            self.transform,
+            self.node_index,
+            self.node_name.clone(),
+        )
+        .with_skin(skin)
+        .with_morph_targets(morph_targets)
+    }
+
+    /// creates the vertex, index, skin and morph target buffers of the mesh
+    pub fn upload(&self, device: &wgpu::Device, material_bind_group: Arc<BindGroup>) -> MyMesh {
+        // the morph pass reads the base vertices as a storage buffer
+        let vertex_usage = match self.morph_targets.is_empty() {
+            true => wgpu::BufferUsages::VERTEX,
+            false => wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+        };
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(&self.vertices),
+            usage: vertex_usage,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: self.indices.as_bytes(),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let skin_buffer = self.skin.as_ref().map(|skin| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Skin Buffer"),
+                contents: bytemuck::cast_slice(skin),
+                usage: wgpu::BufferUsages::VERTEX,
+            })
+        });
+        let morph = (!self.morph_targets.is_empty()).then(|| {
+            let deltas = self
+                .morph_targets
+                .iter()
+                .flat_map(|target| {
+                    (0..self.vertices.len()).flat_map(|i| {
+                        target.position_deltas[i]
+                            .into_iter()
+                            .chain(target.normal_deltas[i])
+                            .chain(target.tangent_deltas[i])
+                    })
+                })
+                .collect::<Vec<f32>>();
+            Arc::new(MeshMorph {
+                target_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Morph Target Buffer"),
+                    contents: bytemuck::cast_slice(&deltas),
+                    usage: wgpu::BufferUsages::STORAGE,
+                }),
+                target_names: self
+                    .morph_targets
+                    .iter()
+                    .map(|target| target.name.clone())
+                    .collect(),
+                default_weights: self
+                    .morph_targets
+                    .iter()
+                    .map(|target| target.default_weight)
+                    .collect(),
+                vertex_count: self.vertices.len() as u32,
+            })
+        });
+        let positions = self
+            .vertices
+            .iter()
+            .map(|vertex| cgmath::Point3::from(vertex.position));
+        MyMesh {
+            vertex_buffer,
+            index_buffer,
+            material_bind_group,
+            num_indices: self.indices.len() as u32,
+            index_format: self.indices.format(),
+            skin_buffer,
+            morph,
+            transform: self.transform,
+            node_index: self.node_index,
+            node_name: self.node_name.clone(),
+            aabb: Aabb::from_points(positions.clone()),
+            bounding_sphere: BoundingSphere::from_points(positions),
+            bvh: self.bvh.clone(),
+        }
+    }
+
+    /// splits the triangle list into meshes of at most max_vertices vertices each.
+    /// triangles are kept in order, vertices shared across a split are duplicated
+    pub fn split(self, max_vertices: usize) -> Vec<DecodedMesh> {
+        // a triangle needs up to three new vertices
+        let max_vertices = usize::max(max_vertices, 3);
+        if self.vertices.len() <= max_vertices {
+            return vec![self];
+        }
+        let mut chunks = Vec::new();
+        // index in self.vertices of every vertex of the current chunk
+        let mut vertex_map = Vec::new();
+        let mut indices = Vec::new();
+        let mut remap: HashMap<u32, u32> = HashMap::new();
+        for triangle in 0..self.indices.len() / 3 {
+            let triangle = [
+                self.indices.get(triangle * 3),
+                self.indices.get(triangle * 3 + 1),
+                self.indices.get(triangle * 3 + 2),
+            ];
+            let new_vertices = triangle
+                .iter()
+                .filter(|index| !remap.contains_key(index))
+                .count();
+            if vertex_map.len() + new_vertices > max_vertices {
+                chunks.push(self.sub_mesh(&vertex_map, std::mem::take(&mut indices)));
+                vertex_map.clear();
+                remap.clear();
+            }
+            for index in triangle {
+                let new_index = *remap.entry(index).or_insert_with(|| {
+                    vertex_map.push(index);
+                    (vertex_map.len() - 1) as u32
+                });
+                indices.push(new_index);
+            }
+        }
+        if !indices.is_empty() {
+            chunks.push(self.sub_mesh(&vertex_map, indices));
+        }
+        chunks
+    }
+}
+
+// glTF metallic-roughness material, the textures are multiplied with the factors in the shader
+pub struct DecodedMaterial {
+    pub base_color_factor: [f32; 4],
+    pub base_color_image: ImageBuffer<Rgba<u8>, Vec<u8>>, // srgb
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub metallic_roughness_image: ImageBuffer<Rgba<u8>, Vec<u8>>, // roughness in g, metallic in b
+    pub normal_image: ImageBuffer<Rgba<u8>, Vec<u8>>,             // tangent space
+    pub occlusion_image: ImageBuffer<Rgba<u8>, Vec<u8>>,          // occlusion in r
+    pub emissive_factor: [f32; 3],
+    pub emissive_image: ImageBuffer<Rgba<u8>, Vec<u8>>, // srgb
+    // a material with opacity < 1 is drawn by the transparent pipeline
+    pub opacity: f32,
+}
+
+impl Default for DecodedMaterial {
+    // 1x1 textures that leave the factors unchanged
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0, 1.0, 1.0, 1.0],
+            base_color_image: MyTexture::solid_image(Rgba([255, 255, 255, 255])),
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_image: MyTexture::solid_image(Rgba([255, 255, 255, 255])),
+            normal_image: MyTexture::solid_image(Rgba([128, 128, 255, 255])),
+            occlusion_image: MyTexture::solid_image(Rgba([255, 255, 255, 255])),
+            emissive_factor: [0.0, 0.0, 0.0],
+            emissive_image: MyTexture::solid_image(Rgba([255, 255, 255, 255])),
+            opacity: 1.0,
+        }
+    }
+}
+
+pub struct DecodedModel {
+    pub meshes: Vec<DecodedMesh>,
+    // every material used by the meshes
+    pub materials: HashMap<u32, DecodedMaterial>,
+    pub skeleton: Skeleton,
+    pub animations: Vec<AnimationClip>,
+}
+
+impl DecodedModel {
+    /// builds the triangle bvh of every mesh that does not have one yet. skinned and morphed meshes are
+    /// skipped, their triangles move away from the positions the bvh would be built from
+    pub fn build_bvhs(&mut self) {
+        for mesh in self.meshes.iter_mut() {
+            if mesh.bvh.is_none() && mesh.skin.is_none() && mesh.morph_targets.is_empty() {
+                mesh.bvh = Some(Arc::new(TriangleBvh::new(&mesh.vertices, &mesh.indices)));
+            }
+        }
+    }
+
+    /// the vertices of skinned meshes are taken as they are, which matches the rest pose of most models
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        let points = self.meshes.iter().flat_map(|mesh| {
+            mesh.vertices.iter().map(|vertex| {
+                mesh.transform
+                    .transform_point(cgmath::Point3::from(vertex.position))
+            })
+        });
+        BoundingSphere::from_points(points)
+    }
+
+    /// creates the gpu buffers and bind groups, has to run on the thread that owns the queue
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        opaque_pipeline: &OpaquePipeline,
+    ) -> ModelData {
+        let material_bind_groups = self
+            .materials
+            .iter()
+            .map(|(material_index, material)| {
+                let material_bind_group =
+                    opaque_pipeline.create_material_bind_group(device, queue, material);
+                (*material_index, Arc::new(material_bind_group))
+            })
+            .collect::<HashMap<_, _>>();
+        let mut opaque_meshes = Vec::new();
+        let mut transparent_meshes = Vec::new();
+        for mesh in self.meshes.iter() {
+            let my_mesh = mesh.upload(device, material_bind_groups[&mesh.material_index].clone());
+            if self.materials[&mesh.material_index].opacity < 1.0 {
+                transparent_meshes.push(Arc::new(my_mesh));
+            } else {
+                opaque_meshes.push(Arc::new(my_mesh));
+            }
+        }
+        ModelData {
+            opaque_meshes,
+            transparent_meshes,
+            skeleton: Arc::new(self.skeleton.clone()),
+            animations: self
+                .animations
+                .iter()
+                .map(|animation| Arc::new(animation.clone()))
+                .collect(),
+            bounding_sphere: self.bounding_sphere(),
+        }
+    }
 }
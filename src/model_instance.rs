@@ -1,16 +1,47 @@
+use std::collections::HashMap;
+
+use crate::animation::AnimationPlayer;
+
 pub struct ModelInstance {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
     pub scale: cgmath::Vector3<f32>,
+    // the key of the player in State::animation_players, the players outlive the instances that are
+    // submitted every frame. None or an unknown key draws skinned meshes in their rest pose
+    pub animation_player: Option<String>,
+    // morph target weights of the meshes of a node, keyed by node name, one weight per target.
+    // they replace the weights of the animation and the defaults of the mesh
+    pub morph_weights: HashMap<String, Vec<f32>>,
 }
 
 impl ModelInstance {
+    pub fn find_animation_player<'a>(
+        &self,
+        animation_players: &'a HashMap<String, AnimationPlayer>,
+    ) -> Option<&'a AnimationPlayer> {
+        animation_players.get(self.animation_player.as_ref()?)
+    }
+    pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
     pub fn to_raw(&self) -> ModelInstanceRaw {
         ModelInstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation)
-                * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z))
-            .into(),
+            model: self.model_matrix().into(),
+            joint_offset: 0,
+        }
+    }
+    /// mesh_transform places the mesh inside the model, see MyMesh::transform.
+    /// joint_offset is the index of the first joint matrix of this instance, only read by skinned meshes
+    pub fn to_raw_with_transform(
+        &self,
+        mesh_transform: &cgmath::Matrix4<f32>,
+        joint_offset: u32,
+    ) -> ModelInstanceRaw {
+        ModelInstanceRaw {
+            model: (self.model_matrix() * mesh_transform).into(),
+            joint_offset,
         }
     }
 }
@@ -20,9 +51,16 @@ impl ModelInstance {
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelInstanceRaw {
     model: [[f32; 4]; 4],
+    joint_offset: u32,
 }
 
 impl ModelInstanceRaw {
+    pub fn position(&self) -> cgmath::Point3<f32> {
+        cgmath::Point3::new(self.model[3][0], self.model[3][1], self.model[3][2])
+    }
+    pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+        self.model.into()
+    }
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
@@ -56,6 +94,11 @@ impl ModelInstanceRaw {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Uint32,
+                },
             ],
         }
     }
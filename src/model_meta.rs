@@ -1,190 +1,781 @@
-use std::{collections::HashMap, io::Cursor, sync::Arc};
+use std::{collections::HashMap, ffi::CString, io::Cursor, path::Path};
 
-use image::{ImageBuffer, ImageReader};
+use cgmath::{InnerSpace, SquareMatrix};
+use image::{ImageBuffer, ImageReader, Rgba};
 use russimp::{
-    material::{DataContent, Material, PropertyTypeInfo, TextureType},
+    Matrix4x4,
+    animation::{Animation, VectorKey},
+    material::{DataContent, Material, MaterialProperty, PropertyTypeInfo, Texture, TextureType},
+    mesh::Mesh,
+    node::Node,
     scene::{PostProcess, Scene},
+    sys,
 };
-use wgpu::{BindGroup, util::DeviceExt};
 
 use crate::{
-    model_data::{ModelData, MyMesh},
+    animation::{
+        AnimationClip, Joint, Keyframes, MAX_JOINT_INFLUENCES, MorphChannel, NodeChannel, Skeleton,
+        SkeletonNode,
+    },
+    asset_error::AssetError,
+    mesh_builder::{MeshBuilder, Primitive},
+    mesh_cache,
+    model_data::{DecodedMaterial, DecodedMesh, DecodedModel, ModelData, MorphTarget},
+    my_texture::MyTexture,
     opaque_pipeline::OpaquePipeline,
-    vertex::Vertex,
+    vertex::{SkinVertex, Vertex},
 };
 
+// what the asset server loads a model from, and the key it is cached under
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
-pub struct ModelMeta {
-    pub path: String,
+pub enum ModelMeta {
+    // a model file imported through russimp
+    File {
+        path: String,
+        // meshes with more vertices are split into several meshes, None keeps every mesh whole
+        max_vertices_per_mesh: Option<u32>,
+    },
+    // generated on the worker threads, see MeshBuilder
+    Primitive(Primitive),
+    // built at runtime and registered with AssetServer::insert_model under this name
+    Procedural(String),
 }
 
 impl ModelMeta {
+    /// a model file
     pub fn new(path: String) -> Self {
-        Self { path }
+        ModelMeta::File {
+            path,
+            max_vertices_per_mesh: None,
+        }
+    }
+    /// only applies to model files
+    pub fn with_max_vertices_per_mesh(mut self, max_vertices_per_mesh: u32) -> Self {
+        if let ModelMeta::File {
+            max_vertices_per_mesh: max_vertices,
+            ..
+        } = &mut self
+        {
+            *max_vertices = Some(max_vertices_per_mesh);
+        }
+        self
+    }
+    /// the path of a model file, or a description of the other models for log messages
+    pub fn name(&self) -> String {
+        match self {
+            ModelMeta::File { path, .. } => path.clone(),
+            ModelMeta::Primitive(primitive) => format!("{:?}", primitive),
+            ModelMeta::Procedural(name) => name.clone(),
+        }
+    }
+    /// the cpu half of load_model: imports the scene and decodes the textures without touching the gpu,
+    /// so it can run on a worker thread. model files use the mesh cache when it has a valid entry for them.
+    /// the picking bvhs are built here as well, they are not cached
+    pub fn decode_model(&self) -> Result<DecodedModel, AssetError> {
+        let mut decoded_model = self.decode_meshes()?;
+        decoded_model.build_bvhs();
+        Ok(decoded_model)
     }
+
+    // the meshes, materials and animations, from the cache if possible
+    fn decode_meshes(&self) -> Result<DecodedModel, AssetError> {
+        match self {
+            ModelMeta::File {
+                path,
+                max_vertices_per_mesh,
+            } => {
+                if let Some(decoded_model) = mesh_cache::load(self) {
+                    return Ok(decoded_model);
+                }
+                let decoded_model = import_model(path, *max_vertices_per_mesh)?;
+                mesh_cache::store(self, &decoded_model);
+                Ok(decoded_model)
+            }
+            ModelMeta::Primitive(primitive) => Ok(primitive.mesh_builder().into_decoded_model()),
+            // nothing to decode, the model only exists once it is inserted
+            ModelMeta::Procedural(name) => Err(AssetError::NotRegistered { name: name.clone() }),
+        }
+    }
+
     pub fn load_model(
         &self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         opaque_pipeline: &OpaquePipeline,
+    ) -> Result<ModelData, AssetError> {
+        let decoded_model = self.decode_model()?;
+        Ok(decoded_model.upload(device, queue, opaque_pipeline))
+    }
+
+    /// loads the model, or logs the error and returns the placeholder model so that rendering can continue
+    pub fn load_model_or_placeholder(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        opaque_pipeline: &OpaquePipeline,
     ) -> ModelData {
-        let scene = Scene::from_file(
-            &self.path,
-            vec![
-                PostProcess::CalculateTangentSpace,
-                PostProcess::Triangulate,
-                PostProcess::JoinIdenticalVertices,
-                PostProcess::SortByPrimitiveType,
-            ],
-        )
-        .unwrap();
-
-        let mut opaque_meshes = Vec::new();
-        let mut transparent_meshes = Vec::new();
-        let root = &scene.root.unwrap();
-        let mut material_bind_group_cache: HashMap<u32, Arc<BindGroup>> = HashMap::new();
-        fn material_to_bind_group(
-            opaque_pipeline: &OpaquePipeline,
-            material: &Material,
-            device: &wgpu::Device,
-            queue: &wgpu::Queue,
-        ) -> Arc<BindGroup> {
-            let diffuse_texture =
-                if let Some(texture) = material.textures.get(&TextureType::Diffuse) {
-                    texture
-                } else {
-                    // to do
-                    material.textures.get(&TextureType::BaseColor).unwrap()
-                };
+        match self.load_model(device, queue, opaque_pipeline) {
+            Ok(model_data) => model_data,
+            Err(error) => {
+                log::warn!("{}, using the placeholder model instead", error);
+                create_placeholder_model(device, queue, opaque_pipeline)
+            }
+        }
+    }
+}
 
-            let diffuse_texture = diffuse_texture.borrow();
+// imports the scene through russimp
+fn import_model(
+    path: &str,
+    max_vertices_per_mesh: Option<u32>,
+) -> Result<DecodedModel, AssetError> {
+    let scene = Scene::from_file(path, import_flags()).map_err(|source| AssetError::Import {
+        path: path.to_string(),
+        source,
+    })?;
+
+    let mut meshes = Vec::new();
+    let root = scene
+        .root
+        .as_ref()
+        .ok_or_else(|| AssetError::UnsupportedFormat {
+            path: path.to_string(),
+            description: "scene has no root node".to_string(),
+        })?;
+    let mut materials: HashMap<u32, DecodedMaterial> = HashMap::new();
+    let mut skeleton = Skeleton {
+        nodes: Vec::new(),
+        joints: Vec::new(),
+    };
+    let mut mesh_nodes = Vec::new();
+    collect_nodes(root, None, &mut skeleton.nodes, &mut mesh_nodes);
+    let rest_global_transforms = skeleton.rest_global_transforms();
+    // joint index of every bone name
+    let mut joint_indices: HashMap<String, u32> = HashMap::new();
+    let morph_targets = match scene.meshes.iter().any(|mesh| !mesh.anim_meshes.is_empty()) {
+        true => decode_morph_targets(path, &scene),
+        false => Vec::new(),
+    };
+    for (mesh_index, node_index) in mesh_nodes {
+        let node_name = skeleton.nodes[node_index].name.clone();
+        let mesh =
+            scene
+                .meshes
+                .get(mesh_index as usize)
+                .ok_or_else(|| AssetError::UnsupportedFormat {
+                    path: path.to_string(),
+                    description: format!(
+                        "node {} refers to missing mesh {}",
+                        node_name, mesh_index
+                    ),
+                })?;
+        let material = scene
+            .materials
+            .get(mesh.material_index as usize)
+            .ok_or_else(|| AssetError::MissingMaterialProperty {
+                path: path.to_string(),
+                property: format!("material {}", mesh.material_index),
+            })?;
+        let properties = material
+            .properties
+            .iter()
+            .map(|property| {
+                let key = (property.key.clone(), property.semantic.clone());
+                let value = property.clone();
+                (key, value)
+            })
+            .collect::<HashMap<_, _>>();
+        let diffuse_uv_channel_property = properties
+            .get(&("$tex.uvwsrc".to_string(), TextureType::Diffuse))
+            .or_else(|| properties.get(&("$tex.uvwsrc".to_string(), TextureType::BaseColor)));
+        let diffuse_uv_channel_index = match diffuse_uv_channel_property.map(|p| &p.data) {
+            Some(PropertyTypeInfo::IntegerArray(value)) if !value.is_empty() => value[0],
+            _ => {
+                log::warn!("{}: no diffuse uv channel found, using channel 0", path);
+                0
+            }
+        };
+
+        let tex_coords = mesh
+            .texture_coords
+            .get(diffuse_uv_channel_index as usize)
+            .and_then(|tex_coords| tex_coords.as_ref());
+        if tex_coords.is_none() {
+            log::warn!(
+                "{}: mesh {} has no uv channel {}",
+                path,
+                mesh.name,
+                diffuse_uv_channel_index
+            );
+        }
+        if mesh.normals.len() != mesh.vertices.len() {
+            log::warn!("{}: mesh {} has no normals", path, mesh.name);
+        }
+        if mesh.tangents.len() != mesh.vertices.len() {
+            log::warn!("{}: mesh {} has no tangents", path, mesh.name);
+        }
 
-            let data = match &diffuse_texture.data {
-                DataContent::Bytes(bytes) => bytes,
-                _ => panic!("Unsupported texture type"),
+        let mut vertices: Vec<Vertex> = Vec::new();
+        for i in 0..mesh.vertices.len() {
+            let vertex = mesh.vertices[i];
+            let tex_coords = match tex_coords.and_then(|tex_coords| tex_coords.get(i)) {
+                Some(tex_coord) => [tex_coord.x, tex_coord.y],
+                None => [0.0, 0.0],
             };
-            let diffuse_image = match diffuse_texture.height {
-                0 => ImageReader::new(Cursor::new(data))
-                    .with_guessed_format()
-                    .unwrap()
-                    .decode()
-                    .unwrap()
-                    .into_rgba8(),
-                _ => ImageBuffer::from_raw(
-                    diffuse_texture.width,
-                    diffuse_texture.height,
-                    data.clone(),
-                )
-                .unwrap(),
+            let normal = match mesh.normals.get(i) {
+                Some(normal) => [normal.x, normal.y, normal.z],
+                None => [0.0, 1.0, 0.0],
             };
-            // let diffuse_image = flip_vertical(&diffuse_image);
-            // to do
-            let material_bind_group =
-                opaque_pipeline.create_material_bind_group(device, queue, &diffuse_image);
-            Arc::new(material_bind_group)
-        }
-
-        println!("Number of meshes: {}", root.meshes.len());
-        for mesh in root.meshes.iter() {
-            let mesh = scene.meshes.get(*mesh as usize).unwrap();
-            let material = scene.materials.get(mesh.material_index as usize).unwrap();
-            let properties = material
-                .properties
-                .iter()
-                .map(|property| {
-                    let key = (property.key.clone(), property.semantic.clone());
-                    let value = property.clone();
-                    (key, value)
-                })
-                .collect::<HashMap<_, _>>();
-            let diffuse_uv_channel_property = if let Some(property) =
-                properties.get(&("$tex.uvwsrc".to_string(), TextureType::Diffuse))
-            {
-                property
-            } else if let Some(property) =
-                properties.get(&("$tex.uvwsrc".to_string(), TextureType::BaseColor))
-            {
-                property
+            // tangent with the handedness of the bitangent in w, zero if assimp could not compute one
+            let tangent = match (mesh.tangents.get(i), mesh.bitangents.get(i)) {
+                (Some(tangent), Some(bitangent)) => {
+                    let n = cgmath::Vector3::from(normal);
+                    let t = cgmath::Vector3::new(tangent.x, tangent.y, tangent.z);
+                    let b = cgmath::Vector3::new(bitangent.x, bitangent.y, bitangent.z);
+                    let handedness = if n.cross(t).dot(b) < 0.0 { -1.0 } else { 1.0 };
+                    [t.x, t.y, t.z, handedness]
+                }
+                _ => [0.0, 0.0, 0.0, 1.0],
+            };
+            vertices.push(Vertex {
+                position: [vertex.x, vertex.y, vertex.z],
+                tex_coords,
+                normal,
+                tangent,
+            });
+        }
+        let mut indices: Vec<u32> = Vec::new();
+        for face in mesh.faces.iter() {
+            // Triangulate leaves points and lines alone
+            if face.0.len() != 3 {
+                continue;
+            }
+            for i in 0..3 {
+                indices.push(face.0[i]);
+            }
+        }
+        materials
+            .entry(mesh.material_index)
+            .or_insert_with(|| decode_material(path, material, &properties));
+        let skin = decode_skin(path, mesh, &mut skeleton, &mut joint_indices);
+        // skinned meshes are moved into the space of the model by their joint matrices
+        let transform = match skin {
+            Some(_) => cgmath::Matrix4::identity(),
+            None => rest_global_transforms[node_index],
+        };
+        // a mesh shared by several nodes keeps its targets for each of them
+        let mesh_morph_targets = morph_targets
+            .get(mesh_index as usize)
+            .cloned()
+            .unwrap_or_default();
+        let decoded_mesh = DecodedMesh::new(
+            vertices,
+            indices,
+            mesh.material_index,
+            transform,
+            node_index,
+            node_name,
+        )
+        .with_skin(skin)
+        .with_morph_targets(mesh_morph_targets);
+        match max_vertices_per_mesh {
+            Some(max_vertices) => meshes.extend(decoded_mesh.split(max_vertices as usize)),
+            None => meshes.push(decoded_mesh),
+        }
+    }
+    let animations = scene
+        .animations
+        .iter()
+        .enumerate()
+        .map(|(i, animation)| decode_animation(path, i, animation, &skeleton))
+        .collect();
+    Ok(DecodedModel {
+        meshes,
+        materials,
+        skeleton,
+        animations,
+    })
+}
+
+fn import_flags() -> Vec<PostProcess> {
+    vec![
+        PostProcess::CalculateTangentSpace,
+        PostProcess::Triangulate,
+        PostProcess::JoinIdenticalVertices,
+        PostProcess::SortByPrimitiveType,
+        // at most 4 joints per vertex
+        PostProcess::LimitBoneWeights,
+    ]
+}
+
+// copies count vectors, None for a null pointer
+unsafe fn read_vectors(vectors: *const sys::aiVector3D, count: usize) -> Option<Vec<[f32; 3]>> {
+    if vectors.is_null() {
+        return None;
+    }
+    let vectors = unsafe { std::slice::from_raw_parts(vectors, count) };
+    Some(
+        vectors
+            .iter()
+            .map(|vector| [vector.x, vector.y, vector.z])
+            .collect(),
+    )
+}
+
+// a scene imported through the raw assimp bindings, released when dropped so every early return frees it
+struct RawScene(*const sys::aiScene);
+
+impl Drop for RawScene {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            // SAFETY: the pointer was returned by aiImportFile and is released exactly once, here
+            unsafe { sys::aiReleaseImport(self.0) };
+        }
+    }
+}
+
+// the morph targets of every mesh of the scene.
+// russimp 3.2 only copies the bitangents of an aiAnimMesh and drops the pointer to the aiScene it was built from,
+// so the file has to be imported a second time through the raw assimp bindings. the same post processing keeps the
+// meshes and their vertices in the same order. this only happens for files that contain morph targets.
+// assimp stores the targets as absolute positions and normals, they are turned into offsets from the mesh
+fn decode_morph_targets(path: &str, scene: &Scene) -> Vec<Vec<MorphTarget>> {
+    let Ok(c_path) = CString::new(path) else {
+        return Vec::new();
+    };
+    let flags = import_flags()
+        .into_iter()
+        .fold(0, |flags, step| flags | step as u32);
+    // SAFETY: c_path is a valid nul terminated string that outlives the call
+    let raw_scene = RawScene(unsafe { sys::aiImportFile(c_path.as_ptr(), flags) });
+    // SAFETY: a non null scene stays valid until raw_scene is dropped at the end of this function
+    let Some(raw_scene_ref) = (unsafe { raw_scene.0.as_ref() }) else {
+        log::warn!("{}: morph targets could not be imported", path);
+        return Vec::new();
+    };
+    if raw_scene_ref.mNumMeshes as usize != scene.meshes.len() || raw_scene_ref.mMeshes.is_null() {
+        log::warn!("{}: morph targets do not match the meshes", path);
+        return Vec::new();
+    }
+    let mut morph_targets = Vec::new();
+    for (mesh_index, mesh) in scene.meshes.iter().enumerate() {
+        // SAFETY: mMeshes holds mNumMeshes non null mesh pointers, checked against scene.meshes above
+        let raw_mesh = unsafe { &**raw_scene_ref.mMeshes.add(mesh_index) };
+        let vertex_count = mesh.vertices.len();
+        let mut mesh_morph_targets = Vec::new();
+        let anim_mesh_count = match raw_mesh.mAnimMeshes.is_null() {
+            true => 0,
+            false => raw_mesh.mNumAnimMeshes as usize,
+        };
+        for target_index in 0..anim_mesh_count {
+            // SAFETY: mAnimMeshes holds mNumAnimMeshes non null pointers owned by the scene
+            let anim_mesh = unsafe { &**raw_mesh.mAnimMeshes.add(target_index) };
+            if anim_mesh.mNumVertices as usize != vertex_count {
+                log::warn!(
+                    "{}: morph target {} of mesh {} has a different vertex count",
+                    path,
+                    target_index,
+                    mesh.name
+                );
+                continue;
+            }
+            let name = String::from(&anim_mesh.mName);
+            let name = if name.is_empty() {
+                format!("target {}", target_index)
             } else {
-                panic!("No diffuse uv channel found");
+                name
             };
-            let diffuse_uv_channel = &diffuse_uv_channel_property.data;
-            let diffuse_uv_channel = match diffuse_uv_channel {
-                PropertyTypeInfo::IntegerArray(value) => value,
-                _ => panic!("Unsupported texture type"),
+            // SAFETY: every attribute array of the anim mesh is either null or has mNumVertices elements,
+            // which was checked to be vertex_count
+            let positions = unsafe { read_vectors(anim_mesh.mVertices, vertex_count) };
+            let normals = unsafe { read_vectors(anim_mesh.mNormals, vertex_count) };
+            let tangents = unsafe { read_vectors(anim_mesh.mTangents, vertex_count) };
+            // a target without some attribute leaves it unchanged
+            let deltas = |targets: Option<Vec<[f32; 3]>>, base: &Vec<russimp::Vector3D>| {
+                (0..vertex_count)
+                    .map(|i| match (&targets, base.get(i)) {
+                        (Some(targets), Some(base)) => [
+                            targets[i][0] - base.x,
+                            targets[i][1] - base.y,
+                            targets[i][2] - base.z,
+                        ],
+                        _ => [0.0, 0.0, 0.0],
+                    })
+                    .collect()
             };
-            let diffuse_uv_channel_index = diffuse_uv_channel[0];
-
-            let tex_coords = mesh
-                .texture_coords
-                .get(diffuse_uv_channel_index as usize)
-                .unwrap();
-            let tex_coords = tex_coords.as_ref().unwrap();
-
-            let mut vertices: Vec<Vertex> = Vec::new();
-            assert!(mesh.vertices.len() == tex_coords.len());
-            for i in 0..mesh.vertices.len() {
-                let vertex = mesh.vertices[i];
-                let tex_coord = tex_coords[i];
-                let normal = mesh.normals[i];
-                vertices.push(Vertex {
-                    position: [vertex.x, vertex.y, vertex.z],
-                    tex_coords: [tex_coord.x, tex_coord.y],
-                    normal: [normal.x, normal.y, normal.z],
+            mesh_morph_targets.push(MorphTarget {
+                name,
+                default_weight: anim_mesh.mWeight,
+                position_deltas: deltas(positions, &mesh.vertices),
+                normal_deltas: deltas(normals, &mesh.normals),
+                tangent_deltas: deltas(tangents, &mesh.tangents),
+            });
+        }
+        morph_targets.push(mesh_morph_targets);
+    }
+    morph_targets
+}
+
+// assimp matrices are row major, cgmath matrices are column major
+fn to_matrix4(matrix: &Matrix4x4) -> cgmath::Matrix4<f32> {
+    cgmath::Matrix4::new(
+        matrix.a1, matrix.b1, matrix.c1, matrix.d1, matrix.a2, matrix.b2, matrix.c2, matrix.d2,
+        matrix.a3, matrix.b3, matrix.c3, matrix.d3, matrix.a4, matrix.b4, matrix.c4, matrix.d4,
+    )
+}
+
+// walks the node tree depth first, adds every node to nodes and collects (mesh index, node index) for every mesh.
+// a mesh referenced by several nodes is collected once per node
+fn collect_nodes(
+    node: &Node,
+    parent: Option<usize>,
+    nodes: &mut Vec<SkeletonNode>,
+    mesh_nodes: &mut Vec<(u32, usize)>,
+) {
+    let node_index = nodes.len();
+    nodes.push(SkeletonNode {
+        name: node.name.clone(),
+        parent,
+        local_transform: to_matrix4(&node.transformation),
+    });
+    for mesh in node.meshes.iter() {
+        mesh_nodes.push((*mesh, node_index));
+    }
+    for child in node.children.borrow().iter() {
+        collect_nodes(child, Some(node_index), nodes, mesh_nodes);
+    }
+}
+
+// joints and weights of every vertex, None if the mesh has no bones.
+// bones are shared by all meshes of the model, new ones are added to the joints of the skeleton
+fn decode_skin(
+    path: &str,
+    mesh: &Mesh,
+    skeleton: &mut Skeleton,
+    joint_indices: &mut HashMap<String, u32>,
+) -> Option<Vec<SkinVertex>> {
+    if mesh.bones.is_empty() {
+        return None;
+    }
+    let mut influences: Vec<Vec<(u32, f32)>> = vec![Vec::new(); mesh.vertices.len()];
+    for bone in mesh.bones.iter() {
+        let joint = match joint_indices.get(&bone.name) {
+            Some(joint) => *joint,
+            None => {
+                let Some(node) = skeleton.node_index(&bone.name) else {
+                    log::warn!("{}: bone {} has no node", path, bone.name);
+                    continue;
+                };
+                let joint = skeleton.joints.len() as u32;
+                skeleton.joints.push(Joint {
+                    node,
+                    inverse_bind_matrix: to_matrix4(&bone.offset_matrix),
                 });
+                joint_indices.insert(bone.name.clone(), joint);
+                joint
             }
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let mut indices: Vec<u16> = Vec::new();
-            for face in mesh.faces.iter() {
-                assert!(face.0.len() == 3);
-                for i in 0..3 {
-                    indices.push(face.0[i] as u16);
-                }
+        };
+        for weight in bone.weights.iter() {
+            if let Some(influence) = influences.get_mut(weight.vertex_id as usize) {
+                influence.push((joint, weight.weight));
             }
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(&indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-            let material_bind_group = material_bind_group_cache
-                .entry(mesh.material_index)
-                .or_insert_with(|| material_to_bind_group(opaque_pipeline, material, device, queue))
-                .clone();
-
-            let my_mesh = MyMesh {
-                vertex_buffer,
-                index_buffer,
-                material_bind_group,
-                num_indices: indices.len() as u32,
+        }
+    }
+    let skin = influences
+        .into_iter()
+        .map(|mut influence| {
+            // keep the largest weights and make them sum up to 1
+            influence.sort_by(|a, b| b.1.total_cmp(&a.1));
+            influence.truncate(MAX_JOINT_INFLUENCES);
+            let total = influence.iter().map(|(_, weight)| weight).sum::<f32>();
+            let mut skin_vertex = SkinVertex::default();
+            for (i, (joint, weight)) in influence.iter().enumerate() {
+                skin_vertex.joints[i] = *joint;
+                skin_vertex.weights[i] = if total > 0.0 { weight / total } else { 0.0 };
+            }
+            skin_vertex
+        })
+        .collect();
+    Some(skin)
+}
+
+fn decode_animation(
+    path: &str,
+    index: usize,
+    animation: &Animation,
+    skeleton: &Skeleton,
+) -> AnimationClip {
+    // keys are in ticks, some formats leave the tick rate at 0
+    let ticks_per_second = if animation.ticks_per_second > 0.0 {
+        animation.ticks_per_second
+    } else {
+        25.0
+    };
+    let seconds = |ticks: f64| (ticks / ticks_per_second) as f32;
+    let name = if animation.name.is_empty() {
+        format!("animation {}", index)
+    } else {
+        animation.name.clone()
+    };
+    let morph_channels = animation
+        .morph_mesh_channels
+        .iter()
+        .filter_map(|channel| {
+            let Some(node) = skeleton.node_index(&channel.name) else {
+                log::warn!(
+                    "{}: animation {} refers to missing node {}",
+                    path,
+                    name,
+                    channel.name
+                );
+                return None;
             };
-            // determine if the mesh is opaque or transparent
-            let opacity = if let Some(opacity_property) =
-                properties.get(&("$mat.opacity".to_string(), TextureType::None))
-            {
-                let opacity_property = match &opacity_property.data {
-                    PropertyTypeInfo::FloatArray(value) => value,
-                    _ => panic!("Unsupported texture type"),
-                };
-                opacity_property[0]
-            } else {
-                println!("Warning: no opacity property found");
-                1.0
+            // keys only list the targets they change, the others are 0
+            let target_count = channel
+                .keys
+                .iter()
+                .flat_map(|key| key.values.iter())
+                .map(|target| *target as usize + 1)
+                .max()
+                .unwrap_or(0);
+            Some(MorphChannel {
+                node,
+                weights: Keyframes {
+                    times: channel.keys.iter().map(|key| seconds(key.time)).collect(),
+                    values: channel
+                        .keys
+                        .iter()
+                        .map(|key| {
+                            let mut weights = vec![0.0; target_count];
+                            for (target, weight) in key.values.iter().zip(key.weights.iter()) {
+                                weights[*target as usize] = *weight as f32;
+                            }
+                            weights
+                        })
+                        .collect(),
+                },
+            })
+        })
+        .collect();
+    let channels = animation
+        .channels
+        .iter()
+        .filter_map(|channel| {
+            let Some(node) = skeleton.node_index(&channel.name) else {
+                log::warn!(
+                    "{}: animation {} refers to missing node {}",
+                    path,
+                    name,
+                    channel.name
+                );
+                return None;
             };
-            if opacity < 1.0 {
-                println!("Mesh is transparent");
-                transparent_meshes.push(Arc::new(my_mesh));
-            } else {
-                println!("Mesh is opaque");
-                opaque_meshes.push(Arc::new(my_mesh));
+            let vector_keyframes = |keys: &Vec<VectorKey>| Keyframes {
+                times: keys.iter().map(|key| seconds(key.time)).collect(),
+                values: keys
+                    .iter()
+                    .map(|key| cgmath::Vector3::new(key.value.x, key.value.y, key.value.z))
+                    .collect(),
+            };
+            Some(NodeChannel {
+                node,
+                translations: vector_keyframes(&channel.position_keys),
+                rotations: Keyframes {
+                    times: channel
+                        .rotation_keys
+                        .iter()
+                        .map(|key| seconds(key.time))
+                        .collect(),
+                    values: channel
+                        .rotation_keys
+                        .iter()
+                        .map(|key| {
+                            cgmath::Quaternion::new(
+                                key.value.w,
+                                key.value.x,
+                                key.value.y,
+                                key.value.z,
+                            )
+                        })
+                        .collect(),
+                },
+                scales: vector_keyframes(&channel.scaling_keys),
+            })
+        })
+        .collect();
+    AnimationClip {
+        name,
+        duration: seconds(animation.duration),
+        channels,
+        morph_channels,
+    }
+}
+
+type Image = ImageBuffer<Rgba<u8>, Vec<u8>>;
+type MaterialProperties = HashMap<(String, TextureType), MaterialProperty>;
+
+fn decode_embedded_texture(path: &str, texture: &Texture) -> Result<Image, AssetError> {
+    match &texture.data {
+        // compressed image file embedded in the model
+        DataContent::Bytes(bytes) if texture.height == 0 => {
+            let image = ImageReader::new(Cursor::new(bytes))
+                .with_guessed_format()
+                .map_err(|source| AssetError::Io {
+                    path: path.to_string(),
+                    source,
+                })?
+                .decode()
+                .map_err(|error| AssetError::from_image_error(path, error))?;
+            Ok(image.into_rgba8())
+        }
+        DataContent::Bytes(bytes) => {
+            ImageBuffer::from_raw(texture.width, texture.height, bytes.clone()).ok_or_else(|| {
+                AssetError::UnsupportedFormat {
+                    path: path.to_string(),
+                    description: "raw texture data does not match its size".to_string(),
+                }
+            })
+        }
+        DataContent::Texel(texels) => {
+            if texels.len() != (texture.width * texture.height) as usize {
+                return Err(AssetError::UnsupportedFormat {
+                    path: path.to_string(),
+                    description: "texel data does not match its size".to_string(),
+                });
+            }
+            // texels are stored as bgra
+            let bytes = texels
+                .iter()
+                .flat_map(|texel| [texel.r, texel.g, texel.b, texel.a])
+                .collect::<Vec<_>>();
+            Ok(ImageBuffer::from_raw(texture.width, texture.height, bytes).unwrap())
+        }
+    }
+}
+
+// the first texture of the given types, either embedded in the model or as a file next to it.
+// None if the material has no such texture
+fn load_material_image(
+    path: &str,
+    material: &Material,
+    properties: &MaterialProperties,
+    texture_types: &[TextureType],
+) -> Option<Result<Image, AssetError>> {
+    for texture_type in texture_types {
+        if let Some(texture) = material.textures.get(texture_type) {
+            return Some(decode_embedded_texture(path, &texture.borrow()));
+        }
+        // russimp only resolves embedded textures
+        let file = properties.get(&("$tex.file".to_string(), texture_type.clone()));
+        if let Some(PropertyTypeInfo::String(file)) = file.map(|property| &property.data) {
+            let file_path = Path::new(path).with_file_name(file);
+            let file_path = file_path.to_string_lossy();
+            return Some(MyTexture::load_image_from_file_path(&file_path));
+        }
+    }
+    None
+}
+
+fn float_property(properties: &MaterialProperties, key: &str) -> Option<Vec<f32>> {
+    match properties.get(&(key.to_string(), TextureType::None)) {
+        Some(property) => match &property.data {
+            PropertyTypeInfo::FloatArray(value) if !value.is_empty() => Some(value.clone()),
+            _ => {
+                log::warn!("material property {} is not a float", key);
+                None
             }
+        },
+        None => None,
+    }
+}
+
+// reads the glTF metallic-roughness parameters that assimp stores as material properties.
+// missing textures become 1x1 images that leave the factors unchanged, textures that fail to decode
+// become the placeholder texture
+fn decode_material(
+    path: &str,
+    material: &Material,
+    properties: &MaterialProperties,
+) -> DecodedMaterial {
+    let mut decoded_material = DecodedMaterial::default();
+    if let Some(color) = float_property(properties, "$clr.base")
+        .or_else(|| float_property(properties, "$clr.diffuse"))
+    {
+        for (i, value) in color.iter().take(4).enumerate() {
+            decoded_material.base_color_factor[i] = *value;
         }
-        ModelData {
-            opaque_meshes,
-            transparent_meshes,
+    }
+    if let Some(metallic) = float_property(properties, "$mat.metallicFactor") {
+        decoded_material.metallic_factor = metallic[0];
+    }
+    if let Some(roughness) = float_property(properties, "$mat.roughnessFactor") {
+        decoded_material.roughness_factor = roughness[0];
+    }
+    if let Some(emissive) = float_property(properties, "$clr.emissive") {
+        for (i, value) in emissive.iter().take(3).enumerate() {
+            decoded_material.emissive_factor[i] = *value;
+        }
+    }
+    // determine if the mesh is opaque or transparent
+    match float_property(properties, "$mat.opacity") {
+        Some(opacity) => decoded_material.opacity = opacity[0],
+        None => log::warn!("no opacity property found"),
+    }
+
+    let images = [
+        (
+            &mut decoded_material.base_color_image,
+            &[TextureType::BaseColor, TextureType::Diffuse][..],
+        ),
+        (
+            &mut decoded_material.metallic_roughness_image,
+            // depending on the assimp version the packed texture is stored under one of these
+            &[
+                TextureType::Unknown,
+                TextureType::Metalness,
+                TextureType::Roughness,
+            ][..],
+        ),
+        (
+            &mut decoded_material.normal_image,
+            &[TextureType::Normals, TextureType::NormalCamera][..],
+        ),
+        (
+            &mut decoded_material.occlusion_image,
+            &[TextureType::LightMap, TextureType::AmbientOcclusion][..],
+        ),
+        (
+            &mut decoded_material.emissive_image,
+            &[TextureType::Emissive, TextureType::EmissionColor][..],
+        ),
+    ];
+    for (image, texture_types) in images {
+        match load_material_image(path, material, properties, texture_types) {
+            Some(Ok(loaded_image)) => *image = loaded_image,
+            Some(Err(error)) => {
+                log::warn!("{}, using the placeholder texture instead", error);
+                *image = MyTexture::placeholder_image();
+            }
+            None => {}
         }
     }
+    decoded_material
+}
+
+/// a unit cube with the placeholder texture
+pub fn placeholder_decoded_model() -> DecodedModel {
+    MeshBuilder::cube(1.0)
+        .with_material(DecodedMaterial {
+            base_color_image: MyTexture::placeholder_image(),
+            ..Default::default()
+        })
+        .into_decoded_model()
+}
+
+pub fn create_placeholder_model(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    opaque_pipeline: &OpaquePipeline,
+) -> ModelData {
+    placeholder_decoded_model().upload(device, queue, opaque_pipeline)
 }
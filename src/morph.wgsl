@@ -0,0 +1,69 @@
+// blends the morph targets of one mesh into a copy of its vertices, see MorphPipeline
+struct MorphParams {
+    vertex_count: u32,
+    target_count: u32,
+    weights: array<f32>,
+};
+@group(0) @binding(0)
+var<storage, read> params: MorphParams;
+// struct Vertex in vertex.rs as 12 floats: position, tex_coords, normal, tangent with handedness in w
+@group(0) @binding(1)
+var<storage, read> base_vertices: array<f32>;
+// position, normal and tangent offsets of every vertex, one target after the other
+@group(0) @binding(2)
+var<storage, read> target_deltas: array<f32>;
+@group(0) @binding(3)
+var<storage, read_write> morphed_vertices: array<f32>;
+
+const VERTEX_FLOATS: u32 = 12u;
+const DELTA_FLOATS: u32 = 9u;
+
+fn read_vec3(index: u32) -> vec3<f32> {
+    return vec3<f32>(base_vertices[index], base_vertices[index + 1u], base_vertices[index + 2u]);
+}
+
+fn read_delta(index: u32) -> vec3<f32> {
+    return vec3<f32>(target_deltas[index], target_deltas[index + 1u], target_deltas[index + 2u]);
+}
+
+fn write_vec3(index: u32, value: vec3<f32>) {
+    morphed_vertices[index] = value.x;
+    morphed_vertices[index + 1u] = value.y;
+    morphed_vertices[index + 2u] = value.z;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let vertex = id.x;
+    if vertex >= params.vertex_count {
+        return;
+    }
+    let base = vertex * VERTEX_FLOATS;
+    var position = read_vec3(base);
+    var normal = read_vec3(base + 5u);
+    var tangent = read_vec3(base + 8u);
+    for (var target_index = 0u; target_index < params.target_count; target_index++) {
+        let weight = params.weights[target_index];
+        if weight == 0.0 {
+            continue;
+        }
+        let delta = (target_index * params.vertex_count + vertex) * DELTA_FLOATS;
+        position += read_delta(delta) * weight;
+        normal += read_delta(delta + 3u) * weight;
+        tangent += read_delta(delta + 6u) * weight;
+    }
+    write_vec3(base, position);
+    // the texture coordinates and the handedness are not morphed
+    morphed_vertices[base + 3u] = base_vertices[base + 3u];
+    morphed_vertices[base + 4u] = base_vertices[base + 4u];
+    // a zero length tangent tells pbr.wgsl to use the derivative frame, keep it that way
+    if length(normal) > 0.0 {
+        normal = normalize(normal);
+    }
+    if length(tangent) > 0.0 {
+        tangent = normalize(tangent);
+    }
+    write_vec3(base + 5u, normal);
+    write_vec3(base + 8u, tangent);
+    morphed_vertices[base + 11u] = base_vertices[base + 11u];
+}
@@ -0,0 +1,183 @@
+// blends morph targets in a compute pass that runs before the shadow and opaque passes.
+// every morphed (mesh, instance) pair gets its own vertex buffer, which is drawn in place of the vertex buffer
+// of the mesh, so the render pipelines and skinning do not need to know about morph targets.
+// the buffers are kept across frames and only the weights are written again
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    model_data::{MeshMorph, MyMesh},
+    vertex::Vertex,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// the buffers of one morphed (mesh, instance) pair
+struct MorphSlot {
+    // also keeps the key of the slot from being reused by another mesh
+    morph: Arc<MeshMorph>,
+    params_buffer: wgpu::Buffer,
+    morphed_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // morphed since the last dispatch
+    dispatched: bool,
+}
+
+impl MorphSlot {
+    fn new(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        mesh: &MyMesh,
+        morph: &Arc<MeshMorph>,
+    ) -> Self {
+        // vertex_count, target_count and one weight per target
+        let params_size = (2 + morph.default_weights.len()) * size_of::<u32>();
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Morph Params Buffer"),
+            size: params_size as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let morphed_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Morphed Vertex Buffer"),
+            size: (morph.vertex_count as usize * size_of::<Vertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("morph_bind_group"),
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: mesh.vertex_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: morph.target_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: morphed_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        Self {
+            morph: morph.clone(),
+            params_buffer,
+            morphed_buffer,
+            bind_group,
+            dispatched: false,
+        }
+    }
+}
+
+pub struct MorphPipeline {
+    pub pipeline: wgpu::ComputePipeline,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    // keyed by the morph targets of the mesh and the index of the instance among the instances of its model
+    slots: HashMap<(*const MeshMorph, usize), MorphSlot>,
+}
+
+impl MorphPipeline {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Morph Bind Group Layout"),
+            entries: &[
+                // params
+                storage_entry(0, true),
+                // base vertices
+                storage_entry(1, true),
+                // target deltas
+                storage_entry(2, true),
+                // morphed vertices
+                storage_entry(3, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Morph Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Morph Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("morph.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Morph Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+        Self {
+            pipeline,
+            bind_group_layout,
+            slots: HashMap::new(),
+        }
+    }
+
+    /// writes the weights of one instance of the mesh, weights has one entry per target. the instance is
+    /// morphed by the next dispatch into a vertex buffer that is kept for the same mesh and instance index
+    /// across frames. returns that buffer, or None if the mesh has no morph targets
+    pub fn morph(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mesh: &MyMesh,
+        instance_index: usize,
+        weights: &[f32],
+    ) -> Option<wgpu::Buffer> {
+        let morph = mesh.morph.as_ref()?;
+        let target_count = morph.default_weights.len();
+        let key = (Arc::as_ptr(morph), instance_index);
+        let slot = self
+            .slots
+            .entry(key)
+            .or_insert_with(|| MorphSlot::new(device, &self.bind_group_layout, mesh, morph));
+        // matches struct MorphParams in morph.wgsl
+        let mut params = vec![morph.vertex_count, target_count as u32];
+        params.extend((0..target_count).map(|i| weights.get(i).copied().unwrap_or(0.0).to_bits()));
+        queue.write_buffer(&slot.params_buffer, 0, bytemuck::cast_slice(&params));
+        slot.dispatched = true;
+        Some(slot.morphed_buffer.clone())
+    }
+
+    /// records every morph since the last call into one compute pass. the buffers of mesh instances that
+    /// were not morphed since the last call are dropped
+    pub fn dispatch(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.slots.retain(|_, slot| slot.dispatched);
+        if self.slots.is_empty() {
+            return;
+        }
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Morph Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        for slot in self.slots.values_mut() {
+            compute_pass.set_bind_group(0, &slot.bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                slot.morph.vertex_count.div_ceil(WORKGROUP_SIZE),
+                1,
+                1,
+            );
+            slot.dispatched = false;
+        }
+    }
+}
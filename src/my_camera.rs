@@ -1,20 +1,20 @@
 use cgmath::{InnerSpace, Zero};
 
+use crate::camera::{Camera, Projection};
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
 );
 
 pub struct MyCamera {
     pub pos: cgmath::Point3<f32>,
     pub yaw: f32,
     pub pitch: f32,
-    pub fovy: f32,
-    pub znear: f32,
-    pub zfar: f32,
+    pub projection: Projection,
     // speed
     pub max_speed: f32,
     pub acceleration: f32,
@@ -24,19 +24,27 @@ pub struct MyCamera {
 }
 
 impl MyCamera {
-    pub fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
-        let forward = cgmath::Vector3::new(
+    /// unit vector in the view direction
+    pub fn forward(&self) -> cgmath::Vector3<f32> {
+        cgmath::Vector3::new(
             self.yaw.to_radians().cos() * self.pitch.to_radians().cos(),
             self.pitch.to_radians().sin(),
             self.yaw.to_radians().sin() * self.pitch.to_radians().cos(),
         )
-        .normalize();
+        .normalize()
+    }
+    /// unit vector to the right of the view direction, parallel to the ground
+    pub fn right(&self) -> cgmath::Vector3<f32> {
+        self.forward().cross(cgmath::Vector3::unit_y()).normalize()
+    }
+}
 
-        // World up vector
-        let world_up = cgmath::Vector3::unit_y();
+impl Camera for MyCamera {
+    fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        let forward = self.forward();
 
         // Calculate the right vector (perpendicular to forward and world up)
-        let right = forward.cross(world_up).normalize();
+        let right = self.right();
 
         // Calculate the actual up vector (perpendicular to forward and right)
         let up = right.cross(forward).normalize();
@@ -44,8 +52,11 @@ impl MyCamera {
         let view = cgmath::Matrix4::look_at_rh(self.pos, target, up);
         view
     }
-    pub fn build_projection_matrix(&self, aspect: f32) -> cgmath::Matrix4<f32> {
-        cgmath::perspective(cgmath::Deg(self.fovy), aspect, self.znear, self.zfar)
+    fn projection(&self) -> Projection {
+        self.projection
+    }
+    fn position(&self) -> cgmath::Point3<f32> {
+        self.pos
     }
 }
 
@@ -55,9 +66,7 @@ impl Default for MyCamera {
             pos: cgmath::Point3::new(0.0, 0.0, 10.0),
             yaw: -90.0,
             pitch: 0.0,
-            fovy: 45.0,
-            znear: 0.1,
-            zfar: 100.0,
+            projection: Projection::default(),
             max_speed: 2.5,
             acceleration: 10.0,
             damp_factor: 5.0,
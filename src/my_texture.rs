@@ -1,10 +1,14 @@
-use std::{collections::HashMap, sync::Mutex};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
 use image::{GenericImageView, ImageBuffer, Rgba};
 use lazy_static::lazy_static;
 use rusttype::{Font, point};
 
 use crate::{
+    asset_error::AssetError,
     cache::{CacheValue, get_font},
     ui_node::UIIdentifier,
 };
@@ -30,17 +34,18 @@ pub enum TextureSource {
 }
 
 impl MyTexture {
-    fn load_image_from_file_path(
+    pub fn load_image_from_file_path(
         file_path: &str,
-    ) -> Result<image::ImageBuffer<Rgba<u8>, Vec<u8>>, image::ImageError> {
-        let img = image::open(file_path)?;
+    ) -> Result<image::ImageBuffer<Rgba<u8>, Vec<u8>>, AssetError> {
+        let img = image::open(file_path)
+            .map_err(|error| AssetError::from_image_error(file_path, error))?;
         Ok(img.to_rgba8())
     }
-    fn load_image_from_text_character(
+    pub fn load_image_from_text_character(
         character: char,
         font_file_path: String,
-    ) -> image::ImageBuffer<Rgba<u8>, Vec<u8>> {
-        let font = get_font(font_file_path);
+    ) -> Result<image::ImageBuffer<Rgba<u8>, Vec<u8>>, Arc<AssetError>> {
+        let font = get_font(font_file_path)?;
         let font = match font.as_ref() {
             CacheValue::Font(font) => font,
             _ => panic!("Invalid cache value"),
@@ -102,13 +107,40 @@ impl MyTexture {
             let intensity = (v * 255.0) as u8;
             image.put_pixel(x as u32, y as u32, Rgba([0, 255, 0, intensity]));
         });
-        image
+        Ok(image)
+    }
+
+    /// the 2x2 magenta and black checkerboard of assets/placeholder.png, built in memory so that
+    /// it is available even if the assets folder is broken
+    pub fn placeholder_image() -> image::ImageBuffer<Rgba<u8>, Vec<u8>> {
+        image::ImageBuffer::from_fn(2, 2, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgba([255, 0, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        })
+    }
+
+    /// 1x1 image of a single color
+    pub fn solid_image(color: Rgba<u8>) -> image::ImageBuffer<Rgba<u8>, Vec<u8>> {
+        image::ImageBuffer::from_pixel(1, 1, color)
     }
 
     pub fn from_image(
         image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+    ) -> Self {
+        Self::from_image_with_format(image, wgpu::TextureFormat::Rgba8UnormSrgb, device, queue)
+    }
+
+    /// data textures like normal maps have to use a linear format instead of srgb
+    pub fn from_image_with_format(
+        image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+        format: wgpu::TextureFormat,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
     ) -> Self {
         let image = image::imageops::flip_vertical(image);
         let dimensions = image.dimensions();
@@ -123,7 +155,7 @@ impl MyTexture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
@@ -160,30 +192,80 @@ impl MyTexture {
             sampler,
         }
     }
-    pub fn load(
+    /// decodes the image of a texture source without touching the gpu
+    pub fn decode_image(
         texture_source: TextureSource,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-    ) -> Result<Self, image::ImageError> {
+    ) -> Result<image::ImageBuffer<Rgba<u8>, Vec<u8>>, Arc<AssetError>> {
         let img = match texture_source {
             TextureSource::FilePath(ref file_path) => Self::load_image_from_file_path(file_path)?,
             TextureSource::TextCharacter {
                 character,
                 font_file_path,
-            } => Self::load_image_from_text_character(character, font_file_path),
-            _ => {
-                panic!("Unsupported texture source");
+            } => Self::load_image_from_text_character(character, font_file_path)?,
+            TextureSource::PureColor { red, green, blue } => {
+                Self::solid_image(Rgba([red, green, blue, 255]))
             }
         };
+        Ok(img)
+    }
+    pub fn load(
+        texture_source: TextureSource,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Self, Arc<AssetError>> {
+        let img = Self::decode_image(texture_source)?;
         let my_texture = Self::from_image(&img, device, queue);
         Ok(my_texture)
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
+    // the scene is lit and blended in this format, the tonemap pass maps it to the swapchain
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// color target of the opaque and transparent passes, read back by the tonemap pass
+    pub fn create_hdr_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: config.width.max(1),
+                height: config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // the tonemap pass loads texels directly, the sampler is only there to fill the struct
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("HDR Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
 
+    /// sample_count has to match the color target it is used with
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -196,7 +278,7 @@ impl MyTexture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT // 3.
@@ -214,7 +296,7 @@ impl MyTexture {
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual), // 5.
+            compare: Some(wgpu::CompareFunction::GreaterEqual), // 5.
             lod_min_clamp: 0.0,
             lod_max_clamp: 100.0,
             ..Default::default()
@@ -226,6 +308,76 @@ impl MyTexture {
         }
     }
 
+    /// multisampled color target that is drawn into and resolved into a single sampled texture of the same format
+    pub fn create_msaa_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: config.width.max(1),
+                height: config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // never sampled, only resolved
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// depth texture array with one layer per shadow map, the view covers all layers and the sampler compares
+    pub fn create_shadow_texture(device: &wgpu::Device, resolution: u32, layers: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow texture"),
+            size: wgpu::Extent3d {
+                width: resolution.max(1),
+                height: resolution.max(1),
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            // linear filtering of a comparison sampler gives 2x2 pcf for free
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
     pub fn create_render_attachment_texture(
         device: &wgpu::Device,
         width: u32,
@@ -267,6 +419,47 @@ impl MyTexture {
             sampler,
         }
     }
+
+    /// a render target that can be copied back to the cpu, used by headless rendering
+    pub fn create_offscreen_target_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture_size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Offscreen Target Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
 }
 
 lazy_static! {
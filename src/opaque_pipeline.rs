@@ -3,14 +3,16 @@
 
 use std::sync::Arc;
 
-use image::{ImageBuffer, Rgba};
 use wgpu::{RenderPipeline, util::DeviceExt};
 
 use crate::{
-    model_data::MyMesh,
-    model_instance::{ModelInstance, ModelInstanceRaw},
+    buffer_arena::BufferArena,
+    frustum::{CullStats, Frustum, cull_instances},
+    material_uniform::MaterialUniform,
+    model_data::{DecodedMaterial, MyMesh},
+    model_instance::ModelInstanceRaw,
     my_texture::MyTexture,
-    vertex::Vertex,
+    vertex::{SkinVertex, Vertex},
 };
 
 // model
@@ -18,32 +20,51 @@ use crate::{
 // opauqe mesh, transparent mesh
 pub struct OpaquePipeline {
     pub pipeline: RenderPipeline,
+    // same shader with vs_skinned, for meshes with a skin buffer. None if the adapter has no storage buffers
+    // in the vertex stage, skinned meshes are drawn in their rest pose by pipeline then
+    pub skinned_pipeline: Option<RenderPipeline>,
     pub material_bind_group_layout: wgpu::BindGroupLayout,
     pub light_bind_group_layout: wgpu::BindGroupLayout,
     pub light_bind_group: wgpu::BindGroup,
 }
 
 impl OpaquePipeline {
-    fn create_material_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    // 0 base color, 1 sampler, 2 material factors, 3 metallic-roughness, 4 normal, 5 occlusion, 6 emissive
+    pub fn create_material_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Material Bind Group Layout"),
             entries: &[
+                texture_entry(0),
                 wgpu::BindGroupLayoutEntry {
-                    binding: 0,
+                    binding: 1,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 1,
+                    binding: 2,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
                     count: None,
                 },
+                texture_entry(3),
+                texture_entry(4),
+                texture_entry(5),
+                texture_entry(6),
             ],
         })
     }
@@ -51,22 +72,62 @@ impl OpaquePipeline {
         &self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        diffuse_image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+        material: &DecodedMaterial,
     ) -> wgpu::BindGroup {
-        let diffuse_texture = MyTexture::from_image(diffuse_image, device, queue);
+        let srgb = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let linear = wgpu::TextureFormat::Rgba8Unorm;
+        let base_color_texture =
+            MyTexture::from_image_with_format(&material.base_color_image, srgb, device, queue);
+        let metallic_roughness_texture = MyTexture::from_image_with_format(
+            &material.metallic_roughness_image,
+            linear,
+            device,
+            queue,
+        );
+        let normal_texture =
+            MyTexture::from_image_with_format(&material.normal_image, linear, device, queue);
+        let occlusion_texture =
+            MyTexture::from_image_with_format(&material.occlusion_image, linear, device, queue);
+        let emissive_texture =
+            MyTexture::from_image_with_format(&material.emissive_image, srgb, device, queue);
+        let material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Buffer"),
+            contents: bytemuck::cast_slice(&[MaterialUniform::new(material)]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
         device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &self.material_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                    resource: wgpu::BindingResource::TextureView(&base_color_texture.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&base_color_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&metallic_roughness_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&occlusion_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&emissive_texture.view),
                 },
             ],
-            label: Some("diffuse_bind_group"),
+            label: Some("material_bind_group"),
         })
     }
 
@@ -74,15 +135,16 @@ impl OpaquePipeline {
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                // storage buffers in the vertex stage are not available on every downlevel adapter
+                visibility: wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
                     min_binding_size: None,
                 },
                 count: None,
             }],
-            label: None,
+            label: Some("Light Bind Group Layout"),
         })
     }
 
@@ -103,10 +165,12 @@ impl OpaquePipeline {
 
     fn create_pipeline(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
         camera_bind_group_layout: &wgpu::BindGroupLayout,
         material_bind_group_layout: &wgpu::BindGroupLayout,
         light_bind_group_layout: &wgpu::BindGroupLayout,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
+        skinned: bool,
+        sample_count: u32,
     ) -> RenderPipeline {
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -115,22 +179,27 @@ impl OpaquePipeline {
                     camera_bind_group_layout,
                     material_bind_group_layout,
                     light_bind_group_layout,
+                    shadow_bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("opaque.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(include_str!("pbr.wgsl").into()),
         });
 
+        // skinned meshes have a third vertex buffer with the joints and weights
+
+        let buffers = [Vertex::desc(), ModelInstanceRaw::desc(), SkinVertex::desc()];
+
         let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
             layout: Some(&render_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &shader,
-                entry_point: Some("vs_main"), // 1.
-                buffers: &[Vertex::desc(), ModelInstanceRaw::desc()], // 2.
+                entry_point: Some(if skinned { "vs_skinned" } else { "vs_main" }), // 1.
+                buffers: if skinned { &buffers } else { &buffers[..2] },           // 2.
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
             fragment: Some(wgpu::FragmentState {
@@ -139,7 +208,7 @@ impl OpaquePipeline {
                 entry_point: Some("fs_main"),
                 targets: &[Some(wgpu::ColorTargetState {
                     // 4.
-                    format: config.format,
+                    format: MyTexture::HDR_FORMAT,
                     blend: Some(wgpu::BlendState::REPLACE),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
@@ -160,12 +229,12 @@ impl OpaquePipeline {
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: MyTexture::DEPTH_FORMAT,
                 depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual, // 1.
-                stencil: wgpu::StencilState::default(),          // 2.
+                depth_compare: wgpu::CompareFunction::GreaterEqual, // 1.
+                stencil: wgpu::StencilState::default(),             // 2.
                 bias: wgpu::DepthBiasState::default(),
             }), // 1.
             multisample: wgpu::MultisampleState {
-                count: 1,                         // 2.
+                count: sample_count,              // 2.
                 mask: !0,                         // 3.
                 alpha_to_coverage_enabled: false, // 4.
             },
@@ -177,38 +246,82 @@ impl OpaquePipeline {
 
     pub fn new(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
         camera_bind_group_layout: &wgpu::BindGroupLayout,
+        skinned_camera_bind_group_layout: Option<&wgpu::BindGroupLayout>,
         light_buffer: &wgpu::Buffer,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
     ) -> Self {
         let material_bind_group_layout = Self::create_material_bind_group_layout(device);
         let light_bind_group_layout = Self::create_light_bind_group_layout(device);
         let pipeline = Self::create_pipeline(
             device,
-            config,
             camera_bind_group_layout,
             &material_bind_group_layout,
             &light_bind_group_layout,
+            shadow_bind_group_layout,
+            false,
+            sample_count,
         );
+        let skinned_pipeline =
+            skinned_camera_bind_group_layout.map(|skinned_camera_bind_group_layout| {
+                Self::create_pipeline(
+                    device,
+                    skinned_camera_bind_group_layout,
+                    &material_bind_group_layout,
+                    &light_bind_group_layout,
+                    shadow_bind_group_layout,
+                    true,
+                    sample_count,
+                )
+            });
         let light_bind_group =
             Self::create_light_bind_group(device, light_buffer, &light_bind_group_layout);
         Self {
             pipeline,
+            skinned_pipeline,
             material_bind_group_layout,
             light_bind_group_layout,
             light_bind_group,
         }
     }
 
+    /// recreates the pipelines for color and depth targets with another sample count, the bind group
+    /// layouts and with them the material bind groups stay valid
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        skinned_camera_bind_group_layout: Option<&wgpu::BindGroupLayout>,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) {
+        let create_pipeline = |camera_bind_group_layout, skinned| {
+            Self::create_pipeline(
+                device,
+                camera_bind_group_layout,
+                &self.material_bind_group_layout,
+                &self.light_bind_group_layout,
+                shadow_bind_group_layout,
+                skinned,
+                sample_count,
+            )
+        };
+        self.pipeline = create_pipeline(camera_bind_group_layout, false);
+        self.skinned_pipeline =
+            skinned_camera_bind_group_layout.map(|layout| create_pipeline(layout, true));
+    }
+
     fn create_render_pass<'a>(
         &self,
         encoder: &'a mut wgpu::CommandEncoder,
         color_view: &'a wgpu::TextureView,
+        resolve_view: Option<&'a wgpu::TextureView>,
         depth_view: &'a wgpu::TextureView,
     ) -> wgpu::RenderPass<'a> {
         let color_attachment = Some(wgpu::RenderPassColorAttachment {
             view: &color_view,
-            resolve_target: None,
+            resolve_target: resolve_view,
             ops: wgpu::Operations {
                 load: wgpu::LoadOp::Clear(wgpu::Color {
                     r: 0.1,
@@ -222,7 +335,8 @@ impl OpaquePipeline {
         let depth_stencil_attachment = wgpu::RenderPassDepthStencilAttachment {
             view: depth_view,
             depth_ops: Some(wgpu::Operations {
-                load: wgpu::LoadOp::Clear(1.0),
+                // reversed depth, 0 is the far plane
+                load: wgpu::LoadOp::Clear(0.0),
                 store: wgpu::StoreOp::Store,
             }),
             stencil_ops: None,
@@ -240,35 +354,56 @@ impl OpaquePipeline {
     pub fn render(
         &self,
         // Use Vec because MyMesh is not hashable, use Arc because it has to move to a new container to mismatch with instances
-        renderables: &Vec<(Arc<MyMesh>, Arc<Vec<ModelInstance>>)>,
+        renderables: &Vec<(Arc<MyMesh>, Arc<Vec<ModelInstanceRaw>>)>,
         encoder: &mut wgpu::CommandEncoder,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         color_view: &wgpu::TextureView,
+        // the single sampled target when color_view is multisampled
+        resolve_view: Option<&wgpu::TextureView>,
         depth_view: &wgpu::TextureView,
         camera_bind_group: &wgpu::BindGroup,
-    ) {
+        // the camera and the joint matrices, for skinned_pipeline
+        skinned_camera_bind_group: Option<&wgpu::BindGroup>,
+        shadow_bind_group: &wgpu::BindGroup,
+        frustum: &Frustum,
+        instance_arena: &mut BufferArena,
+    ) -> CullStats {
+        let mut cull_stats = CullStats::default();
         // begin render pass
-        let mut render_pass = self.create_render_pass(encoder, color_view, depth_view);
-        render_pass.set_pipeline(&self.pipeline);
+        let mut render_pass =
+            self.create_render_pass(encoder, color_view, resolve_view, depth_view);
         //needs a texture bind group from the model
-        render_pass.set_bind_group(0, camera_bind_group, &[]);
         render_pass.set_bind_group(2, &self.light_bind_group, &[]);
+        render_pass.set_bind_group(3, shadow_bind_group, &[]);
         for (mesh, instances) in renderables.iter() {
+            let instances = cull_instances(frustum, mesh, instances, &mut cull_stats);
+            if instances.is_empty() {
+                continue;
+            }
+            let skinned_pipeline = self
+                .skinned_pipeline
+                .as_ref()
+                .zip(skinned_camera_bind_group);
+            match (&mesh.skin_buffer, skinned_pipeline) {
+                (Some(skin_buffer), Some((skinned_pipeline, skinned_camera_bind_group))) => {
+                    render_pass.set_pipeline(skinned_pipeline);
+                    render_pass.set_bind_group(0, skinned_camera_bind_group, &[]);
+                    render_pass.set_vertex_buffer(2, skin_buffer.slice(..));
+                }
+                _ => {
+                    render_pass.set_pipeline(&self.pipeline);
+                    render_pass.set_bind_group(0, camera_bind_group, &[]);
+                }
+            }
             render_pass.set_bind_group(1, mesh.material_bind_group.as_ref(), &[]);
             render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            let instance_data = instances
-                .iter()
-                .map(|instance| instance.to_raw())
-                .collect::<Vec<_>>();
-            let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&instance_data),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+            let instance_allocation =
+                instance_arena.alloc(device, queue, bytemuck::cast_slice(&instances));
+            render_pass.set_vertex_buffer(1, instance_arena.slice(&instance_allocation));
             render_pass.draw_indexed(0..mesh.num_indices, 0, 0..instances.len() as u32);
         }
+        cull_stats
     }
 }
@@ -0,0 +1,177 @@
+// arcball style camera that circles around a target point: dragging with the right mouse button rotates,
+// dragging with the middle button pans the target and the scroll wheel zooms. the input moves goal values
+// and the visible values follow them smoothly. it is a Camera on its own, and apply_to copies it into a
+// MyCamera for the code that drives State::camera
+
+use cgmath::{InnerSpace, Matrix4, Point3, Vector3};
+
+use crate::{
+    bounds::BoundingSphere,
+    camera::{Camera, Projection},
+    input_context::InputContext,
+    model_data::ModelData,
+    model_instance::ModelInstance,
+    my_camera::MyCamera,
+};
+
+pub struct OrbitCamera {
+    pub target: Point3<f32>,
+    pub distance: f32,
+    // degrees around the y axis, 0 looks from +z towards -z
+    pub azimuth: f32,
+    // degrees above the target
+    pub elevation: f32,
+    // the values the ones above move towards
+    pub goal_target: Point3<f32>,
+    pub goal_distance: f32,
+    pub goal_azimuth: f32,
+    pub goal_elevation: f32,
+    // degrees per pixel
+    pub rotate_sensitivity: f32,
+    // fraction of the distance per pixel, so panning feels the same at any zoom
+    pub pan_sensitivity: f32,
+    // fraction of the distance per scroll line
+    pub zoom_sensitivity: f32,
+    // how fast the values reach their goals, per second. 0 or less jumps right to them
+    pub smoothing: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    // degrees, keeps the camera from flipping over at the poles
+    pub max_elevation: f32,
+    pub projection: Projection,
+}
+
+impl OrbitCamera {
+    pub fn new(target: Point3<f32>, distance: f32) -> Self {
+        Self {
+            target,
+            distance,
+            azimuth: 0.0,
+            elevation: 0.0,
+            goal_target: target,
+            goal_distance: distance,
+            goal_azimuth: 0.0,
+            goal_elevation: 0.0,
+            rotate_sensitivity: 0.3,
+            pan_sensitivity: 0.002,
+            zoom_sensitivity: 0.1,
+            smoothing: 15.0,
+            min_distance: 0.05,
+            max_distance: 1000.0,
+            max_elevation: 89.0,
+            projection: Projection::default(),
+        }
+    }
+
+    /// unit vector from the target to the camera
+    pub fn direction(&self) -> Vector3<f32> {
+        let (azimuth, elevation) = (self.azimuth.to_radians(), self.elevation.to_radians());
+        Vector3::new(
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            azimuth.cos() * elevation.cos(),
+        )
+    }
+
+    /// jumps to the goal values without smoothing
+    pub fn snap(&mut self) {
+        self.target = self.goal_target;
+        self.distance = self.goal_distance;
+        self.azimuth = self.goal_azimuth;
+        self.elevation = self.goal_elevation;
+    }
+
+    /// reads the mouse and moves towards the goals, delta_time is the frame time in seconds
+    pub fn update(&mut self, input_context: &mut InputContext, delta_time: f32) {
+        let (delta_x, delta_y) = input_context.device_mouse_delta_accumulated();
+        let (delta_x, delta_y) = (delta_x as f32, delta_y as f32);
+        if input_context.mouse_right() {
+            // the model follows the mouse, so the camera moves the other way
+            self.goal_azimuth -= delta_x * self.rotate_sensitivity;
+            self.goal_elevation = (self.goal_elevation + delta_y * self.rotate_sensitivity)
+                .clamp(-self.max_elevation, self.max_elevation);
+        }
+        if input_context.mouse_middle() {
+            let forward = -self.direction();
+            let right = forward.cross(Vector3::unit_y()).normalize();
+            let up = right.cross(forward);
+            let scale = self.pan_sensitivity * self.goal_distance;
+            // moving the mouse down gives a positive delta
+            self.goal_target += (up * delta_y - right * delta_x) * scale;
+        }
+        let scroll = input_context.scroll_delta_accumulated();
+        if scroll != 0.0 {
+            self.goal_distance = (self.goal_distance * (-scroll * self.zoom_sensitivity).exp())
+                .clamp(self.min_distance, self.max_distance);
+        }
+
+        if self.smoothing <= 0.0 {
+            self.snap();
+            return;
+        }
+        // frame rate independent exponential approach
+        let t = 1.0 - (-self.smoothing * delta_time).exp();
+        self.target += (self.goal_target - self.target) * t;
+        self.distance += (self.goal_distance - self.distance) * t;
+        self.azimuth += (self.goal_azimuth - self.azimuth) * t;
+        self.elevation += (self.goal_elevation - self.elevation) * t;
+    }
+
+    /// moves the goals so the whole sphere is visible from the current direction.
+    /// orthographic projections get a new height instead, the distance only keeps the sphere in front of znear
+    pub fn frame_sphere(&mut self, sphere: BoundingSphere, aspect: f32) {
+        // a little margin around the sphere
+        let radius = sphere.radius * 1.1;
+        let distance = match &mut self.projection {
+            Projection::Perspective { fovy, .. }
+            | Projection::ReversedInfinitePerspective { fovy, .. } => {
+                // the narrower of the two fields of view decides
+                let half_fovy = (*fovy / 2.0).to_radians();
+                let half_fovx = (half_fovy.tan() * aspect).atan();
+                radius / half_fovy.min(half_fovx).sin()
+            }
+            Projection::Orthographic { height, znear, .. } => {
+                *height = 2.0 * radius / aspect.min(1.0);
+                radius + *znear
+            }
+        };
+        self.goal_target = sphere.center;
+        self.goal_distance = distance.clamp(self.min_distance, self.max_distance);
+    }
+
+    /// frames the bounding sphere of the model placed by the instance
+    pub fn frame_model(&mut self, model_data: &ModelData, instance: &ModelInstance, aspect: f32) {
+        let sphere = model_data
+            .bounding_sphere
+            .transform(&instance.model_matrix());
+        self.frame_sphere(sphere, aspect);
+    }
+
+    /// places the camera at the orbit position looking at the target, with the same projection
+    pub fn apply_to(&self, camera: &mut MyCamera) {
+        let forward = -self.direction();
+        camera.pos = self.position();
+        camera.yaw = forward.z.atan2(forward.x).to_degrees();
+        camera.pitch = forward.y.asin().to_degrees();
+        camera.projection = self.projection;
+    }
+}
+
+impl Camera for OrbitCamera {
+    fn build_view_matrix(&self) -> Matrix4<f32> {
+        // up is world up, the elevation never reaches the poles
+        Matrix4::look_at_rh(self.position(), self.target, Vector3::unit_y())
+    }
+    fn projection(&self) -> Projection {
+        self.projection
+    }
+    fn position(&self) -> Point3<f32> {
+        self.target + self.direction() * self.distance
+    }
+}
+
+impl Default for OrbitCamera {
+    fn default() -> Self {
+        Self::new(Point3::new(0.0, 0.0, 0.0), 10.0)
+    }
+}
@@ -0,0 +1,314 @@
+// Vertex shader
+struct CameraUniform{
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    camera_pos: vec4<f32>,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    // first joint matrix of the instance, only used by skinned meshes
+    @location(9) joint_offset: u32,
+};
+
+@group(0) @binding(0) // 1.
+var<uniform> camera: CameraUniform;
+// joint matrices of every skinned instance of the frame, only bound for the skinned pipelines
+@group(0) @binding(1)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+// kind: 0 directional, 1 point, 2 spot
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    cos_inner_cone: f32,
+    cos_outer_cone: f32,
+    // first layer in shadow_maps, -1 without shadows
+    shadow_layer: i32,
+}
+struct Lights {
+    count: u32,
+    lights: array<Light>,
+}
+@group(2) @binding(0)
+var<storage, read> lights: Lights;
+
+
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    // w is the handedness of the bitangent, the tangent is zero if the mesh has none
+    @location(3) tangent: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) world_tangent: vec4<f32>,
+};
+
+struct SkinInput {
+    @location(10) joints: vec4<u32>,
+    @location(11) weights: vec4<f32>,
+};
+
+fn instance_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+// blend of the joint matrices that move the vertex from its rest pose into the space of the model
+fn skin_matrix(skin: SkinInput, joint_offset: u32) -> mat4x4<f32> {
+    return joint_matrices[joint_offset + skin.joints.x] * skin.weights.x
+        + joint_matrices[joint_offset + skin.joints.y] * skin.weights.y
+        + joint_matrices[joint_offset + skin.joints.z] * skin.weights.z
+        + joint_matrices[joint_offset + skin.joints.w] * skin.weights.w;
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    return transform_vertex(model, instance_matrix(instance));
+}
+
+@vertex
+fn vs_skinned(
+    model: VertexInput,
+    instance: InstanceInput,
+    skin: SkinInput,
+) -> VertexOutput {
+    return transform_vertex(model, instance_matrix(instance) * skin_matrix(skin, instance.joint_offset));
+}
+
+fn transform_vertex(model: VertexInput, model_matrix: mat4x4<f32>) -> VertexOutput {
+    let normal_matrix = mat3x3<f32>(
+        model_matrix[0].xyz,
+        model_matrix[1].xyz,
+        model_matrix[2].xyz,
+    );
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.world_normal = normal_matrix * model.normal;
+    out.world_tangent = vec4<f32>(normal_matrix * model.tangent.xyz, model.tangent.w);
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.projection * camera.view * world_position; // 2.
+    return out;
+}
+
+@group(1) @binding(0)
+var t_base_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_material: sampler;
+struct Material {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec3<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    opacity: f32,
+}
+@group(1) @binding(2)
+var<uniform> material: Material;
+@group(1) @binding(3)
+var t_metallic_roughness: texture_2d<f32>;
+@group(1) @binding(4)
+var t_normal: texture_2d<f32>;
+@group(1) @binding(5)
+var t_occlusion: texture_2d<f32>;
+@group(1) @binding(6)
+var t_emissive: texture_2d<f32>;
+
+struct Shadows {
+    light_view_proj: array<mat4x4<f32>, 12>,
+    cascade_splits: vec4<f32>,
+    cascade_count: u32,
+    depth_bias: f32,
+    slope_bias: f32,
+    texel_size: f32,
+}
+@group(3) @binding(0)
+var shadow_maps: texture_depth_2d_array;
+@group(3) @binding(1)
+var shadow_sampler: sampler_comparison;
+@group(3) @binding(2)
+var<uniform> shadows: Shadows;
+
+// fraction of the 3x3 pcf kernel around the position that is lit, 1 outside of the shadow map
+fn shadow_factor(layer: i32, world_position: vec3<f32>, normal: vec3<f32>, light_dir: vec3<f32>) -> f32 {
+    let clip = shadows.light_view_proj[layer] * vec4<f32>(world_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, -ndc.y * 0.5 + 0.5);
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    let bias = max(shadows.slope_bias * (1.0 - dot(normal, light_dir)), shadows.depth_bias);
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadows.texel_size;
+            lit += textureSampleCompareLevel(shadow_maps, shadow_sampler, uv + offset, layer, ndc.z - bias);
+        }
+    }
+    return lit / 9.0;
+}
+
+// the cascade that covers the view space distance of the position, or cascade_count if none does
+fn cascade_index(world_position: vec3<f32>) -> u32 {
+    let view_distance = -(camera.view * vec4<f32>(world_position, 1.0)).z;
+    var cascade = 0u;
+    for (var i = 0u; i < shadows.cascade_count; i++) {
+        if view_distance > shadows.cascade_splits[i] {
+            cascade = i + 1u;
+        }
+    }
+    return cascade;
+}
+
+// smooth falloff that reaches zero at the range of the light
+fn attenuation(distance: f32, range: f32) -> f32 {
+    let ratio = distance / max(range, 0.0001);
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window / (distance * distance + 1.0);
+}
+
+const PI: f32 = 3.14159265359;
+
+// normal mapping with the TBN matrix built from the vertex tangent. meshes without tangents fall back to a
+// tangent frame from the screen space derivatives of the position and the texture coordinates
+fn perturb_normal(in: VertexOutput, normal: vec3<f32>) -> vec3<f32> {
+    // derivatives have to be taken in uniform control flow, so the fallback frame is always computed
+    let dp1 = dpdx(in.world_position);
+    let dp2 = dpdy(in.world_position);
+    let duv1 = dpdx(in.tex_coords);
+    let duv2 = dpdy(in.tex_coords);
+    let dp2perp = cross(dp2, normal);
+    let dp1perp = cross(normal, dp1);
+    let derivative_tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    let derivative_bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+    let scale = inverseSqrt(max(max(dot(derivative_tangent, derivative_tangent), dot(derivative_bitangent, derivative_bitangent)), 1e-12));
+    let tangent_normal = textureSample(t_normal, s_material, in.tex_coords).xyz * 2.0 - 1.0;
+
+    var tbn = mat3x3<f32>(derivative_tangent * scale, derivative_bitangent * scale, normal);
+    if dot(in.world_tangent.xyz, in.world_tangent.xyz) > 1e-8 {
+        // Gram-Schmidt, the interpolated tangent is no longer orthogonal to the normal
+        let tangent = normalize(in.world_tangent.xyz - normal * dot(normal, in.world_tangent.xyz));
+        let bitangent = cross(normal, tangent) * in.world_tangent.w;
+        tbn = mat3x3<f32>(tangent, bitangent, normal);
+    }
+    return normalize(tbn * tangent_normal);
+}
+
+// GGX / Trowbridge-Reitz normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denominator = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denominator * denominator);
+}
+
+// Smith geometry term with the Schlick-GGX approximation for direct lighting
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let ggx_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let ggx_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return ggx_v * ggx_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// entry points can not call each other, so the lighting lives in a helper
+fn shade(in: VertexOutput) -> vec4<f32> {
+    // all texture samples happen before the light loop, where control flow is still uniform
+    let base_color = textureSample(t_base_color, s_material, in.tex_coords) * material.base_color_factor;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_material, in.tex_coords);
+    let metallic = clamp(metallic_roughness.b * material.metallic_factor, 0.0, 1.0);
+    let roughness = clamp(metallic_roughness.g * material.roughness_factor, 0.04, 1.0);
+    let occlusion = textureSample(t_occlusion, s_material, in.tex_coords).r;
+    let emissive = textureSample(t_emissive, s_material, in.tex_coords).rgb * material.emissive_factor;
+    let normal = perturb_normal(in, normalize(in.world_normal));
+
+    let view_dir = normalize(camera.camera_pos.xyz - in.world_position);
+    let n_dot_v = max(dot(normal, view_dir), 1e-4);
+    // dielectrics reflect 4%, metals reflect their base color
+    let f0 = mix(vec3<f32>(0.04), base_color.rgb, metallic);
+    var result = vec3<f32>(0.0);
+
+    let light_count = min(lights.count, arrayLength(&lights.lights));
+    for (var i = 0u; i < light_count; i++) {
+        let light = lights.lights[i];
+        var light_dir: vec3<f32>;
+        var strength = light.intensity;
+        if light.kind == 0u {
+            light_dir = normalize(-light.direction);
+        } else {
+            let to_light = light.position - in.world_position;
+            let distance = length(to_light);
+            light_dir = to_light / max(distance, 0.0001);
+            strength *= attenuation(distance, light.range);
+            if light.kind == 2u {
+                let cos_angle = dot(-light_dir, normalize(light.direction));
+                strength *= smoothstep(light.cos_outer_cone, light.cos_inner_cone, cos_angle);
+            }
+        }
+        let n_dot_l = max(dot(normal, light_dir), 0.0);
+        if n_dot_l <= 0.0 || strength <= 0.0 {
+            continue;
+        }
+        if light.shadow_layer >= 0 {
+            if light.kind == 0u {
+                let cascade = cascade_index(in.world_position);
+                if cascade < shadows.cascade_count {
+                    let layer = light.shadow_layer + i32(cascade);
+                    strength *= shadow_factor(layer, in.world_position, normal, light_dir);
+                }
+            } else {
+                strength *= shadow_factor(light.shadow_layer, in.world_position, normal, light_dir);
+            }
+        }
+
+        // Cook-Torrance
+        let half_dir = normalize(view_dir + light_dir);
+        let n_dot_h = max(dot(normal, half_dir), 0.0);
+        let d = distribution_ggx(n_dot_h, roughness);
+        let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+        let f = fresnel_schlick(max(dot(half_dir, view_dir), 0.0), f0);
+        let specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 1e-4);
+        let diffuse = (1.0 - f) * (1.0 - metallic) * base_color.rgb / PI;
+        result += (diffuse + specular) * light.color * strength * n_dot_l;
+    }
+
+    // We don't need (or want) much ambient light, so 0.03 is fine
+    let ambient = vec3<f32>(0.03) * base_color.rgb * occlusion;
+    return vec4<f32>(result + ambient + emissive, base_color.a);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return shade(in);
+}
+
+// same lighting, the alpha is scaled by the opacity of the material for blending
+@fragment
+fn fs_transparent(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    return vec4<f32>(color.xyz, color.a * material.opacity);
+}
\ No newline at end of file
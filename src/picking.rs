@@ -0,0 +1,79 @@
+// finds the model instance under a ray, usually the one under the cursor, see Camera::screen_ray.
+// State::update requests a pick on every left click, RenderContext::render resolves it into State::pick_results.
+// every mesh is tested with its aabb first and then with its triangle bvh. meshes without a bvh (skinned and
+// morphed ones) are hit by their aabb in the rest pose
+
+use std::collections::HashMap;
+
+use cgmath::{Point3, SquareMatrix};
+
+use crate::{
+    animation::AnimationPlayer, bounds::Ray, model_data::ModelData, model_instance::ModelInstance,
+    model_meta::ModelMeta,
+};
+
+#[derive(Debug, Clone)]
+pub struct PickHit {
+    pub model_meta: ModelMeta,
+    // index into the instances of model_meta
+    pub instance_index: usize,
+    // index into ModelData::meshes
+    pub mesh_index: usize,
+    // index of the triangle in the mesh, None if the mesh has no bvh
+    pub triangle: Option<usize>,
+    // world space
+    pub point: Point3<f32>,
+    // from the origin of the ray
+    pub distance: f32,
+}
+
+/// the nearest hit of the ray among the instances of the models
+pub fn pick<'a>(
+    ray: &Ray,
+    models: impl IntoIterator<Item = (&'a ModelMeta, &'a ModelData, &'a [ModelInstance])>,
+    animation_players: &HashMap<String, AnimationPlayer>,
+) -> Option<PickHit> {
+    let mut nearest: Option<PickHit> = None;
+    for (model_meta, model_data, instances) in models {
+        let skeleton = &model_data.skeleton;
+        for (instance_index, instance) in instances.iter().enumerate() {
+            // rigid meshes follow the pose of the animation like in RenderContext::prepare_instances
+            let animation_player = instance.find_animation_player(animation_players);
+            let global_transforms = animation_player.map(|player| {
+                skeleton.global_transforms(&player.sample_pose(skeleton, &model_data.animations))
+            });
+            let model_matrix = instance.model_matrix();
+            for (mesh_index, mesh) in model_data.meshes().enumerate() {
+                let mesh_transform = match (&global_transforms, &mesh.skin_buffer) {
+                    (Some(global_transforms), None) => global_transforms[mesh.node_index],
+                    _ => mesh.transform,
+                };
+                let Some(inverse) = (model_matrix * mesh_transform).invert() else {
+                    continue;
+                };
+                // t stays the world space distance in the space of the mesh
+                let local_ray = ray.transform(&inverse);
+                let max_t = nearest.as_ref().map_or(f32::INFINITY, |hit| hit.distance);
+                let Some(aabb_t) = mesh.aabb.intersect_ray(&local_ray, max_t) else {
+                    continue;
+                };
+                let (distance, triangle) = match &mesh.bvh {
+                    Some(bvh) => match bvh.intersect(&local_ray, max_t) {
+                        Some((t, triangle)) => (t, Some(triangle)),
+                        None => continue,
+                    },
+                    None => (aabb_t, None),
+                };
+                nearest = Some(PickHit {
+                    model_meta: model_meta.clone(),
+                    instance_index,
+                    mesh_index,
+                    triangle,
+                    point: ray.at(distance),
+                    distance,
+                });
+            }
+        }
+    }
+    nearest
+}
@@ -1,38 +1,86 @@
 use std::{collections::HashMap, mem, sync::Arc};
 
+use image::RgbaImage;
 use tokio::runtime::Runtime;
 use wgpu::{CompositeAlphaMode, util::DeviceExt};
 use winit::window::Window;
 
 use crate::{
-    cache::{CACHE, CacheKey, CacheValue},
+    animation::{AnimationPlayer, MAX_JOINT_MATRICES},
+    asset_server::{AssetServer, AssetState},
     camera_uniform::CameraUniform,
-    light_uniform::LightUniform,
-    model_data::MyMesh,
-    model_instance::ModelInstance,
+    frame_pacer::{FramePacer, PresentSettings},
+    light_uniform::{LightsHeader, MAX_LIGHTS},
+    model_data::{ModelData, MyMesh},
+    model_instance::{ModelInstance, ModelInstanceRaw},
+    morph_pipeline::MorphPipeline,
     my_texture::MyTexture,
     opaque_pipeline::OpaquePipeline,
+    picking::pick,
+    shadow_pipeline::{ShadowPipeline, ShadowSettings},
     state::State,
+    tonemap_pipeline::TonemapPipeline,
+    transparent_pipeline::TransparentPipeline,
     ui_pipeline::UIPipeline,
 };
 
+// everything drawn this frame, filled by prepare_instances
+#[derive(Default)]
+struct FrameRenderables {
+    opaque_meshes: Vec<(Arc<MyMesh>, Arc<Vec<ModelInstanceRaw>>)>,
+    transparent_meshes: Vec<(Arc<MyMesh>, Arc<Vec<ModelInstanceRaw>>)>,
+    joint_matrices: Vec<[[f32; 4]; 4]>,
+}
+
 pub struct RenderContext {
-    pub surface: wgpu::Surface<'static>,
+    // None when rendering headless into offscreen_target
+    pub surface: Option<wgpu::Surface<'static>>,
+    pub offscreen_target: Option<MyTexture>,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
+    // present mode and frames in flight, applied at the start of the next render when they change
+    pub present_settings: PresentSettings,
+    // the settings the surface and frame_pacer were configured with
+    applied_present_settings: PresentSettings,
+    // the present modes the surface supports, empty for headless contexts
+    pub present_modes: Vec<wgpu::PresentMode>,
 
     pub camera_buffer: wgpu::Buffer,
     // most pipelines will use this
     pub camera_bind_group_layout: wgpu::BindGroupLayout,
     pub camera_bind_group: wgpu::BindGroup,
+    // joint matrices of the skinned instances, bound next to the camera for the skinned pipelines only
+    pub joint_buffer: wgpu::Buffer,
+    // the camera and the joint matrices, None if the adapter has no storage buffers in the vertex stage
+    pub skinned_camera_bind_group_layout: Option<wgpu::BindGroupLayout>,
+    pub skinned_camera_bind_group: Option<wgpu::BindGroup>,
     // light stuff
     pub light_buffer: wgpu::Buffer,
+    // msaa samples of the color and depth targets, changed with set_sample_count
+    sample_count: u32,
+    // the sample counts set_sample_count accepts on this adapter
+    pub supported_sample_counts: Vec<u32>,
     pub depth_texture: MyTexture,
+    // the opaque and transparent passes render into it, the tonemap pass reads it
+    pub hdr_texture: MyTexture,
+    // with msaa the passes draw into these and resolve into hdr_texture and the swapchain
+    pub msaa_hdr_texture: Option<MyTexture>,
+    pub msaa_target_texture: Option<MyTexture>,
 
+    // runs before the shadow pass
+    pub morph_pipeline: MorphPipeline,
+    pub shadow_pipeline: ShadowPipeline,
     pub opaque_pipeline: OpaquePipeline,
+    pub transparent_pipeline: TransparentPipeline,
+    // writes hdr_texture to the swapchain
+    pub tonemap_pipeline: TonemapPipeline,
     pub ui_pipeline: UIPipeline,
+    // the resources rewritten every frame, one copy per frame in flight
+    pub frame_pacer: FramePacer,
+    // models are decoded in the background and only drawn once they are ready
+    pub asset_server: AssetServer,
 }
 
 impl RenderContext {
@@ -60,7 +108,9 @@ impl RenderContext {
         let (device, queue) = runtime
             .block_on(adapter.request_device(
                 &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::empty(),
+                    // msaa sample counts other than 1 and 4, see supported_sample_counts
+                    required_features: adapter.features()
+                        & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
                     required_limits: wgpu::Limits::default(),
                     label: None,
                     memory_hints: Default::default(),
@@ -70,29 +120,131 @@ impl RenderContext {
             .unwrap();
 
         let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result in all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
-        // let surface_format = surface_caps
-        //     .formats
-        //     .iter()
-        //     .find(|f| f.is_srgb())
-        //     .copied()
-        //     .unwrap_or(surface_caps.formats[0]);
-        let surface_format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        // the tonemap and ui passes write linear colors and expect the target to encode them to srgb.
+        // a surface without an srgb format is rendered through an srgb view of its format instead
+        let surface_format = surface_caps
+            .formats
+            .iter()
+            .find(|f| f.is_srgb())
+            .copied()
+            .unwrap_or(surface_caps.formats[0]);
+        let view_formats = match surface_format.add_srgb_suffix() {
+            view_format if view_format != surface_format => vec![view_format],
+            _ => vec![],
+        };
+        // vsync with fifo until present_settings asks for something else, every surface supports it
+        let present_settings = PresentSettings::default();
         // define how the surface creates its underlying SurfaceTextures
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width,
             height: size.height,
-            // enable vsync with fifo present mode
+            present_mode: present_settings.present_mode,
+            alpha_mode: CompositeAlphaMode::Opaque,
+            view_formats,
+            desired_maximum_frame_latency: present_settings.max_frames_in_flight,
+        };
+
+        Self::from_device(
+            device,
+            queue,
+            config,
+            size,
+            Some(surface),
+            &adapter,
+            None,
+        )
+    }
+
+    /// creates a render context without a window, the frame is rendered into an offscreen texture
+    /// of the given size and can be read back with read_frame.
+    /// returns None if no adapter (not even a software one) is available
+    pub fn new_headless(width: u32, height: u32) -> Option<Self> {
+        let size = winit::dpi::PhysicalSize::new(width, height);
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+        let runtime = Runtime::new().unwrap();
+        // prefer a real GPU, fall back to a software adapter (e.g. llvmpipe / WARP) on build machines
+        let adapter = runtime
+            .block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            }))
+            .or_else(|| {
+                runtime.block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: None,
+                    force_fallback_adapter: true,
+                }))
+            })?;
+
+        let (device, queue) = runtime
+            .block_on(adapter.request_device(
+                &wgpu::DeviceDescriptor {
+                    // msaa sample counts other than 1 and 4, see supported_sample_counts
+                    required_features: adapter.features()
+                        & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+                    required_limits: wgpu::Limits::downlevel_defaults(),
+                    label: None,
+                    memory_hints: Default::default(),
+                },
+                None, // Trace path
+            ))
+            .ok()?;
+
+        // there is no surface to configure, the pipelines only read the format and size from this
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: size.width,
+            height: size.height,
             present_mode: wgpu::PresentMode::Fifo,
             alpha_mode: CompositeAlphaMode::Opaque,
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
+        let offscreen_target = MyTexture::create_offscreen_target_texture(
+            &device,
+            config.width,
+            config.height,
+            config.format,
+            "offscreen target",
+        );
+        Some(Self::from_device(
+            device,
+            queue,
+            config,
+            size,
+            None,
+            &adapter,
+            Some(offscreen_target),
+        ))
+    }
 
+    fn from_device(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        size: winit::dpi::PhysicalSize<u32>,
+        surface: Option<wgpu::Surface<'static>>,
+        adapter: &wgpu::Adapter,
+        offscreen_target: Option<MyTexture>,
+    ) -> Self {
+        let present_modes = surface.as_ref().map_or(Vec::new(), |surface| {
+            surface.get_capabilities(adapter).present_modes
+        });
+        let supported_sample_counts = Self::supported_sample_counts(
+            adapter,
+            &[
+                MyTexture::HDR_FORMAT,
+                MyTexture::DEPTH_FORMAT,
+                config.format.add_srgb_suffix(),
+            ],
+        );
         let camera_uniform = CameraUniform::default();
 
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -100,20 +252,30 @@ impl RenderContext {
             contents: bytemuck::cast_slice(&[camera_uniform]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
-        let depth_texture = MyTexture::create_depth_texture(&device, &config, "depth texture");
+        let depth_texture = MyTexture::create_depth_texture(&device, &config, 1, "depth texture");
+        let hdr_texture = MyTexture::create_hdr_texture(&device, &config, "hdr texture");
+
+        // storage buffer for up to MAX_JOINT_MATRICES joint matrices, filled every frame
+        let joint_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Joint Buffer"),
+            size: (MAX_JOINT_MATRICES * size_of::<[[f32; 4]; 4]>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
+        let camera_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
+                entries: &[camera_entry],
                 label: Some("view_bind_group_layout"),
             });
         let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -124,69 +286,451 @@ impl RenderContext {
             }],
             label: Some("camera_bind_group"),
         });
-        let light_uniform = LightUniform {
-            position: [2.0, 2.0, 2.0],
-            _padding: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
-        };
-        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Light VB"),
-            contents: bytemuck::cast_slice(&[light_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        // the skinned pipelines take the place of the camera group with this one. a group of its own for the
+        // joint matrices would be a fifth group in the opaque pass, more than the default limits allow
+        let vertex_storage = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::VERTEX_STORAGE);
+        if !vertex_storage {
+            log::warn!(
+                "no storage buffers in the vertex stage, skinned meshes are drawn in their rest pose"
+            );
+        }
+        let skinned_camera_bind_group_layout = vertex_storage.then(|| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    camera_entry,
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("skinned_view_bind_group_layout"),
+            })
         });
-        let opaque_pipeline =
-            OpaquePipeline::new(&device, &config, &camera_bind_group_layout, &light_buffer);
-        let ui_pipeline = UIPipeline::new(&device, &config);
+        let skinned_camera_bind_group = skinned_camera_bind_group_layout.as_ref().map(|layout| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: camera_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: joint_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("skinned_camera_bind_group"),
+            })
+        });
+        // storage buffer for up to MAX_LIGHTS lights, filled every frame
+        let light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Buffer"),
+            size: LightsHeader::buffer_size(),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let shadow_pipeline = ShadowPipeline::new(
+            &device,
+            ShadowSettings::default(),
+            skinned_camera_bind_group_layout.as_ref(),
+        );
+        let opaque_pipeline = OpaquePipeline::new(
+            &device,
+            &camera_bind_group_layout,
+            skinned_camera_bind_group_layout.as_ref(),
+            &light_buffer,
+            &shadow_pipeline.shadow_bind_group_layout,
+            1,
+        );
+        let transparent_pipeline = TransparentPipeline::new(
+            &device,
+            &camera_bind_group_layout,
+            skinned_camera_bind_group_layout.as_ref(),
+            &opaque_pipeline,
+            &shadow_pipeline.shadow_bind_group_layout,
+            1,
+        );
+        let tonemap_pipeline = TonemapPipeline::new(&device, &config, &hdr_texture, 1);
+        let ui_pipeline = UIPipeline::new(&device, &config, 1);
+        let morph_pipeline = MorphPipeline::new(&device);
+        let present_settings = PresentSettings {
+            present_mode: config.present_mode,
+            max_frames_in_flight: config.desired_maximum_frame_latency,
+        };
+        let frame_pacer = FramePacer::new(config.desired_maximum_frame_latency as usize);
         RenderContext {
             surface,
+            offscreen_target,
             device,
             queue,
             config,
             size,
+            applied_present_settings: present_settings.clone(),
+            present_settings,
+            present_modes,
             camera_buffer,
             camera_bind_group_layout,
             camera_bind_group,
+            joint_buffer,
+            skinned_camera_bind_group_layout,
+            skinned_camera_bind_group,
+            sample_count: 1,
+            supported_sample_counts,
             depth_texture,
+            hdr_texture,
+            msaa_hdr_texture: None,
+            msaa_target_texture: None,
             light_buffer,
+            morph_pipeline,
+            shadow_pipeline,
             opaque_pipeline,
+            transparent_pipeline,
+            tonemap_pipeline,
             ui_pipeline,
+            frame_pacer,
+            asset_server: AssetServer::new(),
         }
     }
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.size = new_size;
         self.config.width = new_size.width;
         self.config.height = new_size.height;
-        self.surface.configure(&self.device, &self.config);
-        self.depth_texture =
-            MyTexture::create_depth_texture(&self.device, &self.config, "depth texture");
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.config),
+            None => {
+                self.offscreen_target = Some(MyTexture::create_offscreen_target_texture(
+                    &self.device,
+                    self.config.width,
+                    self.config.height,
+                    self.config.format,
+                    "offscreen target",
+                ));
+            }
+        }
+        self.create_render_targets();
+    }
+
+    // the targets that have the size of the screen, with the current sample count
+    fn create_render_targets(&mut self) {
+        self.depth_texture = MyTexture::create_depth_texture(
+            &self.device,
+            &self.config,
+            self.sample_count,
+            "depth texture",
+        );
+        self.hdr_texture = MyTexture::create_hdr_texture(&self.device, &self.config, "hdr texture");
+        self.tonemap_pipeline.resize(&self.device, &self.hdr_texture);
+        let create_msaa_texture = |format, label| {
+            (self.sample_count > 1).then(|| {
+                MyTexture::create_msaa_texture(
+                    &self.device,
+                    &self.config,
+                    format,
+                    self.sample_count,
+                    label,
+                )
+            })
+        };
+        self.msaa_hdr_texture = create_msaa_texture(MyTexture::HDR_FORMAT, "msaa hdr texture");
+        self.msaa_target_texture =
+            create_msaa_texture(self.config.format.add_srgb_suffix(), "msaa target texture");
+    }
+
+    // the sample counts out of 1, 2, 4 and 8 that every format supports. without
+    // TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES the device only allows 1 and 4
+    fn supported_sample_counts(
+        adapter: &wgpu::Adapter,
+        formats: &[wgpu::TextureFormat],
+    ) -> Vec<u32> {
+        let adapter_specific = adapter
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        [1, 2, 4, 8]
+            .into_iter()
+            .filter(|&count| adapter_specific || count == 1 || count == 4)
+            .filter(|&count| {
+                formats.iter().all(|format| {
+                    adapter
+                        .get_texture_format_features(*format)
+                        .flags
+                        .sample_count_supported(count)
+                })
+            })
+            .collect()
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// msaa for the opaque, transparent and ui passes. counts the adapter does not support fall back to the
+    /// largest supported one below them
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        let supported = self
+            .supported_sample_counts
+            .iter()
+            .copied()
+            .filter(|&count| count <= sample_count)
+            .max()
+            .unwrap_or(1);
+        if supported != sample_count {
+            log::warn!(
+                "{}x msaa is not supported by the adapter, using {}x",
+                sample_count, supported
+            );
+        }
+        if supported == self.sample_count {
+            return;
+        }
+        self.sample_count = supported;
+        self.opaque_pipeline.set_sample_count(
+            &self.device,
+            &self.camera_bind_group_layout,
+            self.skinned_camera_bind_group_layout.as_ref(),
+            &self.shadow_pipeline.shadow_bind_group_layout,
+            supported,
+        );
+        self.transparent_pipeline = TransparentPipeline::new(
+            &self.device,
+            &self.camera_bind_group_layout,
+            self.skinned_camera_bind_group_layout.as_ref(),
+            &self.opaque_pipeline,
+            &self.shadow_pipeline.shadow_bind_group_layout,
+            supported,
+        );
+        self.tonemap_pipeline
+            .set_sample_count(&self.device, &self.config, supported);
+        self.ui_pipeline
+            .set_sample_count(&self.device, &self.config, supported);
+        self.create_render_targets();
+    }
+
+    // reconfigures the surface and the frame pacer when present_settings changed since the last frame
+    fn apply_present_settings(&mut self) {
+        if self.present_settings == self.applied_present_settings {
+            return;
+        }
+        self.applied_present_settings = self.present_settings.clone();
+        let mut present_mode = self.present_settings.present_mode;
+        if self.surface.is_some() && !self.present_modes.contains(&present_mode) {
+            log::warn!(
+                "present mode {:?} is not supported by the surface, using Fifo",
+                present_mode
+            );
+            present_mode = wgpu::PresentMode::Fifo;
+        }
+        let max_frames_in_flight = self.present_settings.max_frames_in_flight.max(1);
+        self.config.present_mode = present_mode;
+        self.config.desired_maximum_frame_latency = max_frames_in_flight;
+        // the frames in flight may still use the swapchain textures
+        self.frame_pacer.wait_idle(&self.device);
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.config);
+        }
+        self.frame_pacer
+            .set_frames_in_flight(&self.device, max_frames_in_flight as usize);
+    }
+
+    // the morph weights of one instance of the mesh: the defaults of the mesh, replaced by the weights of the
+    // animation, replaced by the weights set on the instance. None if the mesh has no targets or all are 0
+    fn morph_weights(
+        mesh: &MyMesh,
+        instance: &ModelInstance,
+        animation_weights: &HashMap<usize, Vec<f32>>,
+    ) -> Option<Vec<f32>> {
+        let mut weights = mesh.morph.as_ref()?.default_weights.clone();
+        let overrides = [
+            animation_weights.get(&mesh.node_index),
+            instance.morph_weights.get(&mesh.node_name),
+        ];
+        for override_weights in overrides.into_iter().flatten() {
+            for (weight, override_weight) in weights.iter_mut().zip(override_weights) {
+                *weight = *override_weight;
+            }
+        }
+        weights.iter().any(|weight| *weight != 0.0).then_some(weights)
+    }
+
+    // adds the instance data of every opaque and transparent mesh of the model to renderables.
+    // animated instances move rigid meshes with the node transforms of their pose, and the joint matrices of
+    // every instance of a skinned model are appended to the joint matrices.
+    // instances of a mesh with active morph targets are morphed into their own vertex buffer and drawn on their own,
+    // the morphs are recorded by MorphPipeline::dispatch
+    fn prepare_instances(
+        &mut self,
+        model_data: &ModelData,
+        instances: &[ModelInstance],
+        animation_players: &HashMap<String, AnimationPlayer>,
+        renderables: &mut FrameRenderables,
+    ) {
+        let skeleton = &model_data.skeleton;
+        let mut opaque_instances = vec![Vec::new(); model_data.opaque_meshes.len()];
+        let mut transparent_instances = vec![Vec::new(); model_data.transparent_meshes.len()];
+        // shared by the instances without an animation player
+        let rest_joint_matrices = skeleton.joint_matrices(&skeleton.rest_global_transforms());
+        for (instance_index, instance) in instances.iter().enumerate() {
+            if renderables.joint_matrices.len() + skeleton.joints.len() > MAX_JOINT_MATRICES {
+                log::warn!(
+                    "more than {} joint matrices submitted, instance dropped",
+                    MAX_JOINT_MATRICES
+                );
+                continue;
+            }
+            let animation_player = instance.find_animation_player(animation_players);
+            let global_transforms = animation_player.map(|player| {
+                skeleton.global_transforms(&player.sample_pose(skeleton, &model_data.animations))
+            });
+            let animation_weights = animation_player
+                .map(|player| player.sample_morph_weights(&model_data.animations))
+                .unwrap_or_default();
+            let joint_offset = renderables.joint_matrices.len() as u32;
+            match &global_transforms {
+                Some(global_transforms) => renderables
+                    .joint_matrices
+                    .extend(skeleton.joint_matrices(global_transforms)),
+                None => renderables
+                    .joint_matrices
+                    .extend_from_slice(&rest_joint_matrices),
+            }
+            let mesh_transform = |mesh: &MyMesh| match (&global_transforms, &mesh.skin_buffer) {
+                (Some(global_transforms), None) => global_transforms[mesh.node_index],
+                _ => mesh.transform,
+            };
+            let mut add_instance =
+                |mesh: &Arc<MyMesh>,
+                 mesh_instances: &mut Vec<ModelInstanceRaw>,
+                 morphed_meshes: &mut Vec<(Arc<MyMesh>, Arc<Vec<ModelInstanceRaw>>)>| {
+                    let instance_raw =
+                        instance.to_raw_with_transform(&mesh_transform(mesh), joint_offset);
+                    let morphed_buffer = Self::morph_weights(mesh, instance, &animation_weights)
+                        .and_then(|weights| {
+                            self.morph_pipeline.morph(
+                                &self.device,
+                                &self.queue,
+                                mesh,
+                                instance_index,
+                                &weights,
+                            )
+                        });
+                    match morphed_buffer {
+                        Some(vertex_buffer) => {
+                            let morphed_mesh = MyMesh {
+                                vertex_buffer,
+                                ..MyMesh::clone(mesh)
+                            };
+                            morphed_meshes
+                                .push((Arc::new(morphed_mesh), Arc::new(vec![instance_raw])));
+                        }
+                        None => mesh_instances.push(instance_raw),
+                    }
+                };
+            for (mesh, mesh_instances) in model_data
+                .opaque_meshes
+                .iter()
+                .zip(opaque_instances.iter_mut())
+            {
+                add_instance(mesh, mesh_instances, &mut renderables.opaque_meshes);
+            }
+            for (mesh, mesh_instances) in model_data
+                .transparent_meshes
+                .iter()
+                .zip(transparent_instances.iter_mut())
+            {
+                add_instance(mesh, mesh_instances, &mut renderables.transparent_meshes);
+            }
+        }
+        for (mesh, instances) in model_data.opaque_meshes.iter().zip(opaque_instances) {
+            renderables
+                .opaque_meshes
+                .push((mesh.clone(), Arc::new(instances)));
+        }
+        for (mesh, instances) in model_data
+            .transparent_meshes
+            .iter()
+            .zip(transparent_instances)
+        {
+            renderables
+                .transparent_meshes
+                .push((mesh.clone(), Arc::new(instances)));
+        }
     }
 
     pub fn render(&mut self, state: &mut State) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.apply_present_settings();
+        // blocks while max_frames_in_flight frames are still running on the gpu
+        self.frame_pacer.begin_frame(&self.device);
+        // headless contexts render into the offscreen target instead of a swapchain texture
+        let output = match &self.surface {
+            Some(surface) => Some(surface.get_current_texture()?),
+            None => None,
+        };
+        // the srgb view of a surface without an srgb format, see new
+        let view_descriptor = wgpu::TextureViewDescriptor {
+            format: Some(self.config.format.add_srgb_suffix()),
+            ..Default::default()
+        };
+        let view = match &output {
+            Some(output) => output.texture.create_view(&view_descriptor),
+            None => self
+                .offscreen_target
+                .as_ref()
+                .unwrap()
+                .texture
+                .create_view(&view_descriptor),
+        };
         // update camera transform
-        let aspect = self.config.width as f32 / self.config.height as f32;
+        // the targets are at least 1x1, a 0 sized context must not divide by 0 either
+        let aspect = self.config.width.max(1) as f32 / self.config.height.max(1) as f32;
         let camera_uniform = CameraUniform::new(&state.camera, aspect, true);
+        let frustum = camera_uniform.frustum();
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[camera_uniform]),
         );
-        // update light transform
-        let light_uniform = LightUniform {
-            position: state.light_position.into(),
-            _padding: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
+        // upload the lights submitted this frame
+        let mut light_submissions = mem::take(&mut state.light_submissions);
+        if light_submissions.len() > MAX_LIGHTS {
+            log::warn!(
+                "{} lights submitted, only the first {} are used",
+                light_submissions.len(),
+                MAX_LIGHTS
+            );
+            light_submissions.truncate(MAX_LIGHTS);
+        }
+        let lights_header = LightsHeader {
+            count: light_submissions.len() as u32,
+            _padding: [0; 3],
         };
+        // assigns the shadow map layers of the lights
+        let light_data = self.shadow_pipeline.prepare(
+            &self.device,
+            &self.queue,
+            &light_submissions,
+            &state.camera,
+            aspect,
+        );
         self.queue.write_buffer(
             &self.light_buffer,
             0,
-            bytemuck::cast_slice(&[light_uniform]),
+            bytemuck::cast_slice(&[lights_header]),
         );
+        if !light_data.is_empty() {
+            self.queue.write_buffer(
+                &self.light_buffer,
+                size_of::<LightsHeader>() as wgpu::BufferAddress,
+                bytemuck::cast_slice(&light_data),
+            );
+        }
 
         let mut encoder = self
             .device
@@ -200,42 +744,129 @@ impl RenderContext {
             .into_iter()
             .map(|(model_meta, instances)| (model_meta, Arc::new(instances)))
             .collect::<HashMap<_, _>>();
-        let mut opaque_meshes = Vec::<(Arc<MyMesh>, Arc<Vec<ModelInstance>>)>::new();
+        self.asset_server
+            .process_uploads(&self.device, &self.queue, &self.opaque_pipeline);
+        let mut renderables = FrameRenderables::default();
+        let mut drawn_models = Vec::new();
         for (model_meta, instances) in model_render_submissions.iter() {
             // need to get the model info to determine which meshes are opaque
-            let model_data = CACHE.get_with(CacheKey::ModelMeta(model_meta.clone()), || {
-                let model_data =
-                    model_meta.load_model(&self.device, &self.queue, &self.opaque_pipeline);
-                Arc::new(CacheValue::ModelData(model_data))
-            });
-            let model_data = match model_data.as_ref() {
-                CacheValue::ModelData(model_info) => model_info,
-                _ => unreachable!(),
+            let model_handle = self.asset_server.load_model(model_meta.clone());
+            let model_data = match model_handle.state() {
+                AssetState::Ready(model_data) => model_data,
+                AssetState::Failed(_) => self.asset_server.placeholder_model(
+                    &self.device,
+                    &self.queue,
+                    &self.opaque_pipeline,
+                ),
+                // not drawn until it is ready
+                AssetState::Loading => continue,
             };
-            for opaque_mesh in model_data.opaque_meshes.iter() {
-                opaque_meshes.push((opaque_mesh.clone(), instances.clone()));
-            }
+            self.prepare_instances(
+                &model_data,
+                instances,
+                &state.animation_players,
+                &mut renderables,
+            );
+            drawn_models.push((model_meta, model_data));
+        }
+        // one compute pass for every morphed instance, before the passes that draw them
+        self.morph_pipeline.dispatch(&mut encoder);
+        // against the models drawn this frame, the results are read by the next State::update
+        state.pick_results = mem::take(&mut state.pick_requests)
+            .iter()
+            .map(|ray| {
+                pick(
+                    ray,
+                    drawn_models.iter().map(|(model_meta, model_data)| {
+                        (
+                            *model_meta,
+                            model_data.as_ref(),
+                            model_render_submissions[*model_meta].as_slice(),
+                        )
+                    }),
+                    &state.animation_players,
+                )
+            })
+            .collect();
+        let FrameRenderables {
+            opaque_meshes,
+            transparent_meshes,
+            joint_matrices,
+        } = renderables;
+        let instance_arena = &mut self.frame_pacer.frame_mut().instance_arena;
+        // with msaa the passes draw into the multisampled targets and resolve into the single sampled ones
+        let (hdr_view, hdr_resolve_view) = match &self.msaa_hdr_texture {
+            Some(msaa_hdr_texture) => (&msaa_hdr_texture.view, Some(&self.hdr_texture.view)),
+            None => (&self.hdr_texture.view, None),
+        };
+        let (target_view, target_resolve_view) = match &self.msaa_target_texture {
+            Some(msaa_target_texture) => (&msaa_target_texture.view, Some(&view)),
+            None => (&view, None),
+        };
+        if !joint_matrices.is_empty() {
+            self.queue.write_buffer(
+                &self.joint_buffer,
+                0,
+                bytemuck::cast_slice(&joint_matrices),
+            );
         }
-        self.opaque_pipeline.render(
+        self.shadow_pipeline.render(
             &opaque_meshes,
             &mut encoder,
             &self.device,
             &self.queue,
-            &view,
+            self.skinned_camera_bind_group.as_ref(),
+            instance_arena,
+        );
+        let mut cull_stats = self.opaque_pipeline.render(
+            &opaque_meshes,
+            &mut encoder,
+            &self.device,
+            &self.queue,
+            hdr_view,
+            hdr_resolve_view,
             &self.depth_texture.view,
             &self.camera_bind_group,
+            self.skinned_camera_bind_group.as_ref(),
+            &self.shadow_pipeline.shadow_bind_group,
+            &frustum,
+            instance_arena,
         );
+        // blended on top of the opaque meshes, before the ui
+        cull_stats.add(self.transparent_pipeline.render(
+            &transparent_meshes,
+            &mut encoder,
+            &self.device,
+            &self.queue,
+            hdr_view,
+            hdr_resolve_view,
+            &self.depth_texture.view,
+            &self.camera_bind_group,
+            self.skinned_camera_bind_group.as_ref(),
+            &self.opaque_pipeline.light_bind_group,
+            &self.shadow_pipeline.shadow_bind_group,
+            state.camera.pos,
+            &frustum,
+            instance_arena,
+        ));
+        state.cull_stats = cull_stats;
+        self.tonemap_pipeline
+            .render(&mut encoder, &self.queue, target_view, target_resolve_view);
 
         let ui_render_instructions = mem::take(&mut state.ui_render_instructions);
-        assert!(ui_render_instructions.len() == 1);
+        // a headless render may only draw the scene, without any UI
+        assert!(ui_render_instructions.len() <= 1);
         assert!(state.ui_render_instructions.is_empty());
         self.ui_pipeline.render(
             &mut encoder,
             ui_render_instructions,
             &self.device,
             &self.queue,
-            &view,
+            target_view,
+            target_resolve_view,
             // &self.depth_texture.view,
+            instance_arena,
+            &mut self.asset_server,
         );
         // submit will accept anything that implements IntoIter
 
@@ -245,9 +876,75 @@ impl RenderContext {
         //     .read_line(&mut input)
         //     .expect("Failed to read line");
         // panic!("render");
+        let submission = self.queue.submit(std::iter::once(encoder.finish()));
+        self.frame_pacer.end_frame(submission);
+        // runs the callbacks of finished work without waiting for this frame
+        self.device.poll(wgpu::Maintain::Poll);
+        if let Some(output) = output {
+            output.present();
+        }
+        Ok(())
+    }
+
+    /// copies the last rendered frame of a headless context back to the cpu.
+    /// None if the context renders to a surface or the copy could not be mapped
+    pub fn read_frame(&self) -> Option<RgbaImage> {
+        let target = self.offscreen_target.as_ref()?;
+        // the target is at least 1x1, even when the context was created or resized to 0
+        let width = target.texture.width();
+        let height = target.texture.height();
+        // rows of a texture to buffer copy have to be aligned to 256 bytes
+        let unpadded_bytes_per_row = 4 * width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                aspect: wgpu::TextureAspect::All,
+                texture: &target.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &output_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
         self.queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = output_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
         self.device.poll(wgpu::Maintain::Wait);
-        output.present();
-        Ok(())
+        receiver.recv().ok()?.ok()?;
+        let padded_data = buffer_slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        for row in padded_data.chunks(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        drop(padded_data);
+        output_buffer.unmap();
+        RgbaImage::from_raw(width, height, pixels)
     }
 }
@@ -0,0 +1,56 @@
+// depth only pass, renders the scene from the point of view of one light into one shadow map layer
+struct ShadowPass {
+    light_view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> shadow_pass: ShadowPass;
+// the skinned camera bind group of the opaque pass, only the joint matrices are used by vs_skinned
+@group(1) @binding(1)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) joint_offset: u32,
+};
+struct SkinInput {
+    @location(10) joints: vec4<u32>,
+    @location(11) weights: vec4<f32>,
+};
+
+fn instance_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    return shadow_pass.light_view_proj * instance_matrix(instance) * vec4<f32>(model.position, 1.0);
+}
+
+// same skinning as vs_skinned in pbr.wgsl
+@vertex
+fn vs_skinned(
+    model: VertexInput,
+    instance: InstanceInput,
+    skin: SkinInput,
+) -> @builtin(position) vec4<f32> {
+    let offset = instance.joint_offset;
+    let skin_matrix = joint_matrices[offset + skin.joints.x] * skin.weights.x
+        + joint_matrices[offset + skin.joints.y] * skin.weights.y
+        + joint_matrices[offset + skin.joints.z] * skin.weights.z
+        + joint_matrices[offset + skin.joints.w] * skin.weights.w;
+    return shadow_pass.light_view_proj * instance_matrix(instance) * skin_matrix * vec4<f32>(model.position, 1.0);
+}
@@ -0,0 +1,571 @@
+// depth only pass that renders the shadow maps of the lights into the layers of one depth texture array
+// the first shadow casting directional light gets one layer per cascade, every shadow casting spot light
+// after that gets one layer. the opaque shader samples the array through shadow_bind_group (group 3)
+
+use std::sync::Arc;
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, SquareMatrix};
+use wgpu::{RenderPipeline, util::DeviceExt};
+
+use crate::{
+    buffer_arena::BufferArena,
+    camera::Camera,
+    light_uniform::{Light, LightKind, LightRaw},
+    model_data::MyMesh,
+    model_instance::ModelInstanceRaw,
+    my_camera::OPENGL_TO_WGPU_MATRIX,
+    my_texture::MyTexture,
+    vertex::{SkinVertex, Vertex},
+};
+
+pub const MAX_CASCADES: usize = 4;
+pub const MAX_SPOT_SHADOWS: usize = 8;
+pub const MAX_SHADOW_LAYERS: usize = MAX_CASCADES + MAX_SPOT_SHADOWS;
+
+#[derive(Debug, Clone)]
+pub struct ShadowSettings {
+    // width and height of every shadow map
+    pub resolution: u32,
+    // far end of every cascade as a view space distance from the camera, at most MAX_CASCADES
+    pub cascade_splits: Vec<f32>,
+    // depth bias for surfaces facing the light, grows up to slope_bias for surfaces at grazing angles
+    pub depth_bias: f32,
+    pub slope_bias: f32,
+    // how far behind a cascade shadow casters are still captured
+    pub caster_distance: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            cascade_splits: vec![6.0, 15.0, 35.0, 100.0],
+            depth_bias: 0.0005,
+            slope_bias: 0.003,
+            caster_distance: 50.0,
+        }
+    }
+}
+
+// matches struct Shadows in pbr.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowUniform {
+    pub light_view_proj: [[[f32; 4]; 4]; MAX_SHADOW_LAYERS],
+    pub cascade_splits: [f32; MAX_CASCADES],
+    pub cascade_count: u32,
+    pub depth_bias: f32,
+    pub slope_bias: f32,
+    pub texel_size: f32,
+}
+
+// matches struct ShadowPass in shadow.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowPassUniform {
+    light_view_proj: [[f32; 4]; 4],
+}
+
+pub struct ShadowPipeline {
+    pub pipeline: RenderPipeline,
+    // None without storage buffers in the vertex stage, like OpaquePipeline::skinned_pipeline
+    pub skinned_pipeline: Option<RenderPipeline>,
+    pub settings: ShadowSettings,
+    pub shadow_texture: MyTexture,
+    // used by the opaque and transparent pipelines to sample the shadow maps
+    pub shadow_bind_group_layout: wgpu::BindGroupLayout,
+    pub shadow_bind_group: wgpu::BindGroup,
+    shadow_buffer: wgpu::Buffer,
+    // one view, uniform buffer and bind group per layer for the shadow pass itself
+    layer_views: Vec<wgpu::TextureView>,
+    layer_buffers: Vec<wgpu::Buffer>,
+    layer_bind_groups: Vec<wgpu::BindGroup>,
+    // resolution of shadow_texture, it is recreated when the settings change
+    resolution: u32,
+    // layers rendered this frame
+    active_layers: Vec<usize>,
+}
+
+impl ShadowPipeline {
+    fn create_layer_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Pass Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        })
+    }
+
+    fn create_shadow_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    fn create_shadow_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        shadow_texture: &MyTexture,
+        shadow_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&shadow_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&shadow_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: shadow_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    fn create_layer_views(shadow_texture: &MyTexture) -> Vec<wgpu::TextureView> {
+        (0..MAX_SHADOW_LAYERS as u32)
+            .map(|layer| {
+                shadow_texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor {
+                        label: Some("shadow layer view"),
+                        dimension: Some(wgpu::TextureViewDimension::D2),
+                        base_array_layer: layer,
+                        array_layer_count: Some(1),
+                        ..Default::default()
+                    })
+            })
+            .collect()
+    }
+
+    // the skinned pipeline reads the joint matrices from the skinned camera bind group of the opaque pass
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layer_bind_group_layout: &wgpu::BindGroupLayout,
+        skinned_camera_bind_group_layout: Option<&wgpu::BindGroupLayout>,
+    ) -> RenderPipeline {
+        let skinned = skinned_camera_bind_group_layout.is_some();
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Shadow Pipeline Layout"),
+                bind_group_layouts: &match skinned_camera_bind_group_layout {
+                    Some(skinned_camera_bind_group_layout) => {
+                        vec![layer_bind_group_layout, skinned_camera_bind_group_layout]
+                    }
+                    None => vec![layer_bind_group_layout],
+                },
+                push_constant_ranges: &[],
+            });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shadow.wgsl").into()),
+        });
+        // skinned meshes have a third vertex buffer with the joints and weights
+        let buffers = [Vertex::desc(), ModelInstanceRaw::desc(), SkinVertex::desc()];
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some(if skinned { "vs_skinned" } else { "vs_main" }),
+                buffers: if skinned { &buffers } else { &buffers[..2] },
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            // depth only
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: MyTexture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                // the bias is applied when sampling, so it can change without a new pipeline
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        settings: ShadowSettings,
+        skinned_camera_bind_group_layout: Option<&wgpu::BindGroupLayout>,
+    ) -> Self {
+        let layer_bind_group_layout = Self::create_layer_bind_group_layout(device);
+        let pipeline = Self::create_pipeline(device, &layer_bind_group_layout, None);
+        let skinned_pipeline = skinned_camera_bind_group_layout
+            .map(|layout| Self::create_pipeline(device, &layer_bind_group_layout, Some(layout)));
+        let resolution = settings.resolution;
+        let shadow_texture =
+            MyTexture::create_shadow_texture(device, resolution, MAX_SHADOW_LAYERS as u32);
+        let shadow_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Buffer"),
+            size: size_of::<ShadowUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let shadow_bind_group_layout = Self::create_shadow_bind_group_layout(device);
+        let shadow_bind_group = Self::create_shadow_bind_group(
+            device,
+            &shadow_bind_group_layout,
+            &shadow_texture,
+            &shadow_buffer,
+        );
+        let layer_views = Self::create_layer_views(&shadow_texture);
+        let layer_buffers = (0..MAX_SHADOW_LAYERS)
+            .map(|_| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Shadow Pass Buffer"),
+                    contents: bytemuck::cast_slice(&[ShadowPassUniform {
+                        light_view_proj: Matrix4::identity().into(),
+                    }]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                })
+            })
+            .collect::<Vec<_>>();
+        let layer_bind_groups = layer_buffers
+            .iter()
+            .map(|layer_buffer| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("shadow_pass_bind_group"),
+                    layout: &layer_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: layer_buffer.as_entire_binding(),
+                    }],
+                })
+            })
+            .collect::<Vec<_>>();
+        Self {
+            pipeline,
+            skinned_pipeline,
+            settings,
+            shadow_texture,
+            shadow_bind_group_layout,
+            shadow_bind_group,
+            shadow_buffer,
+            layer_views,
+            layer_buffers,
+            layer_bind_groups,
+            resolution,
+            active_layers: Vec::new(),
+        }
+    }
+
+    // bounding sphere of the part of the camera frustum between near and far, in world space
+    fn frustum_slice_sphere(
+        camera: &impl Camera,
+        aspect: f32,
+        near: f32,
+        far: f32,
+    ) -> (cgmath::Vector3<f32>, f32) {
+        let inverse_view = camera.build_view_matrix().invert().unwrap();
+        let corners = camera
+            .projection()
+            .slice_corners(aspect, near, far)
+            .map(|corner| (inverse_view * corner.extend(1.0)).truncate());
+        let center = corners
+            .iter()
+            .fold(cgmath::Vector3::new(0.0, 0.0, 0.0), |sum, corner| {
+                sum + corner
+            })
+            / corners.len() as f32;
+        let radius = corners
+            .iter()
+            .map(|corner| (corner - center).magnitude())
+            .fold(0.0, f32::max);
+        // round the radius so that the projection does not change size every frame
+        let radius = (radius * 16.0).ceil() / 16.0;
+        (center, radius)
+    }
+
+    fn cascade_matrix(
+        &self,
+        light: &Light,
+        camera: &impl Camera,
+        aspect: f32,
+        near: f32,
+        far: f32,
+    ) -> Matrix4<f32> {
+        let (center, radius) = Self::frustum_slice_sphere(camera, aspect, near, far);
+        let direction = light.direction.normalize();
+        let up = if direction.y.abs() > 0.99 {
+            cgmath::Vector3::unit_z()
+        } else {
+            cgmath::Vector3::unit_y()
+        };
+        // rotation only, so that the center can be snapped to whole texels in light space.
+        // this keeps the shadow edges from shimmering when the camera moves
+        let light_rotation = Matrix4::look_at_rh(
+            cgmath::Point3::origin(),
+            cgmath::Point3::from_vec(direction),
+            up,
+        );
+        let center = (light_rotation * center.extend(1.0)).truncate();
+        let texel_size = 2.0 * radius / self.resolution as f32;
+        let center_x = (center.x / texel_size).floor() * texel_size;
+        let center_y = (center.y / texel_size).floor() * texel_size;
+        // the light looks down -z, so the depth of a point is -z
+        let projection = cgmath::ortho(
+            center_x - radius,
+            center_x + radius,
+            center_y - radius,
+            center_y + radius,
+            -center.z - radius - self.settings.caster_distance,
+            -center.z + radius,
+        );
+        OPENGL_TO_WGPU_MATRIX * projection * light_rotation
+    }
+
+    fn spot_matrix(light: &Light) -> Matrix4<f32> {
+        let position = cgmath::Point3::from_vec(light.position);
+        let direction = light.direction.normalize();
+        let up = if direction.y.abs() > 0.99 {
+            cgmath::Vector3::unit_z()
+        } else {
+            cgmath::Vector3::unit_y()
+        };
+        let view = Matrix4::look_at_rh(position, position + direction, up);
+        let fovy = f32::min(2.0 * light.outer_cone, 170.0);
+        let projection =
+            cgmath::perspective(cgmath::Deg(fovy), 1.0, 0.05, f32::max(light.range, 0.1));
+        OPENGL_TO_WGPU_MATRIX * projection * view
+    }
+
+    /// assigns shadow layers to the lights and uploads the light matrices.
+    /// returns the lights in the format of the light storage buffer
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lights: &[Light],
+        camera: &impl Camera,
+        aspect: f32,
+    ) -> Vec<LightRaw> {
+        if self.settings.resolution != self.resolution {
+            self.resolution = self.settings.resolution;
+            self.shadow_texture =
+                MyTexture::create_shadow_texture(device, self.resolution, MAX_SHADOW_LAYERS as u32);
+            self.shadow_bind_group = Self::create_shadow_bind_group(
+                device,
+                &self.shadow_bind_group_layout,
+                &self.shadow_texture,
+                &self.shadow_buffer,
+            );
+            self.layer_views = Self::create_layer_views(&self.shadow_texture);
+        }
+        if self.settings.cascade_splits.len() > MAX_CASCADES {
+            log::warn!(
+                "{} cascade splits configured, only the first {} are used",
+                self.settings.cascade_splits.len(),
+                MAX_CASCADES
+            );
+        }
+        let cascade_count = usize::min(self.settings.cascade_splits.len(), MAX_CASCADES);
+        let mut cascade_splits = [0.0; MAX_CASCADES];
+        for (i, split) in self
+            .settings
+            .cascade_splits
+            .iter()
+            .take(cascade_count)
+            .enumerate()
+        {
+            cascade_splits[i] = f32::min(*split, camera.projection().zfar());
+        }
+
+        let mut matrices = vec![Matrix4::identity(); MAX_SHADOW_LAYERS];
+        let mut has_directional_shadow = false;
+        let mut spot_shadow_count = 0;
+        let mut light_data = Vec::with_capacity(lights.len());
+        for light in lights.iter() {
+            let mut shadow_layer = -1;
+            match light.kind {
+                LightKind::Directional
+                    if light.cast_shadows && !has_directional_shadow && cascade_count > 0 =>
+                {
+                    has_directional_shadow = true;
+                    shadow_layer = 0;
+                    let mut near = camera.projection().znear();
+                    for (i, far) in cascade_splits.iter().take(cascade_count).enumerate() {
+                        matrices[i] = self.cascade_matrix(light, camera, aspect, near, *far);
+                        near = *far;
+                    }
+                }
+                LightKind::Spot if light.cast_shadows => {
+                    if spot_shadow_count < MAX_SPOT_SHADOWS {
+                        let layer = MAX_CASCADES + spot_shadow_count;
+                        spot_shadow_count += 1;
+                        shadow_layer = layer as i32;
+                        matrices[layer] = Self::spot_matrix(light);
+                    } else {
+                        log::warn!(
+                            "more than {} spot lights cast shadows, the rest are unshadowed",
+                            MAX_SPOT_SHADOWS
+                        );
+                    }
+                }
+                _ => {}
+            }
+            light_data.push(light.to_raw(shadow_layer));
+        }
+        let cascade_layers = if has_directional_shadow {
+            0..cascade_count
+        } else {
+            0..0
+        };
+        self.active_layers = cascade_layers
+            .chain(MAX_CASCADES..MAX_CASCADES + spot_shadow_count)
+            .collect();
+        for layer in self.active_layers.iter() {
+            queue.write_buffer(
+                &self.layer_buffers[*layer],
+                0,
+                bytemuck::cast_slice(&[ShadowPassUniform {
+                    light_view_proj: matrices[*layer].into(),
+                }]),
+            );
+        }
+        let mut light_view_proj = [[[0.0; 4]; 4]; MAX_SHADOW_LAYERS];
+        for (layer, matrix) in matrices.iter().enumerate() {
+            light_view_proj[layer] = (*matrix).into();
+        }
+        let shadow_uniform = ShadowUniform {
+            light_view_proj,
+            cascade_splits,
+            cascade_count: if has_directional_shadow {
+                cascade_count as u32
+            } else {
+                0
+            },
+            depth_bias: self.settings.depth_bias,
+            slope_bias: self.settings.slope_bias,
+            texel_size: 1.0 / self.resolution.max(1) as f32,
+        };
+        queue.write_buffer(
+            &self.shadow_buffer,
+            0,
+            bytemuck::cast_slice(&[shadow_uniform]),
+        );
+        light_data
+    }
+
+    /// renders the shadow casters into every layer assigned by prepare
+    pub fn render(
+        &self,
+        renderables: &Vec<(Arc<MyMesh>, Arc<Vec<ModelInstanceRaw>>)>,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        skinned_camera_bind_group: Option<&wgpu::BindGroup>,
+        instance_arena: &mut BufferArena,
+    ) {
+        if self.active_layers.is_empty() {
+            return;
+        }
+        // the instance data is shared by all layers
+        let instance_allocations = renderables
+            .iter()
+            .map(|(_, instances)| {
+                instance_arena.alloc(device, queue, bytemuck::cast_slice(instances.as_slice()))
+            })
+            .collect::<Vec<_>>();
+        for layer in self.active_layers.iter().copied() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.layer_views[layer],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_bind_group(0, &self.layer_bind_groups[layer], &[]);
+            // only used by the skinned pipeline
+            if let Some(skinned_camera_bind_group) = skinned_camera_bind_group {
+                render_pass.set_bind_group(1, skinned_camera_bind_group, &[]);
+            }
+            for ((mesh, instances), instance_allocation) in
+                renderables.iter().zip(instance_allocations.iter())
+            {
+                if instances.is_empty() {
+                    continue;
+                }
+                let skinned_pipeline = self
+                    .skinned_pipeline
+                    .as_ref()
+                    .filter(|_| skinned_camera_bind_group.is_some());
+                match (&mesh.skin_buffer, skinned_pipeline) {
+                    (Some(skin_buffer), Some(skinned_pipeline)) => {
+                        render_pass.set_pipeline(skinned_pipeline);
+                        render_pass.set_vertex_buffer(2, skin_buffer.slice(..));
+                    }
+                    _ => render_pass.set_pipeline(&self.pipeline),
+                }
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, instance_arena.slice(instance_allocation));
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+                render_pass.draw_indexed(0..mesh.num_indices, 0, 0..instances.len() as u32);
+            }
+        }
+    }
+}
@@ -4,7 +4,7 @@ use cgmath::{Euler, Quaternion};
 use either::Either;
 
 use crate::{
-    input_context::InputContext, model_instance::ModelInstance, model_meta::ModelMeta, my_camera::MyCamera, ui::{ui_button::UIButton, ui_span::{UISpan, SpanDirection}, ui_text::{CharEvent, UIText, UITextInner}}, ui_node::{
+    animation::AnimationPlayer, bounds::Ray, camera::Camera, frustum::CullStats, picking::PickHit, fly_camera_controller::FlyCameraController, input_context::InputContext, light_uniform::Light, model_instance::ModelInstance, model_meta::ModelMeta, my_camera::MyCamera, orbit_camera::OrbitCamera, ui::{ui_button::UIButton, ui_span::{UISpan, SpanDirection}, ui_text::{CharEvent, UIText, UITextInner}}, ui_node::{
         BoundedLength, HorizontalAlignment, RelativeLength, ToUINode, UINodeEventRaw, UIRenderInstruction, VerticalAlignment
     }, ui_renderable::TextureMeta
 };
@@ -13,6 +13,9 @@ use crate::{
 pub struct State {
     // camera stuff
     pub camera: MyCamera,
+    pub fly_camera_controller: FlyCameraController,
+    // when set it drives the camera instead of fly_camera_controller
+    pub orbit_camera: Option<OrbitCamera>,
     // accumulated time
     pub timer: Option<Instant>,
     pub prev_time: Option<f32>,
@@ -20,10 +23,17 @@ pub struct State {
     pub cursor_timer: Option<Instant>,
     pub accumulated_frame_num: u32,
     pub model_render_submissions: HashMap<ModelMeta, Vec<ModelInstance>>,
+    // kept across frames, found by ModelInstance::animation_player
+    pub animation_players: HashMap<String, AnimationPlayer>,
     // use Arc here because we need to map the container to another container
     // pub ui_render_submissions: HashMap<TextureMeta, Vec<UIInstance>>,
     pub ui_render_instructions: Vec<UIRenderInstruction>,
-    pub light_position: cgmath::Vector3<f32>,
+    pub light_submissions: Vec<Light>,
+    pub pick_requests: Vec<Ray>,
+    // one entry per pick request of the previous frame
+    pub pick_results: Vec<Option<PickHit>>,
+    // instances drawn and culled by the last render
+    pub cull_stats: CullStats,
     pub fps: u32,
     pub canvas: Option<UISpan>,
     pub text: Option<UIText>,
@@ -36,6 +46,17 @@ impl State {
             .or_insert_with(|| Vec::new())
             .push(instance);
     }
+    /// the player under the key, created if it does not exist yet
+    pub fn animation_player_mut(&mut self, key: &str) -> &mut AnimationPlayer {
+        self.animation_players.entry(key.to_string()).or_default()
+    }
+    pub fn submit_light(&mut self, light: Light) {
+        self.light_submissions.push(light);
+    }
+    // resolved by the next render, the hit shows up in pick_results in the same order
+    pub fn request_pick(&mut self, ray: Ray) {
+        self.pick_requests.push(ray);
+    }
     // fn submit_ui_renderable(&mut self, ui_meta: TextureMeta, instance: UIInstance) {
     //     self.ui_render_submissions
     //         .entry(ui_meta)
@@ -116,7 +137,10 @@ impl State {
         let cursor_timer = self.cursor_timer.get_or_insert_with(|| Instant::now());
         let current_fps_time = fps_timer.elapsed().as_secs_f32();
         if current_fps_time >= 1.0 {
-            println!("FPS: {}", self.accumulated_frame_num);
+            println!(
+                "FPS: {}, instances drawn: {}, culled: {}",
+                self.accumulated_frame_num, self.cull_stats.drawn, self.cull_stats.culled
+            );
             self.fps = self.accumulated_frame_num;
             self.accumulated_frame_num = 0;
             *fps_timer = Instant::now();
@@ -141,15 +165,54 @@ impl State {
         let delta_time = current_time - *prev_time;
         assert!(delta_time >= 0.0);
         *prev_time = current_time;
-        let model_meta = ModelMeta {
-            path: "assets/rabbit2.glb".to_string(),
-        };
+        for animation_player in self.animation_players.values_mut() {
+            animation_player.update(delta_time);
+        }
+        match &mut self.orbit_camera {
+            Some(orbit_camera) => {
+                orbit_camera.update(input_context, delta_time);
+                orbit_camera.apply_to(&mut self.camera);
+            }
+            None => self
+                .fly_camera_controller
+                .update(&mut self.camera, input_context, delta_time),
+        }
+        // the hit of the click of the previous frame
+        for hit in self.pick_results.iter().flatten() {
+            log::info!(
+                "picked instance {} of {:?}, mesh {} at distance {}",
+                hit.instance_index, hit.model_meta, hit.mesh_index, hit.distance
+            );
+        }
+        // mouse_left_down clears the flag, the ui below needs it as well
+        let mouse_left_down = input_context.mouse_left_down();
+        // a left click picks the model instance under the cursor
+        if mouse_left_down
+            && let Some(cursor_position) = input_context.mouse_position()
+        {
+            let ray = self.camera.screen_ray(cursor_position, window_size);
+            self.request_pick(ray);
+        }
+        let model_meta = ModelMeta::new("assets/rabbit2.glb".to_string());
 
         // rotate light in a unit circle
         let light_radius = 5.0;
         let light_angle = current_time * 0.5;
-        self.light_position.x = light_radius * light_angle.cos();
-        self.light_position.z = light_radius * light_angle.sin();
+        self.submit_light(Light::point(
+            cgmath::Vector3::new(
+                light_radius * light_angle.cos(),
+                0.0,
+                light_radius * light_angle.sin(),
+            ),
+            cgmath::Vector3::new(1.0, 1.0, 1.0),
+            25.0,
+            20.0,
+        ));
+        self.submit_light(Light::directional(
+            cgmath::Vector3::new(-0.3, -1.0, -0.2),
+            cgmath::Vector3::new(1.0, 0.95, 0.9),
+            0.3,
+        ));
 
         let scale = 1.0;
         let speed = 0.0;
@@ -162,6 +225,8 @@ impl State {
                 cgmath::Rad(delta_angle),
             )),
             scale: cgmath::Vector3::new(scale, scale, scale),
+            animation_player: None,
+            morph_weights: HashMap::new(),
         };
         let instance2 = ModelInstance {
             position: [1.0, 0.0, 0.0].into(),
@@ -171,6 +236,8 @@ impl State {
                 cgmath::Rad(-delta_angle),
             )),
             scale: cgmath::Vector3::new(scale, scale, scale),
+            animation_player: None,
+            morph_weights: HashMap::new(),
         };
         // self.submit_renderable(model_meta.clone(), instance1);
         // self.submit_renderable(model_meta.clone(), instance2);
@@ -197,7 +264,7 @@ impl State {
             mouse_x: cursor_position.0 as u32,
             mouse_y: cursor_position.1 as u32,
             mouse_left: input_context.mouse_left(),
-            mouse_left_down: input_context.mouse_left_down(),
+            mouse_left_down,
             mouse_left_up: input_context.mouse_left_up(),
             mouse_right: input_context.mouse_right(),
             mouse_right_down: input_context.mouse_right_down(),
@@ -217,15 +284,21 @@ impl Default for State {
     fn default() -> Self {
         State {
             camera: MyCamera::default(),
+            fly_camera_controller: FlyCameraController::default(),
+            orbit_camera: None,
             timer: None,
             prev_time: None,
             fps_timer: None,
             cursor_timer: None,
             accumulated_frame_num: 0,
             model_render_submissions: HashMap::new(),
+            animation_players: HashMap::new(),
             // ui_render_submissions: HashMap::new(),
             ui_render_instructions: Vec::new(),
-            light_position: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            light_submissions: Vec::new(),
+            pick_requests: Vec::new(),
+            pick_results: Vec::new(),
+            cull_stats: CullStats::default(),
             fps: 0,
             canvas: None,
             text:None,
@@ -0,0 +1,43 @@
+// maps the hdr scene color to the range of the swapchain, drawn as one triangle that covers the screen
+
+// curve: 0 none, 1 aces, 2 reinhard
+struct Tonemap {
+    exposure: f32,
+    curve: u32,
+}
+
+@group(0) @binding(0)
+var hdr_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> tonemap: Tonemap;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // (-1, -1), (3, -1), (-1, 3)
+    let corner = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // the hdr texture has the size of the target, no filtering needed
+    let color = textureLoad(hdr_texture, vec2<i32>(position.xy), 0).rgb * tonemap.exposure;
+    var mapped: vec3<f32>;
+    switch tonemap.curve {
+        case 1u: {
+            mapped = aces(color);
+        }
+        case 2u: {
+            mapped = color / (1.0 + color);
+        }
+        default: {
+            mapped = color;
+        }
+    }
+    return vec4<f32>(mapped, 1.0);
+}
@@ -0,0 +1,258 @@
+// the opaque and transparent passes render into an Rgba16Float hdr texture, this pass scales it by the exposure,
+// maps it into 0..1 with a tonemapping curve and writes it to the swapchain. the ui is drawn on top afterwards
+
+use wgpu::util::DeviceExt;
+
+use crate::my_texture::MyTexture;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tonemapping {
+    #[default]
+    Aces,
+    Reinhard,
+    // colors above 1 are clipped
+    None,
+}
+
+impl Tonemapping {
+    // matches the curve in tonemap.wgsl
+    fn curve(&self) -> u32 {
+        match self {
+            Tonemapping::None => 0,
+            Tonemapping::Aces => 1,
+            Tonemapping::Reinhard => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TonemapSettings {
+    pub tonemapping: Tonemapping,
+    // the hdr color is multiplied by it before the curve
+    pub exposure: f32,
+}
+
+impl TonemapSettings {
+    /// the same mapping as tonemap.wgsl on the cpu, for a linear color
+    pub fn apply(&self, color: [f32; 3]) -> [f32; 3] {
+        color.map(|channel| {
+            let x = (channel * self.exposure).max(0.0);
+            match self.tonemapping {
+                Tonemapping::None => x.min(1.0),
+                Tonemapping::Aces => {
+                    ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+                }
+                Tonemapping::Reinhard => x / (1.0 + x),
+            }
+        })
+    }
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        Self {
+            tonemapping: Tonemapping::default(),
+            exposure: 1.0,
+        }
+    }
+}
+
+// matches struct Tonemap in tonemap.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TonemapUniform {
+    pub exposure: f32,
+    pub curve: u32,
+    pub _padding: [u32; 2],
+}
+
+pub struct TonemapPipeline {
+    pub settings: TonemapSettings,
+    pub pipeline: wgpu::RenderPipeline,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub uniform_buffer: wgpu::Buffer,
+    // reads the hdr texture of the render context, recreated with it by resize
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl TonemapPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        hdr_texture: &MyTexture,
+        sample_count: u32,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tonemap Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let settings = TonemapSettings::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap Buffer"),
+            contents: bytemuck::cast_slice(&[Self::uniform(&settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, hdr_texture, &uniform_buffer);
+        let pipeline = Self::create_pipeline(device, config, &bind_group_layout, sample_count);
+        Self {
+            settings,
+            pipeline,
+            bind_group_layout,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    // every sample of a multisampled target gets the same color
+    fn create_pipeline(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tonemap Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("tonemap.wgsl").into()),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    // the srgb view of the swapchain, see RenderContext::new
+                    format: config.format.add_srgb_suffix(),
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) {
+        self.pipeline =
+            Self::create_pipeline(device, config, &self.bind_group_layout, sample_count);
+    }
+
+    fn uniform(settings: &TonemapSettings) -> TonemapUniform {
+        TonemapUniform {
+            exposure: settings.exposure,
+            curve: settings.tonemapping.curve(),
+            _padding: [0; 2],
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        hdr_texture: &MyTexture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tonemap Bind Group"),
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&hdr_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// binds the new hdr texture after it was recreated
+    pub fn resize(&mut self, device: &wgpu::Device, hdr_texture: &MyTexture) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            hdr_texture,
+            &self.uniform_buffer,
+        );
+    }
+
+    /// overwrites the whole color view with the tonemapped hdr texture
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        color_view: &wgpu::TextureView,
+        // the single sampled target when color_view is multisampled
+        resolve_view: Option<&wgpu::TextureView>,
+    ) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[Self::uniform(&self.settings)]),
+        );
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target: resolve_view,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        // one triangle that covers the screen
+        render_pass.draw(0..3, 0..1);
+    }
+}
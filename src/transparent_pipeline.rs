@@ -0,0 +1,236 @@
+// draws the transparent meshes on top of the result of the opaque pass
+// it shares the shader and the bind group layouts with OpaquePipeline, so the material bind groups of a mesh
+// work with both pipelines. depth is tested but not written, and every instance is drawn back to front.
+
+use std::sync::Arc;
+
+use cgmath::{MetricSpace, Transform};
+use wgpu::RenderPipeline;
+
+use crate::{
+    buffer_arena::BufferArena,
+    frustum::{CullStats, Frustum, cull_instances},
+    model_data::MyMesh,
+    model_instance::ModelInstanceRaw,
+    my_texture::MyTexture,
+    opaque_pipeline::OpaquePipeline,
+    vertex::{SkinVertex, Vertex},
+};
+
+pub struct TransparentPipeline {
+    pub pipeline: RenderPipeline,
+    // None without storage buffers in the vertex stage, like OpaquePipeline::skinned_pipeline
+    pub skinned_pipeline: Option<RenderPipeline>,
+}
+
+impl TransparentPipeline {
+    fn create_pipeline(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
+        skinned: bool,
+        sample_count: u32,
+    ) -> RenderPipeline {
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Transparent Pipeline Layout"),
+                bind_group_layouts: &[
+                    camera_bind_group_layout,
+                    material_bind_group_layout,
+                    light_bind_group_layout,
+                    shadow_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Transparent Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("pbr.wgsl").into()),
+        });
+
+        // skinned meshes have a third vertex buffer with the joints and weights
+
+        let buffers = [Vertex::desc(), ModelInstanceRaw::desc(), SkinVertex::desc()];
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Transparent Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some(if skinned { "vs_skinned" } else { "vs_main" }),
+                buffers: if skinned { &buffers } else { &buffers[..2] },
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_transparent"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: MyTexture::HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: MyTexture::DEPTH_FORMAT,
+                // transparent meshes must not hide each other, the sorting takes care of the order
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::GreaterEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        skinned_camera_bind_group_layout: Option<&wgpu::BindGroupLayout>,
+        opaque_pipeline: &OpaquePipeline,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let create_pipeline = |camera_bind_group_layout, skinned| {
+            Self::create_pipeline(
+                device,
+                camera_bind_group_layout,
+                &opaque_pipeline.material_bind_group_layout,
+                &opaque_pipeline.light_bind_group_layout,
+                shadow_bind_group_layout,
+                skinned,
+                sample_count,
+            )
+        };
+        Self {
+            pipeline: create_pipeline(camera_bind_group_layout, false),
+            skinned_pipeline: skinned_camera_bind_group_layout
+                .map(|layout| create_pipeline(layout, true)),
+        }
+    }
+
+    fn create_render_pass<'a>(
+        &self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        color_view: &'a wgpu::TextureView,
+        resolve_view: Option<&'a wgpu::TextureView>,
+        depth_view: &'a wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        // keep the result of the opaque pass
+        let color_attachment = Some(wgpu::RenderPassColorAttachment {
+            view: color_view,
+            resolve_target: resolve_view,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: wgpu::StoreOp::Store,
+            },
+        });
+        let depth_stencil_attachment = wgpu::RenderPassDepthStencilAttachment {
+            view: depth_view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: wgpu::StoreOp::Store,
+            }),
+            stencil_ops: None,
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Transparent Render Pass"),
+            color_attachments: &[color_attachment],
+            depth_stencil_attachment: Some(depth_stencil_attachment),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    pub fn render(
+        &self,
+        renderables: &Vec<(Arc<MyMesh>, Arc<Vec<ModelInstanceRaw>>)>,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_view: &wgpu::TextureView,
+        // the single sampled target when color_view is multisampled
+        resolve_view: Option<&wgpu::TextureView>,
+        depth_view: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        skinned_camera_bind_group: Option<&wgpu::BindGroup>,
+        light_bind_group: &wgpu::BindGroup,
+        shadow_bind_group: &wgpu::BindGroup,
+        camera_pos: cgmath::Point3<f32>,
+        frustum: &Frustum,
+        instance_arena: &mut BufferArena,
+    ) -> CullStats {
+        let mut cull_stats = CullStats::default();
+        // every (mesh, instance) pair is drawn on its own, sorted from the farthest to the nearest
+        let mut draws = renderables
+            .iter()
+            .flat_map(|(mesh, instances)| {
+                cull_instances(frustum, mesh, instances, &mut cull_stats)
+                    .into_iter()
+                    .map(move |instance| (mesh, instance))
+            })
+            .map(|(mesh, instance)| {
+                // sorted by the center of the bounds of the mesh in world space, the origin of a mesh can be far
+                // from its vertices, and every mesh of a model shares the origin of the instance otherwise
+                let center = instance.model_matrix().transform_point(mesh.aabb.center());
+                (mesh, instance, center.distance2(camera_pos))
+            })
+            .collect::<Vec<_>>();
+        if draws.is_empty() {
+            return cull_stats;
+        }
+        draws.sort_by(|a, b| b.2.total_cmp(&a.2));
+        let instance_data = draws
+            .iter()
+            .map(|(_, instance, _)| *instance)
+            .collect::<Vec<_>>();
+        let instance_allocation =
+            instance_arena.alloc(device, queue, bytemuck::cast_slice(&instance_data));
+
+        let mut render_pass =
+            self.create_render_pass(encoder, color_view, resolve_view, depth_view);
+        render_pass.set_bind_group(2, light_bind_group, &[]);
+        render_pass.set_bind_group(3, shadow_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, instance_arena.slice(&instance_allocation));
+        for (i, (mesh, _, _)) in draws.iter().enumerate() {
+            let skinned_pipeline = self
+                .skinned_pipeline
+                .as_ref()
+                .zip(skinned_camera_bind_group);
+            match (&mesh.skin_buffer, skinned_pipeline) {
+                (Some(skin_buffer), Some((skinned_pipeline, skinned_camera_bind_group))) => {
+                    render_pass.set_pipeline(skinned_pipeline);
+                    render_pass.set_bind_group(0, skinned_camera_bind_group, &[]);
+                    render_pass.set_vertex_buffer(2, skin_buffer.slice(..));
+                }
+                _ => {
+                    render_pass.set_pipeline(&self.pipeline);
+                    render_pass.set_bind_group(0, camera_bind_group, &[]);
+                }
+            }
+            render_pass.set_bind_group(1, mesh.material_bind_group.as_ref(), &[]);
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+            let i = i as u32;
+            render_pass.draw_indexed(0..mesh.num_indices, 0, i..i + 1);
+        }
+        cull_stats
+    }
+}
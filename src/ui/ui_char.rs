@@ -59,25 +59,35 @@ impl ToUINode for UIChar {
     ) -> UINode<BoxDimensionsRelative, StructuredChildren<BoxDimensionsRelative>> {
         let inner = self.inner.read().unwrap();
         
-        let font = get_font(inner.font_path.clone());
-        let font = match font.as_ref() {
-            CacheValue::Font(font) => font,
-            _ => panic!("Font not found"),
-        };
-        let scale = rusttype::Scale::uniform(inner.scale);
-        let v_metrics = font.v_metrics(scale);
-        // round ascent to the nearest integer
-        let ascent = v_metrics.ascent.round() as i32;
-        let descent = v_metrics.descent.round() as i32;
-        let line_gap = v_metrics.line_gap.round() as u32;
-        let glyph = font.glyph(inner.character).scaled(scale);
-        let h_metrics = glyph.h_metrics();
+        let (width, height, line_gap) = match get_font(inner.font_path.clone()) {
+            Ok(font) => {
+                let font = match font.as_ref() {
+                    CacheValue::Font(font) => font,
+                    _ => panic!("Font not found"),
+                };
+                let scale = rusttype::Scale::uniform(inner.scale);
+                let v_metrics = font.v_metrics(scale);
+                // round ascent to the nearest integer
+                let ascent = v_metrics.ascent.round() as i32;
+                let descent = v_metrics.descent.round() as i32;
+                let line_gap = v_metrics.line_gap.round() as u32;
+                let glyph = font.glyph(inner.character).scaled(scale);
+                let h_metrics = glyph.h_metrics();
 
-        let advance_width = h_metrics.advance_width.round() as u32;
-        // let margin_top = (ascent + bounding_top + line_gap/2) as u32; // ascent - (abs bounding_top)
-        // let margin_bottom = -((descent + bounding_bottom) + line_gap/2) as u32; // bounding_bottom - (abs descent)
-        let width = advance_width;
-        let height = (ascent - descent) as u32;
+                let advance_width = h_metrics.advance_width.round() as u32;
+                // let margin_top = (ascent + bounding_top + line_gap/2) as u32; // ascent - (abs bounding_top)
+                // let margin_bottom = -((descent + bounding_bottom) + line_gap/2) as u32; // bounding_bottom - (abs descent)
+                let width = advance_width;
+                let height = (ascent - descent) as u32;
+                (width, height, line_gap)
+            }
+            Err(error) => {
+                // the glyph texture falls back to the placeholder texture, give it a box of the font size
+                log::warn!("{}, using placeholder metrics", error);
+                let height = inner.scale.round() as u32;
+                (height / 2, height, 0)
+            }
+        };
         let box_dimensions = BoxDimensionsRelative {
             width: BoundedLength::fixed_pixels(width),
             height: BoundedLength::fixed_pixels(height),
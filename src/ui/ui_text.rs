@@ -398,18 +398,26 @@ impl ToUINode for DummyUIChar {
             Some(Box::new(event_handler) as Box<dyn Fn(&crate::ui_node::UINodeEventProcessed)->bool>)
         };
         let mut inner = self.inner.read().unwrap();
-        let font = get_font(inner.font_path.clone());
-        let font = match font.as_ref() {
-            CacheValue::Font(font) => font,
-            _ => panic!("Font not found"),
+        let (height, line_gap) = match get_font(inner.font_path.clone()) {
+            Ok(font) => {
+                let font = match font.as_ref() {
+                    CacheValue::Font(font) => font,
+                    _ => panic!("Font not found"),
+                };
+                let scale = rusttype::Scale::uniform(inner.scale);
+                let v_metrics = font.v_metrics(scale);
+                // round ascent to the nearest integer
+                let ascent = v_metrics.ascent.round() as i32;
+                let descent = v_metrics.descent.round() as i32;
+                let line_gap = v_metrics.line_gap.round() as u32;
+                let height = (ascent - descent) as u32;
+                (height, line_gap)
+            }
+            Err(error) => {
+                log::warn!("{}, using placeholder metrics", error);
+                (inner.scale.round() as u32, 0)
+            }
         };
-        let scale = rusttype::Scale::uniform(inner.scale);
-        let v_metrics = font.v_metrics(scale);
-        // round ascent to the nearest integer
-        let ascent = v_metrics.ascent.round() as i32;
-        let descent = v_metrics.descent.round() as i32;
-        let line_gap = v_metrics.line_gap.round() as u32;
-        let height = (ascent - descent) as u32;
         let width = height;
 
         
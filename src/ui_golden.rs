@@ -0,0 +1,450 @@
+// golden image regression harness for the UINode -> UIRenderInstruction -> UIPipeline chain
+// every scripted frame is rendered offscreen and compared against a stored png
+
+// frames are rendered on the gpu through a headless RenderContext when an adapter exists,
+// otherwise through the cpu reference rasterizer below. the two backends keep separate goldens
+// because the gpu samples textures with linear filtering and the cpu one uses nearest.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use image::{Rgba, RgbaImage};
+
+use crate::{
+    my_texture::MyTexture,
+    render_context::RenderContext,
+    state::State,
+    tonemap_pipeline::TonemapSettings,
+    ui_node::{ToUINode, UINodeEventRaw, UIRenderInstruction},
+    ui_renderable::TextureMeta,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoldenBackend {
+    Gpu,
+    CpuReference,
+}
+
+impl GoldenBackend {
+    fn name(&self) -> &'static str {
+        match self {
+            GoldenBackend::Gpu => "gpu",
+            GoldenBackend::CpuReference => "cpu",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GoldenMismatch {
+    pub frame: usize,
+    pub golden_path: PathBuf,
+    pub actual_path: PathBuf,
+    pub diff_path: Option<PathBuf>, // None if the golden is missing or the sizes do not match
+    pub mismatched_pixels: u32,
+    pub max_difference: u8,
+}
+
+pub struct UIGoldenTest {
+    pub name: String,
+    pub screen_width: u32,
+    pub screen_height: u32,
+    pub golden_dir: PathBuf,
+    // largest allowed difference of a single channel of a single pixel
+    pub tolerance: u8,
+    // write the rendered frames as the new goldens instead of comparing
+    pub update_goldens: bool,
+    pub backend: GoldenBackend,
+    render_context: Option<RenderContext>,
+}
+
+impl UIGoldenTest {
+    /// picks the gpu backend if any adapter (including a software one) is available.
+    /// goldens are updated when the UPDATE_GOLDENS environment variable is set
+    pub fn new(
+        name: &str,
+        screen_width: u32,
+        screen_height: u32,
+        golden_dir: impl AsRef<Path>,
+    ) -> Self {
+        let render_context = RenderContext::new_headless(screen_width, screen_height);
+        let backend = match render_context {
+            Some(_) => GoldenBackend::Gpu,
+            None => {
+                log::warn!("no adapter found, falling back to the cpu reference rasterizer");
+                GoldenBackend::CpuReference
+            }
+        };
+        Self {
+            name: name.to_string(),
+            screen_width,
+            screen_height,
+            golden_dir: golden_dir.as_ref().to_path_buf(),
+            tolerance: 2,
+            update_goldens: std::env::var_os("UPDATE_GOLDENS").is_some(),
+            backend,
+            render_context,
+        }
+    }
+
+    /// same as new, but never touches the gpu
+    pub fn new_cpu_only(
+        name: &str,
+        screen_width: u32,
+        screen_height: u32,
+        golden_dir: impl AsRef<Path>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            screen_width,
+            screen_height,
+            golden_dir: golden_dir.as_ref().to_path_buf(),
+            tolerance: 2,
+            update_goldens: std::env::var_os("UPDATE_GOLDENS").is_some(),
+            backend: GoldenBackend::CpuReference,
+            render_context: None,
+        }
+    }
+
+    fn frame_path(&self, frame: usize, suffix: &str) -> PathBuf {
+        self.golden_dir.join(&self.name).join(format!(
+            "{}_{:03}{}.png",
+            self.backend.name(),
+            frame,
+            suffix
+        ))
+    }
+
+    pub fn render_frame(&mut self, render_instruction: UIRenderInstruction) -> RgbaImage {
+        match self.render_context.as_mut() {
+            Some(render_context) => {
+                // textures are drawn as placeholders until the asset server uploaded them
+                loop {
+                    let mut state = State::default();
+                    state
+                        .ui_render_instructions
+                        .push(render_instruction.clone());
+                    render_context.render(&mut state).unwrap();
+                    if render_context.asset_server.is_idle() {
+                        break;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+                render_context
+                    .read_frame()
+                    .expect("the headless render context has an offscreen target")
+            }
+            None => rasterize_ui_instructions(
+                &[render_instruction],
+                self.screen_width,
+                self.screen_height,
+            ),
+        }
+    }
+
+    /// runs every event of the script through the tree, one frame per event, and compares each frame
+    /// against its golden. a missing golden is a mismatch unless update_goldens is set
+    pub fn run(
+        &mut self,
+        tree: &dyn ToUINode,
+        events: &[UINodeEventRaw],
+    ) -> Result<(), Vec<GoldenMismatch>> {
+        std::fs::create_dir_all(self.golden_dir.join(&self.name)).unwrap();
+        let mut mismatches = Vec::new();
+        for (frame, event) in events.iter().enumerate() {
+            let render_instruction =
+                tree.update_and_to_instruction(self.screen_width, self.screen_height, event);
+            let actual = self.render_frame(render_instruction);
+            let golden_path = self.frame_path(frame, "");
+            if self.update_goldens {
+                log::info!("Writing golden {}", golden_path.display());
+                actual.save(&golden_path).unwrap();
+                continue;
+            }
+            if !golden_path.exists() {
+                let actual_path = self.frame_path(frame, "_actual");
+                actual.save(&actual_path).unwrap();
+                mismatches.push(GoldenMismatch {
+                    frame,
+                    golden_path,
+                    actual_path,
+                    diff_path: None,
+                    mismatched_pixels: actual.width() * actual.height(),
+                    max_difference: u8::MAX,
+                });
+                continue;
+            }
+            let golden = image::open(&golden_path).unwrap().to_rgba8();
+            if let Some(mismatch) = self.compare(frame, &golden, &actual) {
+                mismatches.push(mismatch);
+            }
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(mismatches)
+        }
+    }
+
+    fn compare(
+        &self,
+        frame: usize,
+        golden: &RgbaImage,
+        actual: &RgbaImage,
+    ) -> Option<GoldenMismatch> {
+        let golden_path = self.frame_path(frame, "");
+        let actual_path = self.frame_path(frame, "_actual");
+        if golden.dimensions() != actual.dimensions() {
+            actual.save(&actual_path).unwrap();
+            return Some(GoldenMismatch {
+                frame,
+                golden_path,
+                actual_path,
+                diff_path: None,
+                mismatched_pixels: actual.width() * actual.height(),
+                max_difference: u8::MAX,
+            });
+        }
+        let (diff, mismatched_pixels, max_difference) = diff_images(golden, actual, self.tolerance);
+        if mismatched_pixels == 0 {
+            return None;
+        }
+        let diff_path = self.frame_path(frame, "_diff");
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+        Some(GoldenMismatch {
+            frame,
+            golden_path,
+            actual_path,
+            diff_path: Some(diff_path),
+            mismatched_pixels,
+            max_difference,
+        })
+    }
+}
+
+/// returns the diff image, the number of pixels that differ by more than tolerance and the largest difference.
+/// mismatched pixels are drawn in red on top of a dimmed grayscale copy of the golden
+pub fn diff_images(golden: &RgbaImage, actual: &RgbaImage, tolerance: u8) -> (RgbaImage, u32, u8) {
+    assert!(golden.dimensions() == actual.dimensions());
+    let mut diff = RgbaImage::new(golden.width(), golden.height());
+    let mut mismatched_pixels = 0;
+    let mut max_difference = 0;
+    for (x, y, expected) in golden.enumerate_pixels() {
+        let got = actual.get_pixel(x, y);
+        let difference = expected
+            .0
+            .iter()
+            .zip(got.0.iter())
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap();
+        max_difference = u8::max(max_difference, difference);
+        if difference > tolerance {
+            mismatched_pixels += 1;
+            diff.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        } else {
+            let luma = (expected[0] as u32 + expected[1] as u32 + expected[2] as u32) / 3 / 4;
+            let luma = luma as u8;
+            diff.put_pixel(x, y, Rgba([luma, luma, luma, 255]));
+        }
+    }
+    (diff, mismatched_pixels, max_difference)
+}
+
+// cpu reference rasterizer
+// it follows UIPipeline::render_helper: every instruction is drawn into its own texture, its sub instructions
+// are drawn on top, and the result is stretched into its location inside the parent.
+// texels with alpha < 0.5 are discarded like in ui.wgsl
+
+fn load_texture_meta_image(texture_meta: &TextureMeta) -> RgbaImage {
+    let image = match texture_meta {
+        TextureMeta::Texture { path } => {
+            MyTexture::load_image_from_file_path(path).map_err(Arc::new)
+        }
+        TextureMeta::Font {
+            font_path,
+            character,
+        } => MyTexture::load_image_from_text_character(*character, font_path.clone()),
+    };
+    match image {
+        Ok(image) => image,
+        Err(error) => {
+            log::warn!("{}, using the placeholder texture instead", error);
+            MyTexture::placeholder_image()
+        }
+    }
+}
+
+// draws source stretched over the rect [left, right) x [top, bottom) of target, in pixels
+fn blit_stretched(
+    target: &mut RgbaImage,
+    source: &RgbaImage,
+    left: f32,
+    top: f32,
+    right: f32,
+    bottom: f32,
+) {
+    let rect_width = right - left;
+    let rect_height = bottom - top;
+    if rect_width <= 0.0 || rect_height <= 0.0 {
+        return;
+    }
+    let x_start = f32::max(left.round(), 0.0) as u32;
+    let y_start = f32::max(top.round(), 0.0) as u32;
+    let x_end = f32::min(right.round(), target.width() as f32) as u32;
+    let y_end = f32::min(bottom.round(), target.height() as f32) as u32;
+    for y in y_start..y_end {
+        for x in x_start..x_end {
+            // sample at the pixel center
+            let u = (x as f32 + 0.5 - left) / rect_width;
+            let v = (y as f32 + 0.5 - top) / rect_height;
+            let source_x = u32::min((u * source.width() as f32) as u32, source.width() - 1);
+            let source_y = u32::min((v * source.height() as f32) as u32, source.height() - 1);
+            let texel = source.get_pixel(source_x, source_y);
+            if texel[3] < 128 {
+                continue;
+            }
+            target.put_pixel(x, y, *texel);
+        }
+    }
+}
+
+fn rasterize_to_texture(render_instruction: &UIRenderInstruction) -> RgbaImage {
+    let texture_width = u32::max(render_instruction.texture_width, 1);
+    let texture_height = u32::max(render_instruction.texture_height, 1);
+    // a fresh texture is zero initialized
+    let mut texture = RgbaImage::new(texture_width, texture_height);
+    let image = load_texture_meta_image(&render_instruction.texture_meta);
+    blit_stretched(
+        &mut texture,
+        &image,
+        0.0,
+        0.0,
+        texture_width as f32,
+        texture_height as f32,
+    );
+    for sub_instruction in render_instruction.sub_instructions.iter() {
+        rasterize_into(&mut texture, sub_instruction);
+    }
+    texture
+}
+
+fn rasterize_into(parent: &mut RgbaImage, render_instruction: &UIRenderInstruction) {
+    let child_texture = rasterize_to_texture(render_instruction);
+    let parent_width = parent.width() as f32;
+    let parent_height = parent.height() as f32;
+    blit_stretched(
+        parent,
+        &child_texture,
+        render_instruction.location_left * parent_width,
+        render_instruction.location_top * parent_height,
+        render_instruction.location_right * parent_width,
+        render_instruction.location_bottom * parent_height,
+    );
+}
+
+fn linear_to_srgb(linear: f64) -> u8 {
+    let srgb = if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+/// renders the ui instructions on top of the clear color of the opaque pass, without any gpu
+pub fn rasterize_ui_instructions(
+    render_instructions: &[UIRenderInstruction],
+    screen_width: u32,
+    screen_height: u32,
+) -> RgbaImage {
+    // same clear color as OpaquePipeline::create_render_pass, tonemapped like the default TonemapPipeline
+    // and written to an srgb target
+    let [red, green, blue] = TonemapSettings::default().apply([0.1, 0.2, 0.3]);
+    let clear_color = Rgba([
+        linear_to_srgb(red as f64),
+        linear_to_srgb(green as f64),
+        linear_to_srgb(blue as f64),
+        255,
+    ]);
+    let mut screen = RgbaImage::from_pixel(screen_width, screen_height, clear_color);
+    for render_instruction in render_instructions {
+        rasterize_into(&mut screen, render_instruction);
+    }
+    screen
+}
+
+#[cfg(test)]
+mod tests {
+    use either::Either;
+
+    use super::*;
+    use crate::{
+        ui::{
+            ui_button::UIButton,
+            ui_span::{SpanDirection, UISpan},
+        },
+        ui_node::{BoundedLength, HorizontalAlignment, RelativeLength, VerticalAlignment},
+    };
+
+    fn mouse_event(
+        mouse_x: u32,
+        mouse_y: u32,
+        mouse_left: bool,
+        mouse_left_down: bool,
+        mouse_left_up: bool,
+    ) -> UINodeEventRaw {
+        UINodeEventRaw {
+            mouse_x,
+            mouse_y,
+            mouse_left,
+            mouse_left_down,
+            mouse_left_up,
+            ..Default::default()
+        }
+    }
+
+    // a button inside a span: idle, hovered, pressed, released and left again
+    #[test]
+    fn button_in_span() {
+        let span = UISpan::new(
+            SpanDirection::Horizontal,
+            BoundedLength::fixed_pixels(180),
+            BoundedLength::fixed_pixels(100),
+            Either::Left(RelativeLength::Pixels(10)),
+            Either::Left(RelativeLength::Pixels(10)),
+            HorizontalAlignment::Left,
+            VerticalAlignment::Top,
+            false,
+            TextureMeta::Texture {
+                path: "assets/placeholder.png".into(),
+            },
+        );
+        let button = UIButton::new(
+            BoundedLength::fixed_pixels(100),
+            BoundedLength::fixed_pixels(50),
+            Either::Left(RelativeLength::Pixels(0)),
+            Either::Left(RelativeLength::Pixels(0)),
+            None,
+        );
+        span.push_child(Box::new(button));
+        let events = [
+            mouse_event(150, 100, false, false, false),
+            mouse_event(60, 40, false, false, false),
+            mouse_event(60, 40, true, true, false),
+            mouse_event(60, 40, false, false, true),
+            mouse_event(150, 100, false, false, false),
+        ];
+        let mut golden_test = UIGoldenTest::new_cpu_only(
+            "button_in_span",
+            200,
+            120,
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/goldens"),
+        );
+        if let Err(mismatches) = golden_test.run(&span, &events) {
+            panic!("{:#?}", mismatches);
+        }
+    }
+}
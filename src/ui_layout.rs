@@ -0,0 +1,368 @@
+// pure cpu access to the layout passes of ui_node.rs
+// layout() runs calculate_dimensions, flatten_children and to_unified on a tree, but does not dispatch
+// events and does not create any textures, so it can be used to check layouts without a gpu
+
+use crate::{
+    ui_node::{ToUINode, UIIdentifier},
+    ui_renderable::TextureMeta,
+};
+
+#[derive(Debug, Clone)]
+pub struct LayoutNode {
+    pub identifier: UIIdentifier,
+    pub global_pos_x: u32, // top left corner on the screen
+    pub global_pos_y: u32,
+    pub rel_pos_x: u32, // top left corner inside the parent
+    pub rel_pos_y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub margin: [u32; 4],  // top, right, bottom, left
+    pub padding: [u32; 4], // top, right, bottom, left
+    pub texture_meta: TextureMeta,
+    pub children: Vec<LayoutNode>,
+}
+
+impl LayoutNode {
+    /// global rect as (left, top, right, bottom)
+    pub fn global_rect(&self) -> (u32, u32, u32, u32) {
+        (
+            self.global_pos_x,
+            self.global_pos_y,
+            self.global_pos_x + self.width,
+            self.global_pos_y + self.height,
+        )
+    }
+    fn collect<'a>(&'a self, nodes: &mut Vec<&'a LayoutNode>) {
+        nodes.push(self);
+        for child in self.children.iter() {
+            child.collect(nodes);
+        }
+    }
+    fn to_json(&self, indent: usize) -> String {
+        let pad = " ".repeat(indent * 2);
+        let texture = match &self.texture_meta {
+            TextureMeta::Texture { path } => format!("{{\"path\": {}}}", json_string(path)),
+            TextureMeta::Font {
+                font_path,
+                character,
+            } => format!(
+                "{{\"font_path\": {}, \"character\": {}}}",
+                json_string(font_path),
+                json_string(&character.to_string())
+            ),
+        };
+        let children = self
+            .children
+            .iter()
+            .map(|child| child.to_json(indent + 2))
+            .collect::<Vec<_>>();
+        let children = if children.is_empty() {
+            "[]".to_string()
+        } else {
+            format!("[\n{}\n{}  ]", children.join(",\n"), pad)
+        };
+        format!(
+            "{pad}{{\n\
+             {pad}  \"id\": {},\n\
+             {pad}  \"global_pos\": [{}, {}],\n\
+             {pad}  \"size\": [{}, {}],\n\
+             {pad}  \"rel_pos\": [{}, {}],\n\
+             {pad}  \"margin\": [{}, {}, {}, {}],\n\
+             {pad}  \"padding\": [{}, {}, {}, {}],\n\
+             {pad}  \"texture\": {},\n\
+             {pad}  \"children\": {}\n\
+             {pad}}}",
+            json_string(&self.identifier.to_string()),
+            self.global_pos_x,
+            self.global_pos_y,
+            self.width,
+            self.height,
+            self.rel_pos_x,
+            self.rel_pos_y,
+            self.margin[0],
+            self.margin[1],
+            self.margin[2],
+            self.margin[3],
+            self.padding[0],
+            self.padding[1],
+            self.padding[2],
+            self.padding[3],
+            texture,
+            children,
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LayoutResult {
+    pub screen_width: u32,
+    pub screen_height: u32,
+    pub root: LayoutNode,
+}
+
+impl LayoutResult {
+    /// every node of the tree in pre-order, including the cells created by flatten_children
+    pub fn nodes(&self) -> Vec<&LayoutNode> {
+        let mut nodes = Vec::new();
+        self.root.collect(&mut nodes);
+        nodes
+    }
+    pub fn find(&self, identifier: &UIIdentifier) -> Option<&LayoutNode> {
+        self.nodes()
+            .into_iter()
+            .find(|node| &node.identifier == identifier)
+    }
+    /// inspector output of the whole tree
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\n  \"screen_width\": {},\n  \"screen_height\": {},\n  \"root\":\n{}\n}}",
+            self.screen_width,
+            self.screen_height,
+            self.root.to_json(1)
+        )
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+pub fn layout(tree: &dyn ToUINode, screen_width: u32, screen_height: u32) -> LayoutResult {
+    let ui_node = tree.to_unified_ui_node(screen_width, screen_height);
+    LayoutResult {
+        screen_width,
+        screen_height,
+        root: ui_node.to_layout_node(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use either::Either;
+
+    use super::*;
+    use crate::{
+        ui::ui_span::{SpanDirection, UISpan},
+        ui_node::{BoundedLength, HorizontalAlignment, RelativeLength, VerticalAlignment},
+    };
+
+    fn span(
+        direction: SpanDirection,
+        width: BoundedLength,
+        height: BoundedLength,
+        h_alignment: HorizontalAlignment,
+        v_alignment: VerticalAlignment,
+        uniform_division: bool,
+    ) -> UISpan {
+        UISpan::new(
+            direction,
+            width,
+            height,
+            Either::Left(RelativeLength::Pixels(0)),
+            Either::Left(RelativeLength::Pixels(10)),
+            h_alignment,
+            v_alignment,
+            uniform_division,
+            TextureMeta::Texture {
+                path: "assets/placeholder.png".into(),
+            },
+        )
+    }
+
+    fn leaf(width: u32, height: u32) -> UISpan {
+        span(
+            SpanDirection::Horizontal,
+            BoundedLength::fixed_pixels(width),
+            BoundedLength::fixed_pixels(height),
+            HorizontalAlignment::Left,
+            VerticalAlignment::Top,
+            false,
+        )
+    }
+
+    // a 200x100 span with 10 pixels of padding around the given children
+    fn parent_with_children(
+        direction: SpanDirection,
+        h_alignment: HorizontalAlignment,
+        v_alignment: VerticalAlignment,
+        uniform_division: bool,
+        children: Vec<UISpan>,
+    ) -> LayoutResult {
+        let parent = span(
+            direction,
+            BoundedLength::fixed_pixels(200),
+            BoundedLength::fixed_pixels(100),
+            h_alignment,
+            v_alignment,
+            uniform_division,
+        );
+        for child in children {
+            parent.push_child(Box::new(child));
+        }
+        layout(&parent, 400, 300)
+    }
+
+    // every child of a span is wrapped in a cell
+    fn child_rects(result: &LayoutResult) -> Vec<(u32, u32, u32, u32)> {
+        result
+            .root
+            .children
+            .iter()
+            .map(|cell| cell.children[0].global_rect())
+            .collect()
+    }
+
+    #[test]
+    fn horizontal_span_alignment() {
+        let rects = |h_alignment, v_alignment| {
+            child_rects(&parent_with_children(
+                SpanDirection::Horizontal,
+                h_alignment,
+                v_alignment,
+                false,
+                vec![leaf(40, 20), leaf(60, 20)],
+            ))
+        };
+        assert_eq!(
+            rects(HorizontalAlignment::Left, VerticalAlignment::Top),
+            vec![(10, 10, 50, 30), (50, 10, 110, 30)]
+        );
+        assert_eq!(
+            rects(HorizontalAlignment::Center, VerticalAlignment::Center),
+            vec![(50, 40, 90, 60), (90, 40, 150, 60)]
+        );
+        assert_eq!(
+            rects(HorizontalAlignment::Right, VerticalAlignment::Bottom),
+            vec![(90, 70, 130, 90), (130, 70, 190, 90)]
+        );
+    }
+
+    #[test]
+    fn vertical_span_alignment() {
+        let result = parent_with_children(
+            SpanDirection::Vertical,
+            HorizontalAlignment::Right,
+            VerticalAlignment::Bottom,
+            false,
+            vec![leaf(40, 20), leaf(60, 30)],
+        );
+        assert_eq!(
+            child_rects(&result),
+            vec![(150, 40, 190, 60), (130, 60, 190, 90)]
+        );
+    }
+
+    #[test]
+    fn uniform_division() {
+        let result = parent_with_children(
+            SpanDirection::Horizontal,
+            HorizontalAlignment::Center,
+            VerticalAlignment::Top,
+            true,
+            vec![leaf(20, 20), leaf(40, 20), leaf(20, 20)],
+        );
+        // the 180 pixels inside the padding are split into three cells of 60, whatever the children need
+        let cells = result
+            .root
+            .children
+            .iter()
+            .map(|cell| cell.global_rect())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cells,
+            vec![(10, 10, 70, 90), (70, 10, 130, 90), (130, 10, 190, 90)]
+        );
+        assert_eq!(
+            child_rects(&result),
+            vec![(30, 10, 50, 30), (80, 10, 120, 30), (150, 10, 170, 30)]
+        );
+    }
+
+    #[test]
+    fn bounded_length_is_clamped() {
+        let bounded = |preferred_length, min_length, max_length| {
+            let node = span(
+                SpanDirection::Horizontal,
+                BoundedLength {
+                    preferred_length,
+                    min_length,
+                    max_length,
+                },
+                BoundedLength::fixed_pixels(10),
+                HorizontalAlignment::Left,
+                VerticalAlignment::Top,
+                false,
+            );
+            layout(&node, 400, 300).root.width
+        };
+        // the parent of the root is the screen
+        assert_eq!(
+            bounded(RelativeLength::RelativeParentWidth(0.5), None, None),
+            200
+        );
+        assert_eq!(
+            bounded(
+                RelativeLength::RelativeParentWidth(1.0),
+                None,
+                Some(RelativeLength::Pixels(150))
+            ),
+            150
+        );
+        assert_eq!(
+            bounded(
+                RelativeLength::Pixels(10),
+                Some(RelativeLength::RelativeScreenHeight(0.1)),
+                None
+            ),
+            30
+        );
+        assert_eq!(
+            bounded(
+                RelativeLength::Pixels(100),
+                Some(RelativeLength::Pixels(50)),
+                Some(RelativeLength::Pixels(150))
+            ),
+            100
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "min length is greater than max length")]
+    fn bounded_length_min_above_max_panics() {
+        let node = span(
+            SpanDirection::Horizontal,
+            BoundedLength {
+                preferred_length: RelativeLength::Pixels(100),
+                min_length: Some(RelativeLength::Pixels(150)),
+                max_length: Some(RelativeLength::Pixels(50)),
+            },
+            BoundedLength::fixed_pixels(10),
+            HorizontalAlignment::Left,
+            VerticalAlignment::Top,
+            false,
+        );
+        layout(&node, 400, 300);
+    }
+
+    #[test]
+    fn json_string_escaping() {
+        assert_eq!(json_string("assets/a.png"), "\"assets/a.png\"");
+        assert_eq!(json_string("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(json_string("C:\\ui"), "\"C:\\\\ui\"");
+        assert_eq!(json_string("a\nb\tc"), "\"a\\nb\\tc\"");
+        assert_eq!(json_string("\u{1}\u{1f}"), "\"\\u0001\\u001f\"");
+        assert_eq!(json_string("ü字"), "\"ü字\"");
+    }
+}
@@ -13,7 +13,7 @@ use either::Either;
 use lazy_static::lazy_static;
 use winit::keyboard::KeyCode;
 
-use crate::{state, ui::UICell, ui_renderable::TextureMeta};
+use crate::{state, ui::UICell, ui_layout::LayoutNode, ui_renderable::TextureMeta};
 
 
 
@@ -27,16 +27,17 @@ pub trait ToUINode {
         screen_height: u32,
         event: &UINodeEventRaw,
     )->UIRenderInstruction{
-        let ui_node = self.to_ui_node();
-        let ui_node = ui_node.calculate_dimensions(screen_width, screen_height, screen_width, screen_height);
-        let ui_node = ui_node.flatten_children(
-            0, 0, 
-            screen_width, 
-            screen_height, 
-            HorizontalAlignment::Left, 
-        VerticalAlignment::Top);
-        let ui_node = ui_node.to_unified();
+        let ui_node = self.to_unified_ui_node(screen_width, screen_height);
         ui_node.handle_event(event);
+        let ui_node = self.to_unified_ui_node(screen_width, screen_height);
+        ui_node.to_ui_render_instruction(screen_width, screen_height)
+    }
+    /// runs the layout passes (calculate_dimensions, flatten_children, to_unified) without dispatching events
+    fn to_unified_ui_node(
+        &self,
+        screen_width: u32,
+        screen_height: u32,
+    ) -> UINode<BoxDimensionsWithGlobal, UnifiedChildren> {
         let ui_node = self.to_ui_node();
         let ui_node = ui_node.calculate_dimensions(screen_width, screen_height, screen_width, screen_height);
         let ui_node = ui_node.flatten_children(
@@ -45,12 +46,10 @@ pub trait ToUINode {
             screen_height, 
             HorizontalAlignment::Left, 
         VerticalAlignment::Top);
-        let ui_node = ui_node.to_unified();
-        ui_node.to_ui_render_instruction(screen_width, screen_height)
+        ui_node.to_unified()
     }
 }
 
-
 #[derive(Clone, Copy)]
 pub enum HorizontalAlignment {
     Left,
@@ -590,6 +589,7 @@ impl UINode<BoxDimensionsRelative, StructuredChildren<BoxDimensionsRelative>> {
 }
 
 // the canvas will be rendered on the entire screen
+#[derive(Clone)]
 pub struct UIRenderInstruction {
     pub version: u64,     // cache key
     pub id: UIIdentifier, // cache key
@@ -1067,6 +1067,27 @@ impl UINode<BoxDimensionsWithGlobal, UnifiedChildren> {
             texture_meta: meta.clone(),
         }
     }
+    pub fn to_layout_node(&self) -> LayoutNode {
+        let box_dimensions = &self.box_dimensions;
+        LayoutNode {
+            identifier: self.identifier.clone(),
+            global_pos_x: box_dimensions.global_pos_x,
+            global_pos_y: box_dimensions.global_pos_y,
+            rel_pos_x: box_dimensions.rel_pos_x,
+            rel_pos_y: box_dimensions.rel_pos_y,
+            width: box_dimensions.width,
+            height: box_dimensions.height,
+            margin: box_dimensions.margin,
+            padding: box_dimensions.padding,
+            texture_meta: self.texture_meta.clone(),
+            children: self
+                .children
+                .children
+                .iter()
+                .map(|child| child.to_layout_node())
+                .collect(),
+        }
+    }
     pub fn to_string(&self, indent: u32) -> String {
         let UINode {
             box_dimensions,
@@ -1158,6 +1179,7 @@ impl UINode<BoxDimensionsWithGlobal, UnifiedChildren> {
 }
 
 
+#[derive(Default)]
 pub struct UINodeEventRaw{
     pub mouse_x: u32,
     pub mouse_y: u32,
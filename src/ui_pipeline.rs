@@ -6,16 +6,27 @@ use std::sync::Arc;
 use wgpu::{RenderPipeline, util::DeviceExt};
 
 use crate::{
+    asset_server::AssetServer,
+    buffer_arena::{ArenaAllocation, BufferArena},
     cache::{CacheKey, CacheValue, CACHE},
     my_texture::{MyTexture, TextureSource},
     ui_node::{UIIdentifier, UIRenderInstruction},
     ui_renderable::{UIInstance, UIInstanceRaw},
 };
 
+// a textured quad, drawn together with its siblings in one render pass into their parent
+pub struct UIDraw {
+    pub material_bind_group: wgpu::BindGroup,
+    pub instance_allocation: ArenaAllocation,
+}
+
 // model
 // mesh_num
 pub struct UIPipeline {
+    // draws to the screen, with the sample count of the render context
     pub pipeline: RenderPipeline,
+    // draws into the ui textures of the cache, they are created by MyTexture::create_render_attachment_texture
+    pub texture_pipeline: RenderPipeline,
     pub material_bind_group_layout: wgpu::BindGroupLayout,
     pub index_buffer: wgpu::Buffer,
 }
@@ -67,8 +78,9 @@ impl UIPipeline {
     }
     fn create_pipeline(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
         material_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
     ) -> RenderPipeline {
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -97,7 +109,7 @@ impl UIPipeline {
                 entry_point: Some("fs_main"),
                 targets: &[Some(wgpu::ColorTargetState {
                     // 4.
-                    format: config.format,
+                    format,
                     blend: Some(wgpu::BlendState::REPLACE),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
@@ -124,7 +136,7 @@ impl UIPipeline {
             //     bias: wgpu::DepthBiasState::default(),
             // }), // 1.
             multisample: wgpu::MultisampleState {
-                count: 1,                         // 2.
+                count: sample_count,              // 2.
                 mask: !0,                         // 3.
                 alpha_to_coverage_enabled: false, // 4.
             },
@@ -134,25 +146,58 @@ impl UIPipeline {
         render_pipeline
     }
 
-    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Self {
         let material_bind_group_layout = Self::create_material_bind_group_layout(device);
-        let pipeline = Self::create_pipeline(device, config, &material_bind_group_layout);
+        // the srgb view of the swapchain, see RenderContext::new
+        let pipeline = Self::create_pipeline(
+            device,
+            config.format.add_srgb_suffix(),
+            &material_bind_group_layout,
+            sample_count,
+        );
+        let texture_pipeline = Self::create_pipeline(
+            device,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            &material_bind_group_layout,
+            1,
+        );
         let index_buffer = Self::create_index_buffer(device);
         Self {
             pipeline,
+            texture_pipeline,
             material_bind_group_layout,
             index_buffer,
         }
     }
 
+    /// the ui textures are never multisampled, only the screen pipeline changes
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            config.format.add_srgb_suffix(),
+            &self.material_bind_group_layout,
+            sample_count,
+        );
+    }
+
     fn create_render_pass<'a>(
         encoder: &'a mut wgpu::CommandEncoder,
         color_view: &'a wgpu::TextureView,
+        resolve_view: Option<&'a wgpu::TextureView>,
         // depth_view: &'a wgpu::TextureView,
     ) -> wgpu::RenderPass<'a> {
         let color_attachment = Some(wgpu::RenderPassColorAttachment {
             view: &color_view,
-            resolve_target: None,
+            resolve_target: resolve_view,
             ops: wgpu::Operations {
                 load: wgpu::LoadOp::Load,
                 store: wgpu::StoreOp::Store,
@@ -176,6 +221,29 @@ impl UIPipeline {
         encoder.begin_render_pass(&render_pass_descriptor)
     }
 
+    // records every quad in one render pass, so a multisampled target is only resolved once
+    fn draw_quads(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        resolve_view: Option<&wgpu::TextureView>,
+        pipeline: &RenderPipeline,
+        draws: &[UIDraw],
+        instance_arena: &BufferArena,
+    ) {
+        if draws.is_empty() {
+            return;
+        }
+        let mut render_pass = Self::create_render_pass(encoder, color_view, resolve_view);
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        for draw in draws {
+            render_pass.set_bind_group(0, &draw.material_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, instance_arena.slice(&draw.instance_allocation));
+            render_pass.draw_indexed(0..6, 0, 0..1);
+        }
+    }
+
     pub fn create_index_buffer(device: &wgpu::Device) -> wgpu::Buffer {
         let indices: [u16; 6] = [0, 1, 2, 3, 4, 5];
         device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -184,16 +252,19 @@ impl UIPipeline {
             usage: wgpu::BufferUsages::INDEX,
         })
     }
-    /// render_helper renders the texture specified by render_instruction to the outer texture
-    pub fn render_helper<'a>(
+    /// render_helper renders the texture specified by render_instruction, and returns the quad that draws it
+    /// into the outer texture. the caller records the quads of all siblings in one pass.
+    /// the bool is false if a texture of the subtree is still loading, the result is not cached then
+    pub fn render_helper(
         &self,
         encoder: &mut wgpu::CommandEncoder,
         render_instruction: UIRenderInstruction,
-        parent_texture_view: impl Into<&'a wgpu::TextureView>,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         render_to_screen: bool,
-    ) {
+        instance_arena: &mut BufferArena,
+        asset_server: &mut AssetServer,
+    ) -> (UIDraw, bool) {
         let version = render_instruction.version;
         let id = render_instruction.id;
         let child_texture = CACHE.get(&CacheKey::UITexture(id.clone()));
@@ -215,6 +286,7 @@ impl UIPipeline {
             }
         };
         // let child_texture = None;
+        let mut loaded = true;
         let child_texture = match child_texture {
             Some(child_texture) => child_texture,
             None => {
@@ -229,14 +301,9 @@ impl UIPipeline {
 
                 let ui_renderable = render_instruction
                     .texture_meta
-                    .to_ui_renderable(device, queue, self);
-                let material_bind_group = ui_renderable.material_bind_group;
-                // queue the rendering of the child texture
-                let mut render_pass = Self::create_render_pass(encoder, &texture.view);
-                render_pass.set_pipeline(&self.pipeline);
-                render_pass
-                    .set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                render_pass.set_bind_group(0, &material_bind_group, &[]);
+                    .to_ui_renderable(device, queue, self, asset_server);
+                loaded = !ui_renderable.loading;
+                // the background of the texture, the sub instructions are drawn on top of it
                 let ui_instance = UIInstance {
                     location_left: -1.0,
                     location_right: 1.0,
@@ -244,32 +311,43 @@ impl UIPipeline {
                     location_bottom: -1.0,
                     flip_vertically: true, // render to texture
                 };
-                let instance_buffer =
-                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some("Instance Buffer"),
-                        contents: bytemuck::cast_slice(&[ui_instance.to_raw()]),
-                        usage: wgpu::BufferUsages::VERTEX,
-                    });
-                render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
-                render_pass.draw_indexed(0..6, 0, 0..1);
-                drop(render_pass);
-                // device.poll(wgpu::Maintain::Wait);
-                // call the sub instructions before rendering the child texture so that they are queued first
+                let instance_allocation =
+                    instance_arena.alloc(device, queue, bytemuck::cast_slice(&[ui_instance.to_raw()]));
+                let mut draws = vec![UIDraw {
+                    material_bind_group: ui_renderable.material_bind_group,
+                    instance_allocation,
+                }];
+                // render the textures of the sub instructions first, so that their passes are queued before
+                // the pass that draws them into this texture
                 for sub_instruction in render_instruction.sub_instructions {
-                    self.render_helper(
+                    let (draw, sub_loaded) = self.render_helper(
                         encoder,
                         sub_instruction,
-                        &texture.view,
                         device,
                         queue,
                         false,
+                        instance_arena,
+                        asset_server,
                     );
+                    loaded &= sub_loaded;
+                    draws.push(draw);
                 }
-                let result = Arc::new(CacheValue::UITexture { texture, version });
-                CACHE.insert(
-                    CacheKey::UITexture(id),
-                    result.clone(),
+                self.draw_quads(
+                    encoder,
+                    &texture.view,
+                    None,
+                    &self.texture_pipeline,
+                    &draws,
+                    instance_arena,
                 );
+                let result = Arc::new(CacheValue::UITexture { texture, version });
+                // drawn again next frame until every texture is loaded
+                if loaded {
+                    CACHE.insert(
+                        CacheKey::UITexture(id),
+                        result.clone(),
+                    );
+                }
                 result
             }
         };
@@ -294,11 +372,8 @@ impl UIPipeline {
             location_bottom: normalized_location_bottom,
             flip_vertically: !render_to_screen,
         };
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&[ui_instance.to_raw()]),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+        let instance_allocation =
+            instance_arena.alloc(device, queue, bytemuck::cast_slice(&[ui_instance.to_raw()]));
         // tmp
         // let placeholder_texture = CACHE.get_with(
         //     CacheKey::PlaceholderTexture,
@@ -312,19 +387,11 @@ impl UIPipeline {
         //     _ => unreachable!(),
         // };
 
-        let mut render_pass = Self::create_render_pass(encoder, parent_texture_view.into());
-        render_pass.set_pipeline(&self.pipeline);
-        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        render_pass.set_bind_group(
-            0,
-            &self.create_material_bind_group(device, child_texture),
-            &[],
-        );
-        render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
-        render_pass.draw_indexed(0..6, 0, 0..1);
-        drop(render_pass);
-
-        // device.poll(wgpu::Maintain::Wait);
+        let draw = UIDraw {
+            material_bind_group: self.create_material_bind_group(device, child_texture),
+            instance_allocation,
+        };
+        (draw, loaded)
     }
     pub fn render(
         &self,
@@ -333,11 +400,35 @@ impl UIPipeline {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         color_view: &wgpu::TextureView,
+        // the single sampled target when color_view is multisampled
+        resolve_view: Option<&wgpu::TextureView>,
         // depth_view: &wgpu::TextureView, // use depth to sort
+        instance_arena: &mut BufferArena,
+        asset_server: &mut AssetServer,
     ) {
-        for render_instruction in render_instructions {
-            self.render_helper(encoder, render_instruction, color_view, device, queue, true);
-        }
+        let draws = render_instructions
+            .into_iter()
+            .map(|render_instruction| {
+                let (draw, _) = self.render_helper(
+                    encoder,
+                    render_instruction,
+                    device,
+                    queue,
+                    true,
+                    instance_arena,
+                    asset_server,
+                );
+                draw
+            })
+            .collect::<Vec<_>>();
+        self.draw_quads(
+            encoder,
+            color_view,
+            resolve_view,
+            &self.pipeline,
+            &draws,
+            instance_arena,
+        );
 
         // begin render pass
         // let mut render_pass = self.create_render_pass(encoder, color_view, depth_view);
@@ -1,7 +1,5 @@
-use std::sync::Arc;
-
 use crate::{
-    cache::{CACHE, CacheKey, CacheValue},
+    asset_server::{AssetServer, AssetState},
     my_texture::{MyTexture, TextureSource},
     ui_pipeline::UIPipeline,
 };
@@ -28,61 +26,53 @@ pub enum TextureMeta {
     Font { font_path: String, character: char },
 }
 
-pub fn create_placeholder_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> Arc<CacheValue> {
-    let texture = MyTexture::load(
+pub fn create_placeholder_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> MyTexture {
+    match MyTexture::load(
         TextureSource::FilePath("assets/placeholder.png".to_string()),
         device,
         queue,
-    )
-    .unwrap();
-    Arc::new(CacheValue::Texture(texture))
+    ) {
+        Ok(texture) => texture,
+        Err(error) => {
+            log::warn!("{}, using the built-in placeholder", error);
+            MyTexture::from_image(&MyTexture::placeholder_image(), device, queue)
+        }
+    }
 }
+
 impl TextureMeta {
+    pub fn to_texture_source(&self) -> TextureSource {
+        match self {
+            TextureMeta::Texture { path } => TextureSource::FilePath(path.clone()),
+            TextureMeta::Font {
+                font_path,
+                character,
+            } => TextureSource::TextCharacter {
+                character: *character,
+                font_file_path: font_path.clone(),
+            },
+        }
+    }
+    /// the texture is decoded by the asset server, the placeholder texture is drawn while it is loading
+    /// and after it failed
     pub fn to_ui_renderable(
         &self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         ui_pipeline: &UIPipeline,
+        asset_server: &mut AssetServer,
     ) -> UIRenderable {
-        let texture: Arc<CacheValue> = match self {
-            TextureMeta::Texture { path } => CACHE.get_with(
-                CacheKey::Texture(TextureSource::FilePath(path.clone())),
-                || {
-                    let texture =
-                        MyTexture::load(TextureSource::FilePath(path.clone()), device, queue)
-                            .unwrap();
-                    Arc::new(CacheValue::Texture(texture))
-                },
-            ),
-            TextureMeta::Font {
-                font_path,
-                character,
-            } => CACHE.get_with(
-                CacheKey::Texture(TextureSource::TextCharacter {
-                    character: *character,
-                    font_file_path: font_path.clone(),
-                }),
-                || {
-                    let texture = MyTexture::load(
-                        TextureSource::TextCharacter {
-                            font_file_path: font_path.clone(),
-                            character: *character,
-                        },
-                        device,
-                        queue,
-                    )
-                    .unwrap();
-                    Arc::new(CacheValue::Texture(texture))
-                },
-            ),
-        };
-        let texture = match texture.as_ref() {
-            CacheValue::Texture(texture) => texture,
-            _ => unreachable!(),
+        let texture_handle = asset_server.load_texture(self.to_texture_source());
+        let (texture, loading) = match texture_handle.state() {
+            AssetState::Ready(texture) => (texture, false),
+            // the asset server already logged the error
+            AssetState::Failed(_) => (asset_server.placeholder_texture(device, queue), false),
+            AssetState::Loading => (asset_server.placeholder_texture(device, queue), true),
         };
-        let material_bind_group = ui_pipeline.create_material_bind_group(device, texture);
+        let material_bind_group = ui_pipeline.create_material_bind_group(device, &texture);
         UIRenderable {
             material_bind_group,
+            loading,
         }
     }
 }
@@ -157,4 +147,6 @@ impl UIInstanceRaw {
 
 pub struct UIRenderable {
     pub material_bind_group: wgpu::BindGroup,
+    // the placeholder stands in for a texture that is still loading
+    pub loading: bool,
 }